@@ -0,0 +1,52 @@
+//! Measures the fixed per-scan overhead of scanning tiny inputs.
+//!
+//! Runs against a local stand-in that answers every INSTREAM with `stream: OK`, so only the
+//! client-side framing and connection setup are measured.
+//!
+//! ```console
+//! cargo bench --bench small_scan
+//! ```
+use std::{
+    io::{Read, Write},
+    net::TcpListener,
+    thread,
+    time::Instant,
+};
+
+const ITERATIONS: u32 = 2000;
+
+fn main() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut received = vec![];
+            let mut buf = [0; 8192];
+            // The request ends with the four byte zero-length footer.
+            while !received.ends_with(&[0, 0, 0, 0]) {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                received.extend_from_slice(&buf[..n]);
+            }
+            let _ = stream.write_all(b"stream: OK\0");
+        }
+    });
+
+    let payload = b"This is not a virus.";
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let res = clamav_tcp::scan(addr, &payload[..], None).unwrap();
+        assert!(!res.is_infected);
+    }
+    let elapsed = start.elapsed();
+
+    println!(
+        "{ITERATIONS} scans of {} bytes in {elapsed:?} ({:?} per scan)",
+        payload.len(),
+        elapsed / ITERATIONS
+    );
+}
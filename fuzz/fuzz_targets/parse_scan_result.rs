@@ -0,0 +1,37 @@
+//! Feeds arbitrary replies through the INSTREAM reply parser.
+//!
+//! ```console
+//! cargo +nightly fuzz run parse_scan_result
+//! ```
+#![no_main]
+
+use clamav_tcp::ScanResult;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Replies are read as UTF-8, so invalid UTF-8 never reaches the parser.
+    let Ok(reply) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    let Ok(res) = reply.parse::<ScanResult>() else {
+        return;
+    };
+
+    // The detections and the infected flag must agree.
+    assert_eq!(res.is_infected, !res.detected_infections.is_empty());
+
+    // A clean result needs an OK status after a "stream:" marker.
+    if !res.is_infected {
+        let has_ok = reply
+            .split(['\0', '\n'])
+            .filter_map(|line| line.split_once("stream:"))
+            .any(|(_, status)| {
+                status
+                    .trim()
+                    .get(..2)
+                    .is_some_and(|s| s.eq_ignore_ascii_case("OK"))
+            });
+        assert!(has_ok, "{reply:?} was reported clean");
+    }
+});
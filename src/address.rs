@@ -0,0 +1,182 @@
+use std::{
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs},
+    vec,
+};
+
+/// The address of a ClamAV host listening on TCP, as used by [ClamdClient](crate::ClamdClient).
+///
+/// Converts from the common address forms, so any of them can be passed where a
+/// `impl Into<ClamdAddress>` is expected:
+/// ```rust
+/// use std::net::{Ipv4Addr, SocketAddr};
+/// use clamav_tcp::ClamdAddress;
+/// let addresses: [ClamdAddress; 5] = [
+///     "localhost:3310".into(),
+///     String::from("localhost:3310").into(),
+///     ("127.0.0.1", 3310).into(),
+///     (Ipv4Addr::LOCALHOST, 3310).into(),
+///     SocketAddr::from(([127, 0, 0, 1], 3310)).into(),
+/// ];
+/// assert_eq!(addresses[2], addresses[4]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ClamdAddress {
+    /// A `host:port` string such as `"localhost:3310"`, resolved every time it is connected to.
+    Host(String),
+    /// An IP address and port.
+    Socket(SocketAddr),
+    /// Addresses resolved in advance, tried in order like the addresses a host name resolves to.
+    Resolved(Vec<SocketAddr>),
+}
+
+impl fmt::Display for ClamdAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClamdAddress::Host(host) => f.write_str(host),
+            ClamdAddress::Socket(addr) => addr.fmt(f),
+            ClamdAddress::Resolved(addrs) => {
+                for (i, addr) in addrs.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    addr.fmt(f)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl ToSocketAddrs for ClamdAddress {
+    type Iter = vec::IntoIter<SocketAddr>;
+
+    fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
+        match self {
+            ClamdAddress::Host(host) => host.to_socket_addrs(),
+            ClamdAddress::Socket(addr) => Ok(vec![*addr].into_iter()),
+            ClamdAddress::Resolved(addrs) => Ok(addrs.clone().into_iter()),
+        }
+    }
+}
+
+impl From<&str> for ClamdAddress {
+    fn from(host: &str) -> Self {
+        ClamdAddress::Host(host.to_string())
+    }
+}
+
+impl From<String> for ClamdAddress {
+    fn from(host: String) -> Self {
+        ClamdAddress::Host(host)
+    }
+}
+
+impl From<&String> for ClamdAddress {
+    fn from(host: &String) -> Self {
+        ClamdAddress::Host(host.clone())
+    }
+}
+
+impl From<(&str, u16)> for ClamdAddress {
+    fn from((host, port): (&str, u16)) -> Self {
+        match host.parse::<IpAddr>() {
+            Ok(ip) => ClamdAddress::Socket(SocketAddr::new(ip, port)),
+            Err(_) => ClamdAddress::Host(format!("{host}:{port}")),
+        }
+    }
+}
+
+impl From<(String, u16)> for ClamdAddress {
+    fn from((host, port): (String, u16)) -> Self {
+        ClamdAddress::from((&*host, port))
+    }
+}
+
+impl From<SocketAddr> for ClamdAddress {
+    fn from(addr: SocketAddr) -> Self {
+        ClamdAddress::Socket(addr)
+    }
+}
+
+impl From<Vec<SocketAddr>> for ClamdAddress {
+    fn from(addrs: Vec<SocketAddr>) -> Self {
+        ClamdAddress::Resolved(addrs)
+    }
+}
+
+impl From<SocketAddrV4> for ClamdAddress {
+    fn from(addr: SocketAddrV4) -> Self {
+        ClamdAddress::Socket(addr.into())
+    }
+}
+
+impl From<SocketAddrV6> for ClamdAddress {
+    fn from(addr: SocketAddrV6) -> Self {
+        ClamdAddress::Socket(addr.into())
+    }
+}
+
+impl From<(IpAddr, u16)> for ClamdAddress {
+    fn from(addr: (IpAddr, u16)) -> Self {
+        ClamdAddress::Socket(addr.into())
+    }
+}
+
+impl From<(Ipv4Addr, u16)> for ClamdAddress {
+    fn from(addr: (Ipv4Addr, u16)) -> Self {
+        ClamdAddress::Socket(addr.into())
+    }
+}
+
+impl From<(Ipv6Addr, u16)> for ClamdAddress {
+    fn from(addr: (Ipv6Addr, u16)) -> Self {
+        ClamdAddress::Socket(addr.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ip_literals_with_a_port_are_not_resolved() {
+        let localhost = SocketAddr::from(([127, 0, 0, 1], 3310));
+        assert_eq!(
+            ClamdAddress::from(("127.0.0.1", 3310)),
+            ClamdAddress::Socket(localhost)
+        );
+        assert_eq!(
+            ClamdAddress::from(("::1".to_string(), 3310)),
+            ClamdAddress::Socket(SocketAddr::from((Ipv6Addr::LOCALHOST, 3310)))
+        );
+        assert_eq!(
+            ClamdAddress::from(("localhost", 3310)),
+            ClamdAddress::Host("localhost:3310".to_string())
+        );
+    }
+
+    #[test]
+    fn resolved_addresses_are_kept_in_order() {
+        let addrs = vec![
+            SocketAddr::from((Ipv6Addr::LOCALHOST, 3310)),
+            SocketAddr::from((Ipv4Addr::LOCALHOST, 3310)),
+        ];
+        let addr = ClamdAddress::from(addrs.clone());
+        assert_eq!(addr.to_socket_addrs().unwrap().collect::<Vec<_>>(), addrs);
+        assert_eq!(addr.to_string(), "[::1]:3310, 127.0.0.1:3310");
+    }
+
+    #[test]
+    fn displays_as_a_connectable_address() {
+        for addr in [
+            ClamdAddress::from("localhost:3310"),
+            ClamdAddress::from((Ipv6Addr::LOCALHOST, 3310)),
+            ClamdAddress::from(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 3310)),
+        ] {
+            let reparsed: Vec<_> = addr.to_string().to_socket_addrs().unwrap().collect();
+            let resolved: Vec<_> = addr.to_socket_addrs().unwrap().collect();
+            assert_eq!(reparsed, resolved, "{addr}");
+        }
+    }
+}
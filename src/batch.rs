@@ -0,0 +1,165 @@
+use std::{
+    net::ToSocketAddrs,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use crate::{resolve, scan, ClamAVClientError, ScanResult, ScanTarget};
+
+/// Scans many [ScanTarget]s in parallel, with at most `max_in_flight` connections to ClamAV
+/// open at a time, and returns their results in the order of `items`.
+///
+/// ClamAV serves every connection on a thread of its own, up to its `MaxThreads`, and queues
+/// the rest, so `max_in_flight` should not be above it. The targets are scanned on
+/// `max_in_flight` threads, each taking the next target as soon as it is done with the last,
+/// and each target is opened only when its scan starts. A failed scan only fails its own item.
+/// Fails without scanning anything if `max_in_flight` is zero or `addr` is invalid.
+///
+/// ```rust
+/// use std::path::Path;
+/// let paths = [Path::new("Cargo.toml"), Path::new("resources/eicar.txt")];
+/// let results = clamav_tcp::scan_many_concurrent("localhost:3310", paths, 4).unwrap();
+/// assert!(!results[0].as_ref().unwrap().is_infected);
+/// assert!(results[1].as_ref().unwrap().is_infected);
+/// ```
+pub fn scan_many_concurrent<A, I>(
+    addr: A,
+    items: I,
+    max_in_flight: usize,
+) -> Result<Vec<Result<ScanResult, ClamAVClientError>>, ClamAVClientError>
+where
+    A: ToSocketAddrs,
+    I: IntoIterator,
+    I::Item: ScanTarget + Send,
+{
+    if max_in_flight == 0 {
+        return Err(ClamAVClientError::InvalidConfiguration(
+            "at least one scan must be allowed in flight".to_string(),
+        ));
+    }
+    // Resolved once, rather than by every scan.
+    let addrs = resolve(addr)?;
+    let items: Vec<Mutex<Option<I::Item>>> = items
+        .into_iter()
+        .map(|item| Mutex::new(Some(item)))
+        .collect();
+    let results: Vec<Mutex<Option<Result<ScanResult, ClamAVClientError>>>> =
+        items.iter().map(|_| Mutex::new(None)).collect();
+    let next = AtomicUsize::new(0);
+
+    thread::scope(|s| {
+        for _ in 0..max_in_flight.min(items.len()) {
+            s.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(index) else {
+                    break;
+                };
+                let item = lock(item).take().expect("every item is taken once");
+                *lock(&results[index]) = Some(scan(&addrs[..], item, None));
+            });
+        }
+    });
+
+    Ok(results
+        .into_iter()
+        .map(|res| {
+            res.into_inner()
+                .unwrap_or_else(|e| e.into_inner())
+                .expect("every item is scanned")
+        })
+        .collect())
+}
+
+/// Locks `mutex`, ignoring poisoning: a panicking scan leaves nothing half written.
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{SocketAddr, TcpListener},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::protocol::INSTREAM_FOOTER;
+
+    /// Starts a server that answers every scan after a short delay, reporting inputs containing
+    /// `eicar` as infected, and returns the most scans it had in flight at once.
+    fn slow_scanner() -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let max = max_in_flight.clone();
+        thread::spawn(move || {
+            for conn in listener.incoming() {
+                let (in_flight, max) = (in_flight.clone(), max.clone());
+                thread::spawn(move || {
+                    let mut conn = conn.unwrap();
+                    let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max.fetch_max(now, Ordering::SeqCst);
+                    let mut request = vec![];
+                    let mut buf = [0; 256];
+                    while !request.ends_with(INSTREAM_FOOTER) {
+                        let n = conn.read(&mut buf).unwrap();
+                        assert!(n > 0, "closed mid request");
+                        request.extend_from_slice(&buf[..n]);
+                    }
+                    thread::sleep(Duration::from_millis(20));
+                    let infected = request.windows(5).any(|w| w == b"eicar");
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    let reply: &[u8] = if infected {
+                        b"stream: Win.Test.EICAR_HDB-1 FOUND\0"
+                    } else {
+                        b"stream: OK\0"
+                    };
+                    conn.write_all(reply).unwrap();
+                });
+            }
+        });
+        (addr, max_in_flight)
+    }
+
+    #[test]
+    fn caps_scans_in_flight_and_keeps_the_order() {
+        let (addr, max_in_flight) = slow_scanner();
+        let items: Vec<&[u8]> = vec![b"clean", b"eicar", b"clean", b"clean", b"eicar", b"clean"];
+        let results = scan_many_concurrent(addr, items.clone(), 2).unwrap();
+
+        let infected: Vec<bool> = results
+            .iter()
+            .map(|r| r.as_ref().unwrap().is_infected)
+            .collect();
+        let expected: Vec<bool> = items.iter().map(|item| *item == b"eicar").collect();
+        assert_eq!(infected, expected);
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn failed_items_only_fail_themselves() {
+        let (addr, _) = slow_scanner();
+        let items = [
+            std::path::Path::new("resources/eicar.txt"),
+            std::path::Path::new("resources/does-not-exist"),
+        ];
+        let results = scan_many_concurrent(addr, items, 4).unwrap();
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn rejects_zero_scans_in_flight() {
+        let res = scan_many_concurrent("localhost:3310", Vec::<&[u8]>::new(), 0);
+        assert!(matches!(
+            res,
+            Err(ClamAVClientError::InvalidConfiguration(_))
+        ));
+    }
+}
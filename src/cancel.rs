@@ -0,0 +1,157 @@
+use std::{
+    io::Read,
+    net::{Shutdown, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    connect_tcp_socket,
+    scan::{parse_reply, read_scan_reply, write_instream},
+    ClamAVClientError, ScanResult,
+};
+
+/// Connects to ClamAV for a scan that can be cancelled from another thread.
+///
+/// Returns the scan to run, and a [ScanHandle] that aborts it. Cancelling shuts the connection
+/// down, so a blocked read or write errors out promptly and the scan returns
+/// [ClamAVClientError::Cancelled].
+///
+/// ```rust
+/// use clamav_tcp;
+/// let (scan, handle) = clamav_tcp::scan_cancelable("localhost:3310").unwrap();
+/// let scanner = std::thread::spawn(move || scan.run(&mut std::io::repeat(0), None));
+/// handle.cancel();
+/// assert!(matches!(
+///     scanner.join().unwrap(),
+///     Err(clamav_tcp::ClamAVClientError::Cancelled)
+/// ));
+/// ```
+pub fn scan_cancelable(
+    addr: impl ToSocketAddrs,
+) -> Result<(CancelableScan, ScanHandle), ClamAVClientError> {
+    let stream = connect_tcp_socket(addr)?;
+    let handle_stream = stream
+        .try_clone()
+        .map_err(ClamAVClientError::UnableToConnect)?;
+    let cancelled = Arc::new(AtomicBool::new(false));
+
+    Ok((
+        CancelableScan {
+            stream,
+            cancelled: cancelled.clone(),
+        },
+        ScanHandle {
+            stream: handle_stream,
+            cancelled,
+        },
+    ))
+}
+
+/// A scan created by [scan_cancelable] that hasn't been run yet.
+#[derive(Debug)]
+pub struct CancelableScan {
+    stream: TcpStream,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelableScan {
+    /// Scans something that is [Read], like [scan](crate::scan), unless the scan is cancelled.
+    pub fn run<D: Read>(
+        mut self,
+        file: &mut D,
+        chunk_size: Option<usize>,
+    ) -> Result<ScanResult, ClamAVClientError> {
+        let res = write_instream(&mut self.stream, file, chunk_size, |_| Ok(())).and_then(|_| {
+            let buf = read_scan_reply(&mut self.stream)?;
+            parse_reply(&buf, false)
+        });
+
+        // Whatever error the shut down connection caused, report the cancellation instead. A scan
+        // that finished before it was cancelled keeps its result.
+        match res {
+            Err(_) if self.cancelled.load(Ordering::SeqCst) => Err(ClamAVClientError::Cancelled),
+            res => res,
+        }
+    }
+}
+
+/// Cancels the scan it was created with, see [scan_cancelable].
+#[derive(Debug)]
+pub struct ScanHandle {
+    stream: TcpStream,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ScanHandle {
+    /// Aborts the scan. Cancelling a scan that has already finished has no effect.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        // The connection may already be closed if the scan finished.
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::INSTREAM_FOOTER;
+    use std::{net::TcpListener, thread, time::Duration};
+
+    #[test]
+    fn cancel_aborts_a_scan_waiting_for_its_reply() {
+        // Reads the whole request but never replies, until the connection is shut down.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut buf = [0; 256];
+            while !request.ends_with(INSTREAM_FOOTER) {
+                match conn.read(&mut buf) {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => request.extend_from_slice(&buf[..n]),
+                }
+            }
+            while matches!(conn.read(&mut buf), Ok(n) if n > 0) {}
+        });
+
+        let (scan, handle) = scan_cancelable(addr).unwrap();
+        let scanner = thread::spawn(move || scan.run(&mut "This is not a virus.".as_bytes(), None));
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!scanner.is_finished());
+        handle.cancel();
+        let res = scanner.join().unwrap();
+        assert!(matches!(res, Err(ClamAVClientError::Cancelled)));
+    }
+
+    #[test]
+    fn can_cancel_running_scan() {
+        let (scan, handle) = scan_cancelable("localhost:3310").unwrap();
+        let scanner = thread::spawn(move || scan.run(&mut std::io::repeat(0), None));
+
+        thread::sleep(Duration::from_millis(50));
+        handle.cancel();
+        let res = scanner.join().unwrap();
+        assert!(matches!(res, Err(ClamAVClientError::Cancelled)));
+    }
+
+    #[test]
+    fn cancelling_before_running_aborts_scan() {
+        let (scan, handle) = scan_cancelable("localhost:3310").unwrap();
+        handle.cancel();
+        let res = scan.run(&mut "This is not a virus.".as_bytes(), None);
+        assert!(matches!(res, Err(ClamAVClientError::Cancelled)));
+    }
+
+    #[test]
+    fn uncancelled_scan_completes() {
+        let (scan, _handle) = scan_cancelable("localhost:3310").unwrap();
+        let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+        let res = scan.run(&mut eicar, None).unwrap();
+        assert_eq!(1, res.detected_infections.len());
+    }
+}
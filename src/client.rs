@@ -0,0 +1,1576 @@
+use std::{
+    fs::File,
+    io::{Read, Seek},
+    net::{Shutdown, TcpStream},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[cfg(feature = "socket2")]
+use crate::SocketOptions;
+#[cfg(feature = "socks")]
+use crate::Socks5Proxy;
+use crate::{
+    connect_tcp_socket, connect_tcp_socket_timeout,
+    eyeballs::connect_racing,
+    multiscan::scan_path_on,
+    ping::{ping_latency_on, ping_on},
+    probe::{probe_over, self_test_on},
+    protocol::Framing,
+    quarantine::quarantine_infected,
+    scan::{
+        buffer_single_chunk, exchange_closing, parse_reply, remaining_len, ChunksReader, ScanStats,
+        DEFAULT_CHUNK_SIZE,
+    },
+    stats::stats_on,
+    transport::DEFAULT_REPLY_BUFFER_SIZE,
+    upload::{judge_upload, UploadRejection},
+    version::{commands_on, version_on, VersionCache},
+    Byte, ClamAVClientError, ClamdAddress, ClamdConnection, ClamdStats, CommandSet, ProbeReport,
+    ScanMetrics, ScanReport, ScanResult, VersionInfo,
+};
+
+/// The settings read by [ClamdClient::from_config_str].
+#[cfg(feature = "config")]
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    address: String,
+    chunk_size: Option<usize>,
+    /// In seconds.
+    timeout: Option<f64>,
+}
+
+/// Messages ClamAV uses when it can't reach a path it was asked to scan.
+const PATH_ACCESS_ERRORS: &[&str] = &[
+    "No such file or directory",
+    "Access denied",
+    "Permission denied",
+    "File path check failure",
+    "Can't open file or directory",
+];
+
+/// A ClamAV client holding the address of the ClamAV host and the settings used for every request.
+///
+/// The client only holds its configuration and opens a new connection per request, so it is
+/// `Send + Sync` and can be kept in shared state and used from several threads at once.
+///
+/// ```rust
+/// use clamav_tcp::ClamdClient;
+/// let client = ClamdClient::new("localhost:3310");
+/// assert_eq!(client.ping().unwrap(), "PONG\0");
+///
+/// let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+/// let res = client.scan(&mut eicar).unwrap();
+/// assert_eq!(1, res.detected_infections.len());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClamdClient {
+    address: ClamdAddress,
+    chunk_size: usize,
+    reply_buffer_size: usize,
+    fallback_to_stream: bool,
+    strict_mode: bool,
+    declared_max_stream: Option<u64>,
+    single_chunk_max: Option<usize>,
+    metrics: Option<Arc<ScanMetrics>>,
+    timeout: Option<Duration>,
+    commands: Commands,
+    version_cache: Option<Arc<VersionCache>>,
+    /// The framing found to work, if it is detected automatically.
+    detected_framing: Option<Arc<Mutex<Option<Framing>>>>,
+    happy_eyeballs: Option<Duration>,
+    shutdown_write: bool,
+    #[cfg(feature = "socks")]
+    proxy: Option<Socks5Proxy>,
+    #[cfg(feature = "socket2")]
+    socket_options: Option<SocketOptions>,
+}
+
+impl ClamdClient {
+    /// Creates a client for the ClamAV host at `address` (eg. `"localhost:3310"`) with the default settings.
+    pub fn new(address: impl Into<ClamdAddress>) -> Self {
+        ClamdClient {
+            address: address.into(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            reply_buffer_size: DEFAULT_REPLY_BUFFER_SIZE,
+            fallback_to_stream: false,
+            strict_mode: false,
+            declared_max_stream: None,
+            single_chunk_max: None,
+            metrics: None,
+            timeout: None,
+            commands: Commands::default(),
+            version_cache: None,
+            detected_framing: None,
+            happy_eyeballs: None,
+            shutdown_write: false,
+            #[cfg(feature = "socks")]
+            proxy: None,
+            #[cfg(feature = "socket2")]
+            socket_options: None,
+        }
+    }
+
+    /// Creates a client for the ClamAV host listening on TCP at `address`, given in any of the
+    /// forms [ClamdAddress] converts from.
+    ///
+    /// ```rust
+    /// use clamav_tcp::ClamdClient;
+    /// let by_pair = ClamdClient::tcp(("127.0.0.1", 3310));
+    /// let by_str = ClamdClient::tcp("localhost:3310");
+    /// assert_eq!(by_pair.ping().unwrap(), by_str.ping().unwrap());
+    /// ```
+    pub fn tcp(address: impl Into<ClamdAddress>) -> Self {
+        ClamdClient::new(address)
+    }
+
+    /// Creates a [ClamdClientBuilder] for the ClamAV host at `address`.
+    pub fn builder(address: impl Into<ClamdAddress>) -> ClamdClientBuilder {
+        ClamdClientBuilder {
+            address: address.into(),
+            chunk_size: None,
+            reply_buffer_size: None,
+            require_chunk_size: false,
+            fallback_to_stream: false,
+            strict_mode: false,
+            declared_max_stream: None,
+            single_chunk_max: None,
+            metrics: None,
+            timeout: None,
+            commands: Commands::default(),
+            version_cache_ttl: None,
+            auto_terminator: false,
+            happy_eyeballs: None,
+            shutdown_write: false,
+            #[cfg(feature = "socks")]
+            proxy: None,
+            #[cfg(feature = "socket2")]
+            socket_options: None,
+        }
+    }
+
+    /// Creates a [ClamdClientBuilder] that has no default chunk size.
+    ///
+    /// [ClamdClientBuilder::build] fails unless [ClamdClientBuilder::chunk_size] was set, so the
+    /// memory used per scan is always an explicit decision. Use [ClamdClient::builder] to fall
+    /// back to the default of 4096 bytes instead.
+    ///
+    /// ```rust
+    /// use clamav_tcp::ClamdClient;
+    /// assert!(ClamdClient::explicit_builder("localhost:3310").build().is_err());
+    /// assert!(ClamdClient::explicit_builder("localhost:3310")
+    ///     .chunk_size(1024)
+    ///     .build()
+    ///     .is_ok());
+    /// ```
+    pub fn explicit_builder(address: impl Into<ClamdAddress>) -> ClamdClientBuilder {
+        ClamdClientBuilder {
+            require_chunk_size: true,
+            ..ClamdClient::builder(address)
+        }
+    }
+
+    /// Creates a client from TOML settings, so ClamAV can be configured in an application's
+    /// existing config file. Requires the `config` feature.
+    ///
+    /// Only `address` is required:
+    /// ```toml
+    /// address = "localhost:3310"
+    /// # Bytes per INSTREAM chunk, see ClamdClientBuilder::chunk_size.
+    /// chunk_size = 16384
+    /// # Seconds, see ClamdClientBuilder::timeout.
+    /// timeout = 2.5
+    /// ```
+    ///
+    /// Unknown keys and invalid values fail with [ClamAVClientError::InvalidConfiguration].
+    ///
+    /// ```rust
+    /// use clamav_tcp::ClamdClient;
+    /// let client = ClamdClient::from_config_str(r#"
+    ///     address = "localhost:3310"
+    ///     timeout = 5
+    /// "#).unwrap();
+    /// assert_eq!(client.ping().unwrap(), "PONG\0");
+    /// ```
+    #[cfg(feature = "config")]
+    pub fn from_config_str(config: &str) -> Result<ClamdClient, ClamAVClientError> {
+        let config: Config = toml::from_str(config)
+            .map_err(|e| ClamAVClientError::InvalidConfiguration(e.message().to_string()))?;
+
+        let mut builder = ClamdClient::builder(config.address);
+        if let Some(chunk_size) = config.chunk_size {
+            builder = builder.chunk_size(chunk_size);
+        }
+        if let Some(timeout) = config.timeout {
+            let timeout = Duration::try_from_secs_f64(timeout).map_err(|_| {
+                ClamAVClientError::InvalidConfiguration(format!(
+                    "timeout must be a positive number of seconds, got {timeout}"
+                ))
+            })?;
+            builder = builder.timeout(timeout);
+        }
+        builder.build()
+    }
+
+    /// The address of the ClamAV host.
+    pub fn address(&self) -> &ClamdAddress {
+        &self.address
+    }
+
+    /// Opens a connection in a session, to send several commands over one connection.
+    ///
+    /// The connection uses the client's chunk size and strict mode. See [ClamdConnection].
+    pub fn connect(&self) -> Result<ClamdConnection, ClamAVClientError> {
+        let stream = self.open()?;
+        Ok(ClamdConnection::from_transport(stream, self.chunk_size)?.strict_mode(self.strict_mode))
+    }
+
+    /// Checks if the ClamAV host is up. See [ping](crate::ping).
+    pub fn ping(&self) -> Result<String, ClamAVClientError> {
+        let request = self.commands.ping_request(self.framing()?);
+        ping_on(&mut self.open()?, &request)
+    }
+
+    /// Measures the PING round trip. See [ping_latency](crate::ping_latency).
+    pub fn ping_latency(&self) -> Result<Duration, ClamAVClientError> {
+        let request = self.commands.ping_request(self.framing()?);
+        ping_latency_on(&mut self.open()?, &request)
+    }
+
+    /// Checks ClamAV version. See [version](crate::version).
+    pub fn version(&self) -> Result<String, ClamAVClientError> {
+        let request = self.commands.version_request(self.framing()?);
+        version_on(&mut self.open()?, &request)
+    }
+
+    /// Checks ClamAV version like [ClamdClient::version], reusing the last reply until it is older
+    /// than the TTL set with [ClamdClientBuilder::version_cache_ttl].
+    ///
+    /// ClamAV's version only changes when it is updated, so a cached reply saves a round trip for
+    /// frequent capability checks, at the cost of reporting the old version for up to the TTL
+    /// after an update. The cache is shared by the clones of the client. Without a TTL every call
+    /// asks ClamAV.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use clamav_tcp::ClamdClient;
+    /// let client = ClamdClient::builder("localhost:3310")
+    ///     .version_cache_ttl(Duration::from_secs(300))
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(client.version_cached().unwrap(), client.version_cached().unwrap());
+    /// ```
+    pub fn version_cached(&self) -> Result<String, ClamAVClientError> {
+        match &self.version_cache {
+            Some(cache) => cache.get_or_refresh(|| self.version()),
+            None => self.version(),
+        }
+    }
+
+    /// Checks ClamAV version and parses the reply. See [version_parsed](crate::version_parsed).
+    pub fn version_parsed(&self) -> Result<VersionInfo, ClamAVClientError> {
+        self.version()?.parse()
+    }
+
+    /// Checks ClamAV's health over a single session connection. See [probe](crate::probe).
+    pub fn probe(&self, scan_eicar: bool) -> ProbeReport {
+        match self.connect() {
+            Ok(conn) => probe_over(conn, scan_eicar),
+            Err(_) => ProbeReport::default(),
+        }
+    }
+
+    /// Checks that ClamAV detects the EICAR test file. See [self_test](crate::self_test).
+    pub fn self_test(&self) -> Result<bool, ClamAVClientError> {
+        self_test_on(&mut self.open()?)
+    }
+
+    /// Fetches ClamAV's statistics. See [stats](crate::stats).
+    pub fn stats(&self) -> Result<ClamdStats, ClamAVClientError> {
+        stats_on(&mut self.open()?, self.reply_buffer_size)
+    }
+
+    /// Lists the commands the ClamAV host supports. See [commands](crate::commands).
+    pub fn commands(&self) -> Result<CommandSet, ClamAVClientError> {
+        commands_on(&mut self.open()?)
+    }
+
+    /// Scans something that is [Read] using the configured chunk size. See [scan](crate::scan).
+    ///
+    /// With [ClamdClientBuilder::declared_max_stream] set, the scan is aborted with
+    /// [ClamAVClientError::SizeLimitExceeded] as soon as more than the limit has been streamed.
+    pub fn scan<D: Read>(&self, file: &mut D) -> Result<ScanResult, ClamAVClientError> {
+        self.scan_with_opts(file, ScanOpts::default())
+    }
+
+    /// Scans something that is [Read] like [ClamdClient::scan], overriding some of the client's
+    /// settings for this scan only. Settings that aren't set in `opts` are taken from the client.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use clamav_tcp::{ClamdClient, ScanOpts};
+    /// let client = ClamdClient::new("localhost:3310");
+    /// let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+    /// let opts = ScanOpts::default()
+    ///     .chunk_size(16384)
+    ///     .timeout(Duration::from_secs(60));
+    /// let res = client.scan_with_opts(&mut eicar, opts).unwrap();
+    /// assert!(res.is_infected);
+    /// ```
+    pub fn scan_with_opts<D: Read>(
+        &self,
+        file: &mut D,
+        opts: ScanOpts,
+    ) -> Result<ScanResult, ClamAVClientError> {
+        self.scan_instream(file, opts, None).map(|(res, _)| res)
+    }
+
+    /// Scans something that is [Read] and [Seek], like [ClamdClient::scan].
+    ///
+    /// With [ClamdClientBuilder::declared_max_stream] set, the length of the input is checked
+    /// before connecting, so an input that is too large is rejected without streaming any of it.
+    ///
+    /// ```rust
+    /// use clamav_tcp::{ClamAVClientError, ClamdClient};
+    /// let client = ClamdClient::builder("localhost:3310")
+    ///     .declared_max_stream(16)
+    ///     .build()
+    ///     .unwrap();
+    /// let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+    /// assert!(matches!(
+    ///     client.scan_seekable(&mut eicar),
+    ///     Err(ClamAVClientError::SizeLimitExceeded)
+    /// ));
+    /// ```
+    pub fn scan_seekable<D: Read + Seek>(
+        &self,
+        file: &mut D,
+    ) -> Result<ScanResult, ClamAVClientError> {
+        if let Some(max) = self.declared_max_stream {
+            let len = remaining_len(file).map_err(ClamAVClientError::UnableToReadInput)?;
+            if len > max {
+                return Err(ClamAVClientError::SizeLimitExceeded);
+            }
+        }
+        self.scan(file)
+    }
+
+    /// Scans something that is [Read] and [Seek] like [ClamdClient::scan], reporting whether less
+    /// was read than its length. See [scan_report](crate::scan_report).
+    pub fn scan_report<D: Read + Seek>(
+        &self,
+        file: &mut D,
+    ) -> Result<ScanReport, ClamAVClientError> {
+        let expected_len = remaining_len(file).map_err(ClamAVClientError::UnableToReadInput)?;
+        let (result, stats) = self.scan_instream(file, ScanOpts::default(), None)?;
+        Ok(ScanReport {
+            result,
+            stats,
+            expected_len,
+            truncated: stats.bytes_sent < expected_len,
+        })
+    }
+
+    /// Scans the bytes of something that is [Read] and [Seek] that are there when the scan
+    /// starts like [ClamdClient::scan], returning the number of bytes scanned. See
+    /// [scan_snapshot](crate::scan_snapshot).
+    pub fn scan_snapshot<D: Read + Seek>(
+        &self,
+        file: &mut D,
+    ) -> Result<(ScanResult, u64), ClamAVClientError> {
+        let len = remaining_len(file).map_err(ClamAVClientError::UnableToReadInput)?;
+        self.scan_instream(&mut file.take(len), ScanOpts::default(), None)
+            .map(|(res, stats)| (res, stats.bytes_sent))
+    }
+
+    /// Scans input that is already split into chunks with the client's settings, like
+    /// [ClamdClient::scan], splitting chunks longer than the configured chunk size. See
+    /// [scan_chunks](crate::scan_chunks).
+    pub fn scan_chunks<I>(&self, chunks: I) -> Result<ScanResult, ClamAVClientError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[Byte]>,
+    {
+        self.scan_instream(&mut ChunksReader::new(chunks), ScanOpts::default(), None)
+            .map(|(res, _)| res)
+    }
+
+    /// Scans a local file with the client's settings and, if it is infected, **deletes it** or
+    /// moves it into `quarantine_dir`. See [scan_file_quarantine](crate::scan_file_quarantine).
+    pub fn scan_file_quarantine(
+        &self,
+        path: impl AsRef<Path>,
+        quarantine_dir: Option<&Path>,
+    ) -> Result<ScanResult, ClamAVClientError> {
+        let path = path.as_ref();
+        let mut file = File::open(path).map_err(ClamAVClientError::UnableToReadInput)?;
+        let res = self.scan(&mut file)?;
+        // The file must be closed before it is removed on some platforms.
+        drop(file);
+        quarantine_infected(res, path, quarantine_dir)
+    }
+
+    /// Scans an upload with the client's settings, like [ClamdClient::scan], and accepts it only
+    /// if it is at most `max_bytes` long and clean. See [scan_upload](crate::scan_upload).
+    pub fn scan_upload<D: Read>(
+        &self,
+        body: &mut D,
+        max_bytes: u64,
+    ) -> Result<(), UploadRejection> {
+        // One byte over the limit is enough to tell that the upload is too large.
+        let mut body = body.take(max_bytes.saturating_add(1));
+        let res = self.scan_instream(&mut body, ScanOpts::default(), Some(max_bytes));
+        judge_upload(res.map(|(res, _)| res), body.limit() == 0)
+    }
+
+    /// Scans a path on the ClamAV host. See [scan_path](crate::scan_path).
+    ///
+    /// If [ClamdClientBuilder::fallback_to_stream] is enabled and ClamAV can't access the path,
+    /// the file is opened locally and streamed to ClamAV instead.
+    pub fn scan_path(&self, path: &str) -> Result<ScanResult, ClamAVClientError> {
+        match self
+            .open()
+            .and_then(|mut stream| scan_path_on(&mut stream, path, self.reply_buffer_size))
+        {
+            Err(ClamAVClientError::ClamdError(e))
+                if self.fallback_to_stream && is_path_access_error(&e) =>
+            {
+                match File::open(path) {
+                    Ok(mut file) => self.scan(&mut file),
+                    Err(_) => {
+                        // Keep ClamAV's error if the file isn't readable here either.
+                        let res = Err(ClamAVClientError::ClamdError(e));
+                        self.record(res.as_ref(), 0);
+                        res
+                    }
+                }
+            }
+            res => {
+                self.record(res.as_ref(), 0);
+                res
+            }
+        }
+    }
+
+    /// Streams `file` over INSTREAM with the client's settings, overridden by `opts`, and records
+    /// the scan in the metrics. Every scan of streamed input ends up here.
+    ///
+    /// On top of [ClamdClientBuilder::declared_max_stream], the scan is aborted with
+    /// [ClamAVClientError::SizeLimitExceeded] once more than `max_len` bytes have been streamed.
+    fn scan_instream<D: Read>(
+        &self,
+        file: &mut D,
+        opts: ScanOpts,
+        max_len: Option<u64>,
+    ) -> Result<(ScanResult, ScanStats), ClamAVClientError> {
+        let chunk_size = opts.chunk_size.unwrap_or(self.chunk_size);
+        check_chunk_size(chunk_size)?;
+        let timeout = opts.timeout.or(self.timeout);
+        check_timeout(timeout)?;
+        let max_len = match (self.declared_max_stream, max_len) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+
+        let (buffered, chunk_size) = match self.single_chunk_max {
+            Some(max_len) => buffer_single_chunk(file, max_len, chunk_size)
+                .map_err(ClamAVClientError::UnableToReadInput)?,
+            None => (vec![], chunk_size),
+        };
+
+        let header = self.commands.instream_request(self.framing()?);
+        let mut streamed: u64 = 0;
+        let res = self
+            .open_with(timeout)
+            .and_then(|mut stream| {
+                let mut input = buffered.as_slice().chain(file);
+                exchange_closing(
+                    &mut stream,
+                    &header,
+                    &mut input,
+                    Some(chunk_size),
+                    |chunk| {
+                        streamed += chunk.len() as u64;
+                        match max_len {
+                            Some(max) if streamed > max => {
+                                Err(ClamAVClientError::SizeLimitExceeded)
+                            }
+                            _ => Ok(()),
+                        }
+                    },
+                    |stream| {
+                        if self.shutdown_write {
+                            stream.shutdown(Shutdown::Write)?;
+                        }
+                        Ok(())
+                    },
+                )
+            })
+            .and_then(|(reply, stats)| Ok((parse_reply(&reply, self.strict_mode)?, stats)));
+
+        self.record(res.as_ref().map(|(res, _)| res), streamed);
+        res
+    }
+
+    /// Connects to the ClamAV host, through the proxy if there is one.
+    fn open(&self) -> Result<TcpStream, ClamAVClientError> {
+        self.open_with(self.timeout)
+    }
+
+    /// Connects to the ClamAV host like [ClamdClient::open], with `timeout` instead of the
+    /// configured one.
+    fn open_with(&self, timeout: Option<Duration>) -> Result<TcpStream, ClamAVClientError> {
+        #[cfg(feature = "socks")]
+        if let Some(proxy) = &self.proxy {
+            let mut stream = self.connect_to(&proxy.address, timeout)?;
+            proxy.handshake(&mut stream, &self.address)?;
+            return Ok(stream);
+        }
+        self.connect_to(&self.address, timeout)
+    }
+
+    /// Connects to `address` with the configured socket options or connect race, see [connect_to].
+    fn connect_to(
+        &self,
+        address: &ClamdAddress,
+        timeout: Option<Duration>,
+    ) -> Result<TcpStream, ClamAVClientError> {
+        #[cfg(feature = "socket2")]
+        if let Some(socket_options) = &self.socket_options {
+            return socket_options.connect(address, timeout);
+        }
+        if let Some(attempt_delay) = self.happy_eyeballs {
+            return connect_racing(address, attempt_delay, timeout);
+        }
+        connect_to(address, timeout)
+    }
+
+    /// The framing to send commands with, detecting it first if needed, see
+    /// [ClamdClientBuilder::auto_terminator].
+    fn framing(&self) -> Result<Framing, ClamAVClientError> {
+        let Some(detected) = &self.detected_framing else {
+            return Ok(Framing::Null);
+        };
+        // Held while detecting, so concurrent requests wait for a single detection.
+        let mut detected = detected.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(framing) = *detected {
+            return Ok(framing);
+        }
+        let framing = self.detect_framing()?;
+        *detected = Some(framing);
+        Ok(framing)
+    }
+
+    /// Finds the framing ClamAV answers VERSION in, trying `z` first.
+    fn detect_framing(&self) -> Result<Framing, ClamAVClientError> {
+        let null = version_on(
+            &mut self.open()?,
+            &self.commands.version_request(Framing::Null),
+        );
+        if null.is_ok() {
+            return Ok(Framing::Null);
+        }
+        let newline = version_on(
+            &mut self.open()?,
+            &self.commands.version_request(Framing::Newline),
+        );
+        match newline {
+            Ok(_) => Ok(Framing::Newline),
+            Err(_) => null.map(|_| Framing::Null),
+        }
+    }
+
+    fn record(&self, res: Result<&ScanResult, &ClamAVClientError>, bytes: u64) {
+        if let Some(metrics) = &self.metrics {
+            metrics.record(res, bytes);
+        }
+    }
+}
+
+/// Settings that override the client's own for a single [ClamdClient::scan_with_opts].
+///
+/// Unset settings fall back to the client's. More settings may be added, so build it from
+/// [ScanOpts::default] and the setters.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct ScanOpts {
+    /// Bytes per INSTREAM chunk, see [ClamdClientBuilder::chunk_size].
+    pub chunk_size: Option<usize>,
+    /// Timeout for connecting and for every read and write, see [ClamdClientBuilder::timeout].
+    pub timeout: Option<Duration>,
+}
+
+impl ScanOpts {
+    /// Streams the input in chunks of `chunk_size` bytes.
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = Some(chunk_size);
+        self
+    }
+
+    /// Gives up on connecting and on every read and write after `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+/// The command tokens a [ClamdClient] sends, for ClamAV-compatible engines that use different
+/// ones than ClamAV. Set with [ClamdClientBuilder::commands].
+///
+/// Tokens are sent in ClamAV's `z` framing, eg. the token `PING` is sent as `zPING\0`, or `n`
+/// framing with [ClamdClientBuilder::auto_terminator], and must not be empty. They are only
+/// used by the requests the client makes itself, not by the commands sent over a
+/// [ClamdConnection]. More tokens may be added, so build it from [Commands::default] and the
+/// setters.
+///
+/// ```rust
+/// use clamav_tcp::{ClamdClient, Commands};
+/// let client = ClamdClient::builder("localhost:3310")
+///     .commands(Commands::default().instream("INSTREAM"))
+///     .build()
+///     .unwrap();
+/// assert!(ClamdClient::builder("localhost:3310")
+///     .commands(Commands::default().ping(""))
+///     .build()
+///     .is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Commands {
+    /// Streams data to scan. Defaults to `INSTREAM`.
+    pub instream: String,
+    /// Checks that the engine is up. Defaults to `PING`.
+    pub ping: String,
+    /// Asks for the engine version. Defaults to `VERSION`.
+    pub version: String,
+}
+
+impl Default for Commands {
+    fn default() -> Self {
+        Commands {
+            instream: "INSTREAM".to_string(),
+            ping: "PING".to_string(),
+            version: "VERSION".to_string(),
+        }
+    }
+}
+
+impl Commands {
+    /// Sends `token` instead of `INSTREAM`.
+    pub fn instream(mut self, token: impl Into<String>) -> Self {
+        self.instream = token.into();
+        self
+    }
+
+    /// Sends `token` instead of `PING`.
+    pub fn ping(mut self, token: impl Into<String>) -> Self {
+        self.ping = token.into();
+        self
+    }
+
+    /// Sends `token` instead of `VERSION`.
+    pub fn version(mut self, token: impl Into<String>) -> Self {
+        self.version = token.into();
+        self
+    }
+
+    fn check(&self) -> Result<(), ClamAVClientError> {
+        for (name, token) in [
+            ("INSTREAM", &self.instream),
+            ("PING", &self.ping),
+            ("VERSION", &self.version),
+        ] {
+            // A terminator would end the command early and send the rest as another one.
+            if token.is_empty() || token.contains(['\0', '\n']) {
+                return Err(ClamAVClientError::InvalidConfiguration(format!(
+                    "the {name} command must be a non-empty token without \\0 or \\n, got {token:?}"
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn instream_request(&self, framing: Framing) -> Vec<Byte> {
+        framing.command(&self.instream)
+    }
+
+    fn ping_request(&self, framing: Framing) -> Vec<Byte> {
+        framing.command(&self.ping)
+    }
+
+    fn version_request(&self, framing: Framing) -> Vec<Byte> {
+        framing.command(&self.version)
+    }
+}
+
+/// Connects to `address`, giving up after `timeout` if there is one.
+fn connect_to(
+    address: &ClamdAddress,
+    timeout: Option<Duration>,
+) -> Result<TcpStream, ClamAVClientError> {
+    match timeout {
+        Some(timeout) => connect_tcp_socket_timeout(address, timeout),
+        None => connect_tcp_socket(address),
+    }
+}
+
+fn check_chunk_size(chunk_size: usize) -> Result<(), ClamAVClientError> {
+    if chunk_size == 0 || u32::try_from(chunk_size).is_err() {
+        return Err(ClamAVClientError::InvalidConfiguration(format!(
+            "chunk size must be between 1 and {} bytes",
+            u32::MAX
+        )));
+    }
+    Ok(())
+}
+
+fn check_timeout(timeout: Option<Duration>) -> Result<(), ClamAVClientError> {
+    if timeout == Some(Duration::ZERO) {
+        return Err(ClamAVClientError::InvalidConfiguration(
+            "timeout must not be zero".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Builds a [ClamdClient] with non-default settings.
+///
+/// ```rust
+/// use clamav_tcp::ClamdClient;
+/// let client = ClamdClient::builder("localhost:3310")
+///     .chunk_size(16384)
+///     .fallback_to_stream(true)
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClamdClientBuilder {
+    address: ClamdAddress,
+    chunk_size: Option<usize>,
+    reply_buffer_size: Option<usize>,
+    require_chunk_size: bool,
+    fallback_to_stream: bool,
+    strict_mode: bool,
+    declared_max_stream: Option<u64>,
+    single_chunk_max: Option<usize>,
+    metrics: Option<Arc<ScanMetrics>>,
+    timeout: Option<Duration>,
+    commands: Commands,
+    version_cache_ttl: Option<Duration>,
+    auto_terminator: bool,
+    happy_eyeballs: Option<Duration>,
+    shutdown_write: bool,
+    #[cfg(feature = "socks")]
+    proxy: Option<Socks5Proxy>,
+    #[cfg(feature = "socket2")]
+    socket_options: Option<SocketOptions>,
+}
+
+impl ClamdClientBuilder {
+    /// Size of the chunks the input is streamed to ClamAV in.
+    ///
+    /// Defaults to 4096 bytes, unless the builder was created with [ClamdClient::explicit_builder].
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = Some(chunk_size);
+        self
+    }
+
+    /// Number of bytes read at a time from the replies to [ClamdClient::stats] and
+    /// [ClamdClient::scan_path], which can be large. Doesn't affect how inputs are streamed, see
+    /// [ClamdClientBuilder::chunk_size] for that. Defaults to 8192 bytes.
+    pub fn reply_buffer_size(mut self, reply_buffer_size: usize) -> Self {
+        self.reply_buffer_size = Some(reply_buffer_size);
+        self
+    }
+
+    /// Whether [ClamdClient::scan_path] streams the file to ClamAV when ClamAV can't access the path.
+    ///
+    /// Useful when the same code runs with ClamAV on the same host (eg. in development) and
+    /// on a different host or container (eg. in production). The path is then read on the
+    /// machine running the client. Defaults to `false`.
+    pub fn fallback_to_stream(mut self, fallback_to_stream: bool) -> Self {
+        self.fallback_to_stream = fallback_to_stream;
+        self
+    }
+
+    /// Whether scan replies must be exactly `\0` terminated, with no unexpected trailing data.
+    ///
+    /// Helps catch proxies or protocol mismatches between the client and ClamAV early.
+    /// Defaults to `false`, which ignores anything ClamAV sends after a result.
+    pub fn strict_mode(mut self, strict_mode: bool) -> Self {
+        self.strict_mode = strict_mode;
+        self
+    }
+
+    /// The `StreamMaxLength` ClamAV is configured with, to reject inputs that are too large locally.
+    ///
+    /// ClamAV aborts a stream that is longer than its `StreamMaxLength`, but only after the
+    /// client has sent that much. With this set, [ClamdClient::scan] stops streaming once the
+    /// limit is passed and [ClamdClient::scan_seekable] rejects inputs before sending anything.
+    /// The check is advisory: the value must match the server configuration, which can't be
+    /// queried over the ClamAV protocol. Defaults to no limit.
+    pub fn declared_max_stream(mut self, max_bytes: u64) -> Self {
+        self.declared_max_stream = Some(max_bytes);
+        self
+    }
+
+    /// Sends inputs of at most `max_len` bytes to ClamAV as a single INSTREAM chunk, which saves
+    /// the framing of many small chunks.
+    ///
+    /// [ClamdClient::scan] reads up to `max_len` bytes into memory before connecting. Longer inputs
+    /// are streamed in chunks of [ClamdClientBuilder::chunk_size] as usual, so `max_len` should not
+    /// be above the `StreamMaxLength` ClamAV is configured with. Defaults to always streaming in
+    /// chunks.
+    pub fn single_chunk(mut self, max_len: usize) -> Self {
+        self.single_chunk_max = Some(max_len);
+        self
+    }
+
+    /// Counters the client updates after every scan. See [ScanMetrics].
+    pub fn metrics(mut self, metrics: Arc<ScanMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Timeout for connecting to ClamAV and for every read and write after that. Without it,
+    /// requests wait on ClamAV indefinitely.
+    ///
+    /// A timed out request fails with a [retryable](ClamAVClientError::is_retryable) error.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// How long [ClamdClient::version_cached] reuses a version reply before asking ClamAV again.
+    /// Defaults to not caching.
+    pub fn version_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.version_cache_ttl = Some(ttl);
+        self
+    }
+
+    /// Whether to detect if ClamAV, or a proxy in front of it, only understands `n` framed
+    /// commands, eg. `nPING\n`, instead of the `z` framed ones used by default.
+    ///
+    /// Before its first request the client sends VERSION with `z` framing and, if that fails,
+    /// with `n` framing, and keeps using the one that worked. If neither does, the request fails
+    /// with the error of the `z` attempt and detection is tried again on the next request. Only
+    /// PING, VERSION and INSTREAM are sent with the detected framing, see [Commands], and strict
+    /// mode rejects the `\n` terminated replies. Defaults to `false`, always using `z` framing.
+    pub fn auto_terminator(mut self, auto_terminator: bool) -> Self {
+        self.auto_terminator = auto_terminator;
+        self
+    }
+
+    /// Whether [ClamdClient::scan] and [ClamdClient::scan_with_opts] shut down the sending half of
+    /// the connection once the input has been streamed, telling ClamAV that nothing else will be
+    /// sent. The reply is read as usual.
+    ///
+    /// Only those scans use a connection for a single command, so sessions from
+    /// [ClamdClient::connect] and other requests are not affected. Some proxies close the whole
+    /// connection when one side shuts down, so this defaults to `false`.
+    pub fn shutdown_write(mut self, shutdown_write: bool) -> Self {
+        self.shutdown_write = shutdown_write;
+        self
+    }
+
+    /// Races the connects to the addresses ClamAV's host name resolves to, or that it was given
+    /// as, instead of trying them one after the other, so an unreachable address family on a
+    /// dual-stack host doesn't hold up every request.
+    ///
+    /// Addresses are tried alternating between IPv6 and IPv4, each on its own thread, starting
+    /// the next one after `attempt_delay` or as soon as the previous one fails. The first
+    /// connection made is used and the others are closed. A blocked attempt still holds its
+    /// thread until it times out, so this is best combined with [ClamdClientBuilder::timeout].
+    /// Can't be combined with socket options. Defaults to trying the addresses one at a time.
+    ///
+    /// ```rust
+    /// use std::time::Duration;
+    /// use clamav_tcp::ClamdClient;
+    /// let client = ClamdClient::builder("localhost:3310")
+    ///     .happy_eyeballs(Duration::from_millis(250))
+    ///     .timeout(Duration::from_secs(5))
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(client.ping().unwrap(), "PONG\0");
+    /// ```
+    pub fn happy_eyeballs(mut self, attempt_delay: Duration) -> Self {
+        self.happy_eyeballs = Some(attempt_delay);
+        self
+    }
+
+    /// The command tokens sent for PING, VERSION and INSTREAM, for ClamAV-compatible engines that
+    /// use different ones. See [Commands]. Defaults to ClamAV's own.
+    pub fn commands(mut self, commands: Commands) -> Self {
+        self.commands = commands;
+        self
+    }
+
+    /// Tunnels every connection to ClamAV through a SOCKS5 proxy. The timeout also applies to
+    /// connecting to the proxy and to the proxy handshake. Requires the `socks` feature.
+    #[cfg(feature = "socks")]
+    pub fn socks5_proxy(mut self, proxy: Socks5Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Socket options for every connection to ClamAV, or to the SOCKS5 proxy if there is one.
+    /// See [SocketOptions]. Requires the `socket2` feature.
+    #[cfg(feature = "socket2")]
+    pub fn socket_options(mut self, socket_options: SocketOptions) -> Self {
+        self.socket_options = Some(socket_options);
+        self
+    }
+
+    /// Builds the [ClamdClient], checking that the settings can be used.
+    pub fn build(self) -> Result<ClamdClient, ClamAVClientError> {
+        let chunk_size = match self.chunk_size {
+            Some(chunk_size) => chunk_size,
+            None if self.require_chunk_size => {
+                return Err(ClamAVClientError::InvalidConfiguration(
+                    "chunk size must be set explicitly".to_string(),
+                ))
+            }
+            None => DEFAULT_CHUNK_SIZE,
+        };
+        check_chunk_size(chunk_size)?;
+        let reply_buffer_size = self.reply_buffer_size.unwrap_or(DEFAULT_REPLY_BUFFER_SIZE);
+        if reply_buffer_size == 0 {
+            return Err(ClamAVClientError::InvalidConfiguration(
+                "reply buffer size must not be zero".to_string(),
+            ));
+        }
+        if let Some(max_len) = self.single_chunk_max {
+            check_chunk_size(max_len)?;
+        }
+        check_timeout(self.timeout)?;
+        self.commands.check()?;
+        if self.version_cache_ttl == Some(Duration::ZERO) {
+            return Err(ClamAVClientError::InvalidConfiguration(
+                "version cache TTL must not be zero".to_string(),
+            ));
+        }
+        #[cfg(feature = "socket2")]
+        if self.happy_eyeballs.is_some() && self.socket_options.is_some() {
+            return Err(ClamAVClientError::InvalidConfiguration(
+                "happy eyeballs can't be combined with socket options".to_string(),
+            ));
+        }
+
+        Ok(ClamdClient {
+            address: self.address,
+            chunk_size,
+            reply_buffer_size,
+            fallback_to_stream: self.fallback_to_stream,
+            strict_mode: self.strict_mode,
+            declared_max_stream: self.declared_max_stream,
+            single_chunk_max: self.single_chunk_max,
+            metrics: self.metrics,
+            timeout: self.timeout,
+            commands: self.commands,
+            version_cache: self
+                .version_cache_ttl
+                .map(|ttl| Arc::new(VersionCache::new(ttl))),
+            detected_framing: self.auto_terminator.then(Arc::default),
+            happy_eyeballs: self.happy_eyeballs,
+            shutdown_write: self.shutdown_write,
+            #[cfg(feature = "socks")]
+            proxy: self.proxy,
+            #[cfg(feature = "socket2")]
+            socket_options: self.socket_options,
+        })
+    }
+}
+
+fn is_path_access_error(e: &str) -> bool {
+    PATH_ACCESS_ERRORS.iter().any(|m| e.contains(m))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Starts a server that answers one INSTREAM connection per reply in `replies`, and returns
+    /// its address and the requests it received. A request ends with the footer or when the
+    /// client stops sending.
+    fn instream_server(
+        replies: &'static [&'static [u8]],
+    ) -> (String, std::thread::JoinHandle<Vec<Vec<u8>>>) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let mut requests = vec![];
+            for reply in replies {
+                let (mut conn, _) = listener.accept().unwrap();
+                let mut request = vec![];
+                let mut buf = [0; 256];
+                while !request.ends_with(crate::protocol::INSTREAM_FOOTER) {
+                    match conn.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let _ = std::io::Write::write_all(&mut conn, reply);
+                requests.push(request);
+            }
+            requests
+        });
+        (addr, server)
+    }
+
+    #[test]
+    fn builder_rejects_zero_timeout() {
+        let res = ClamdClient::builder("localhost:3310")
+            .timeout(Duration::ZERO)
+            .build();
+        assert!(matches!(
+            res,
+            Err(ClamAVClientError::InvalidConfiguration(_))
+        ));
+    }
+
+    #[test]
+    fn times_out_on_a_silent_host() {
+        // Accepts the connection but never replies.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = ClamdClient::builder(listener.local_addr().unwrap())
+            .timeout(Duration::from_millis(50))
+            .build()
+            .unwrap();
+
+        let err = client.ping().unwrap_err();
+        assert!(err.is_retryable(), "{err:?}");
+        drop(listener);
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn reads_config() {
+        let client = ClamdClient::from_config_str(
+            r#"
+            address = "localhost:3310"
+            chunk_size = 1024
+            timeout = 0.5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(client.address(), &ClamdAddress::from("localhost:3310"));
+        assert_eq!(client.chunk_size, 1024);
+        assert_eq!(client.timeout, Some(Duration::from_millis(500)));
+
+        let client = ClamdClient::from_config_str(r#"address = "localhost:3310""#).unwrap();
+        assert_eq!(client.chunk_size, DEFAULT_CHUNK_SIZE);
+        assert_eq!(client.timeout, None);
+    }
+
+    #[cfg(feature = "config")]
+    #[test]
+    fn rejects_invalid_config() {
+        for config in [
+            "",
+            "address = 3310",
+            r#"address = "localhost:3310"
+            chunk_size = 0"#,
+            r#"address = "localhost:3310"
+            timeout = -1"#,
+            r#"address = "localhost:3310"
+            timeout = 0"#,
+            r#"address = "localhost:3310"
+            retries = 3"#,
+        ] {
+            assert!(
+                matches!(
+                    ClamdClient::from_config_str(config),
+                    Err(ClamAVClientError::InvalidConfiguration(_))
+                ),
+                "{config}"
+            );
+        }
+    }
+
+    #[test]
+    fn opts_override_client_settings() {
+        let client = ClamdClient::builder("localhost:3310")
+            .chunk_size(4)
+            .build()
+            .unwrap();
+        let mut eicar = File::open("resources/eicar.txt").unwrap();
+        let res = client
+            .scan_with_opts(&mut eicar, ScanOpts::default().chunk_size(1024))
+            .unwrap();
+        assert!(res.is_infected);
+
+        // A silent host times out with the per-scan timeout even though the client has none.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = ClamdClient::new(listener.local_addr().unwrap());
+        let opts = ScanOpts::default().timeout(Duration::from_millis(50));
+        let err = client
+            .scan_with_opts(&mut "data".as_bytes(), opts)
+            .unwrap_err();
+        assert!(err.is_retryable(), "{err:?}");
+    }
+
+    #[test]
+    fn opts_are_validated() {
+        let client = ClamdClient::new("localhost:3310");
+        for opts in [
+            ScanOpts::default().chunk_size(0),
+            ScanOpts::default().timeout(Duration::ZERO),
+        ] {
+            assert!(matches!(
+                client.scan_with_opts(&mut "data".as_bytes(), opts),
+                Err(ClamAVClientError::InvalidConfiguration(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn builder_rejects_zero_chunk_size() {
+        let res = ClamdClient::builder("localhost:3310").chunk_size(0).build();
+        assert!(matches!(
+            res,
+            Err(ClamAVClientError::InvalidConfiguration(_))
+        ));
+    }
+
+    #[test]
+    fn explicit_builder_requires_chunk_size() {
+        let res = ClamdClient::explicit_builder("localhost:3310").build();
+        assert!(matches!(
+            res,
+            Err(ClamAVClientError::InvalidConfiguration(_))
+        ));
+
+        let client = ClamdClient::explicit_builder("localhost:3310")
+            .chunk_size(1024)
+            .build()
+            .unwrap();
+        assert_eq!(client.chunk_size, 1024);
+    }
+
+    #[test]
+    fn builder_rejects_oversized_single_chunk() {
+        for max_len in [0, u32::MAX as usize + 1] {
+            let res = ClamdClient::builder("localhost:3310")
+                .single_chunk(max_len)
+                .build();
+            assert!(matches!(
+                res,
+                Err(ClamAVClientError::InvalidConfiguration(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn builder_rejects_zero_reply_buffer_size() {
+        let res = ClamdClient::builder("localhost:3310")
+            .reply_buffer_size(0)
+            .build();
+        assert!(matches!(
+            res,
+            Err(ClamAVClientError::InvalidConfiguration(_))
+        ));
+
+        let client = ClamdClient::builder("localhost:3310")
+            .reply_buffer_size(64)
+            .build()
+            .unwrap();
+        assert_eq!(client.reply_buffer_size, 64);
+        assert_eq!(client.chunk_size, DEFAULT_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn default_commands_match_clamav() {
+        let commands = Commands::default();
+        assert_eq!(
+            commands.instream_request(Framing::Null),
+            crate::protocol::INSTREAM_HEADER
+        );
+        assert_eq!(
+            commands.ping_request(Framing::Null),
+            crate::ping::PING_REQUEST
+        );
+        assert_eq!(
+            commands.version_request(Framing::Null),
+            crate::version::VERSION_REQUEST
+        );
+    }
+
+    #[test]
+    fn sends_overridden_commands() {
+        let commands = Commands::default().ping("HELLO");
+        assert_eq!(commands.ping_request(Framing::Null), b"zHELLO\0");
+
+        let client = ClamdClient::builder("localhost:3310")
+            .commands(commands)
+            .build()
+            .unwrap();
+        let mut conn = crate::transport::mock::MockTransport::new(b"PONG\0");
+        ping_on(&mut conn, &client.commands.ping_request(Framing::Null)).unwrap();
+        assert_eq!(conn.written, b"zHELLO\0");
+    }
+
+    #[test]
+    fn builder_rejects_empty_commands() {
+        for commands in [
+            Commands::default().instream(""),
+            Commands::default().ping(""),
+            Commands::default().version("VER\0SION"),
+        ] {
+            let res = ClamdClient::builder("localhost:3310")
+                .commands(commands)
+                .build();
+            assert!(matches!(
+                res,
+                Err(ClamAVClientError::InvalidConfiguration(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn builder_defaults_chunk_size() {
+        let client = ClamdClient::builder("localhost:3310").build().unwrap();
+        assert_eq!(client.chunk_size, DEFAULT_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn strict_mode_accepts_clamd_replies() {
+        let client = ClamdClient::builder("localhost:3310")
+            .strict_mode(true)
+            .build()
+            .unwrap();
+        let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+        let res = client.scan(&mut eicar).unwrap();
+        assert_eq!(1, res.detected_infections.len());
+    }
+
+    #[test]
+    fn declared_max_stream_rejects_large_seekable_input() {
+        // Nothing is listening here, so the check must happen before connecting.
+        let client = ClamdClient::builder("127.0.0.1:1")
+            .declared_max_stream(10)
+            .build()
+            .unwrap();
+        let mut input = std::io::Cursor::new(vec![0; 11]);
+        let res = client.scan_seekable(&mut input);
+        assert!(matches!(res, Err(ClamAVClientError::SizeLimitExceeded)));
+    }
+
+    #[test]
+    fn declared_max_stream_aborts_unsized_input() {
+        let client = ClamdClient::builder("localhost:3310")
+            .chunk_size(8)
+            .declared_max_stream(20)
+            .build()
+            .unwrap();
+        let res = client.scan(&mut std::io::repeat(0));
+        assert!(matches!(res, Err(ClamAVClientError::SizeLimitExceeded)));
+
+        let res = client.scan(&mut "small enough".as_bytes()).unwrap();
+        assert!(!res.is_infected);
+    }
+
+    #[test]
+    fn detects_newline_framing_once() {
+        // Answers only `n` framed commands, dropping `z` framed ones without a reply.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let mut requests = vec![];
+            for _ in 0..4 {
+                let (mut conn, _) = listener.accept().unwrap();
+                let mut buf = [0; 16];
+                let len = conn.read(&mut buf).unwrap();
+                let request = String::from_utf8_lossy(&buf[..len]).into_owned();
+                let reply: &[u8] = match request.as_str() {
+                    "nVERSION\n" => b"ClamAV 1.0.0/26734/Mon Nov 28 08:17:05 2022\n",
+                    "nPING\n" => b"PONG\n",
+                    _ => b"",
+                };
+                let _ = std::io::Write::write_all(&mut conn, reply);
+                requests.push(request);
+            }
+            requests
+        });
+
+        let client = ClamdClient::builder(addr.to_string())
+            .auto_terminator(true)
+            .build()
+            .unwrap();
+        assert!(client.version().unwrap().ends_with('\n'));
+        assert_eq!(client.ping().unwrap(), "PONG\n");
+        assert_eq!(
+            server.join().unwrap(),
+            vec!["zVERSION\0", "nVERSION\n", "nVERSION\n", "nPING\n"]
+        );
+    }
+
+    #[test]
+    fn caches_version_across_clones() {
+        // The server only answers once, so a second request would fail to connect.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let _ = conn.read(&mut [0; 16]);
+            let _ = std::io::Write::write_all(
+                &mut conn,
+                b"ClamAV 1.0.0/26734/Mon Nov 28 08:17:05 2022\0",
+            );
+        });
+        let client = ClamdClient::builder(addr.to_string())
+            .version_cache_ttl(Duration::from_secs(60))
+            .build()
+            .unwrap();
+        let first = client.version_cached().unwrap();
+        assert_eq!(client.clone().version_cached().unwrap(), first);
+
+        let res = ClamdClient::builder("localhost:3310")
+            .version_cache_ttl(Duration::ZERO)
+            .build();
+        assert!(matches!(
+            res,
+            Err(ClamAVClientError::InvalidConfiguration(_))
+        ));
+    }
+
+    #[test]
+    fn reads_the_reply_after_shutting_down_the_write_half() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            // Only returns once the client has shut down its side.
+            let mut request = vec![];
+            conn.read_to_end(&mut request).unwrap();
+            std::io::Write::write_all(&mut conn, b"stream: Win.Test.EICAR_HDB-1 FOUND\0").unwrap();
+            request
+        });
+
+        let client = ClamdClient::builder(addr.to_string())
+            .shutdown_write(true)
+            .build()
+            .unwrap();
+        let res = client.scan(&mut &b"eicar"[..]).unwrap();
+        assert_eq!(res.detected_infections, vec!["Win.Test.EICAR_HDB-1"]);
+        let request = server.join().unwrap();
+        assert!(request.ends_with(crate::protocol::INSTREAM_FOOTER));
+    }
+
+    #[test]
+    fn scan_upload_uses_the_client_settings() {
+        let (addr, server) = instream_server(&[b"stream: OK", b"stream: OK\0"]);
+        let metrics = Arc::new(ScanMetrics::default());
+        let client = ClamdClient::builder(addr)
+            .chunk_size(16)
+            .strict_mode(true)
+            .metrics(metrics.clone())
+            .build()
+            .unwrap();
+
+        // Strict mode rejects the unterminated reply.
+        let res = client.scan_upload(&mut &b"hello"[..], 1024);
+        assert!(matches!(
+            res,
+            Err(UploadRejection::ScanError(
+                ClamAVClientError::UnableToParseResponse(_)
+            ))
+        ));
+        // Nothing past the byte over the limit is read.
+        let res = client.scan_upload(&mut &[b'a'; 64][..], 20);
+        assert!(matches!(res, Err(UploadRejection::TooLarge)));
+
+        let requests = server.join().unwrap();
+        assert!(!requests[1].ends_with(crate::protocol::INSTREAM_FOOTER));
+        assert_eq!(metrics.scans(), 2);
+        assert_eq!(metrics.errors(), 2);
+        assert_eq!(metrics.bytes(), 5 + 21);
+    }
+
+    #[test]
+    fn scan_chunks_uses_the_client_settings() {
+        let (addr, server) = instream_server(&[b"stream: OK\0", b"stream: OK\0"]);
+        let metrics = Arc::new(ScanMetrics::default());
+        let client = ClamdClient::builder(addr)
+            .chunk_size(4)
+            .declared_max_stream(7)
+            .shutdown_write(true)
+            .metrics(metrics.clone())
+            .build()
+            .unwrap();
+
+        let res = client.scan_chunks([&b"abc"[..], b"", b"defgh"]);
+        assert!(matches!(res, Err(ClamAVClientError::SizeLimitExceeded)));
+        let res = client.scan_chunks([&b"ab"[..], b"", b"cdefg"]).unwrap();
+        assert!(!res.is_infected);
+
+        let requests = server.join().unwrap();
+        let expected = [
+            crate::protocol::INSTREAM_HEADER,
+            &2u32.to_be_bytes(),
+            b"ab",
+            &4u32.to_be_bytes(),
+            b"cdef",
+            &1u32.to_be_bytes(),
+            b"g",
+            crate::protocol::INSTREAM_FOOTER,
+        ]
+        .concat();
+        assert_eq!(requests[1], expected);
+        assert_eq!(metrics.scans(), 2);
+        assert_eq!(metrics.errors(), 1);
+    }
+
+    #[test]
+    fn scan_snapshot_uses_the_client_settings() {
+        let (addr, server) = instream_server(&[b"stream: OK\0", b"stream: OK\0"]);
+        let metrics = Arc::new(ScanMetrics::default());
+        let client = ClamdClient::builder(addr)
+            .chunk_size(4)
+            .declared_max_stream(10)
+            .metrics(metrics.clone())
+            .build()
+            .unwrap();
+
+        let mut input = std::io::Cursor::new(vec![b'a'; 10]);
+        let (res, scanned) = client.scan_snapshot(&mut input).unwrap();
+        assert!(!res.is_infected);
+        assert_eq!(scanned, 10);
+        let mut input = std::io::Cursor::new(vec![b'a'; 11]);
+        let res = client.scan_snapshot(&mut input);
+        assert!(matches!(res, Err(ClamAVClientError::SizeLimitExceeded)));
+
+        server.join().unwrap();
+        assert_eq!(metrics.scans(), 2);
+        assert_eq!(metrics.errors(), 1);
+        assert_eq!(metrics.bytes(), 10 + 11);
+    }
+
+    #[test]
+    fn scan_report_uses_the_client_settings() {
+        let (addr, server) = instream_server(&[b"stream: OK\0"]);
+        let metrics = Arc::new(ScanMetrics::default());
+        let client = ClamdClient::builder(addr)
+            .chunk_size(4)
+            .single_chunk(16)
+            .metrics(metrics.clone())
+            .build()
+            .unwrap();
+
+        let mut input = std::io::Cursor::new(b"0123456789".to_vec());
+        let report = client.scan_report(&mut input).unwrap();
+        assert!(!report.truncated);
+        assert_eq!(report.stats.chunks, 1);
+
+        let requests = server.join().unwrap();
+        let expected = [
+            crate::protocol::INSTREAM_HEADER,
+            &10u32.to_be_bytes(),
+            b"0123456789",
+            crate::protocol::INSTREAM_FOOTER,
+        ]
+        .concat();
+        assert_eq!(requests[0], expected);
+        assert_eq!(metrics.scans(), 1);
+        assert_eq!(metrics.bytes(), 10);
+    }
+
+    #[cfg(feature = "socket2")]
+    #[test]
+    fn rejects_happy_eyeballs_with_socket_options() {
+        let res = ClamdClient::builder("localhost:3310")
+            .happy_eyeballs(Duration::from_millis(250))
+            .socket_options(SocketOptions::default().reuse_address(true))
+            .build();
+        assert!(matches!(
+            res,
+            Err(ClamAVClientError::InvalidConfiguration(_))
+        ));
+    }
+
+    #[test]
+    fn updates_metrics_after_scans() {
+        let metrics = Arc::new(ScanMetrics::default());
+        let client = ClamdClient::builder("localhost:3310")
+            .metrics(metrics.clone())
+            .build()
+            .unwrap();
+
+        client.scan(&mut "This is not a virus.".as_bytes()).unwrap();
+        let mut eicar = File::open("resources/eicar.txt").unwrap();
+        client.scan(&mut eicar).unwrap();
+        client.scan_path("/this/path/does/not/exist").unwrap_err();
+
+        assert_eq!(metrics.scans(), 3);
+        assert_eq!(metrics.infected(), 1);
+        assert_eq!(metrics.errors(), 1);
+        assert_eq!(
+            metrics.bytes(),
+            20 + std::fs::metadata("resources/eicar.txt").unwrap().len()
+        );
+    }
+
+    #[test]
+    fn recognizes_path_access_errors() {
+        assert!(is_path_access_error(
+            "/data/upload.zip: lstat() failed: No such file or directory. ERROR"
+        ));
+        assert!(is_path_access_error(
+            "/data/upload.zip: Access denied. ERROR"
+        ));
+        assert!(!is_path_access_error(
+            "/data/upload.zip: Can't allocate memory ERROR"
+        ));
+    }
+
+    #[test]
+    fn scan_path_reports_missing_path() {
+        let client = ClamdClient::new("localhost:3310");
+        let res = client.scan_path("/this/path/does/not/exist");
+        assert!(matches!(res, Err(ClamAVClientError::ClamdError(_))));
+    }
+
+    #[test]
+    fn scan_path_falls_back_to_stream() {
+        // Answers SCAN like a ClamAV on another host, where this file doesn't exist, and scans
+        // what is streamed instead.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let mut requests = vec![];
+            for reply in [
+                &b"resources/eicar.txt: lstat() failed: No such file or directory. ERROR\0"[..],
+                b"stream: Win.Test.EICAR_HDB-1 FOUND\0",
+            ] {
+                let (mut conn, _) = listener.accept().unwrap();
+                let mut request = vec![];
+                let mut buf = [0; 256];
+                while !request.ends_with(b"\0")
+                    || request.starts_with(b"zINSTREAM")
+                        && !request.ends_with(crate::protocol::INSTREAM_FOOTER)
+                {
+                    let n = conn.read(&mut buf).unwrap();
+                    assert!(n > 0, "closed mid request");
+                    request.extend_from_slice(&buf[..n]);
+                }
+                std::io::Write::write_all(&mut conn, reply).unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+
+        let client = ClamdClient::builder(addr.to_string())
+            .fallback_to_stream(true)
+            .build()
+            .unwrap();
+        let res = client.scan_path("resources/eicar.txt").unwrap();
+        assert_eq!(res.detected_infections, vec!["Win.Test.EICAR_HDB-1"]);
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0], b"zSCAN resources/eicar.txt\0");
+        let eicar = std::fs::read("resources/eicar.txt").unwrap();
+        assert!(requests[1].starts_with(crate::protocol::INSTREAM_HEADER));
+        assert!(requests[1].windows(eicar.len()).any(|w| w == eicar));
+    }
+}
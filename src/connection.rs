@@ -0,0 +1,576 @@
+use std::{collections::HashMap, io::Read, net::TcpStream};
+
+use crate::{
+    scan::{parse_reply, write_instream},
+    transport::{read_buffered_reply, trace_sent},
+    unable_to_parse, Byte, ClamAVClientError, ClamdTransport, ScanResult,
+};
+const IDSESSION_REQUEST: &[Byte] = b"zIDSESSION\0";
+const END_REQUEST: &[Byte] = b"zEND\0";
+const PING_REQUEST: &[Byte] = b"zPING\0";
+const VERSION_REQUEST: &[Byte] = b"zVERSION\0";
+/// Maximum number of scans [ClamdConnection::scan_all] sends before reading their replies.
+///
+/// Bounding it keeps unread replies from filling the socket buffers, which would leave both
+/// sides blocked on writing.
+const MAX_PIPELINED: usize = 32;
+
+/// A connection to ClamAV in a session, so several commands can be sent over it.
+///
+/// Created with [ClamdClient::connect](crate::ClamdClient::connect). ClamAV numbers the replies
+/// in a session, and every reply is checked against the command it answers. The session is
+/// ended when the connection is dropped, or explicitly with [ClamdConnection::close].
+///
+/// A command that fails before its reply was read, eg. a scan whose input can't be read, leaves
+/// the connection out of step with ClamAV. The connection is then broken: every later command
+/// fails with [ClamAVClientError::ConnectionClosed] without sending anything, and the session
+/// isn't ended with `END`, which ClamAV could take for part of the stream. Connections that are
+/// kept for reuse, eg. in a pool, must be discarded once [ClamdConnection::is_broken]. A command
+/// that ClamAV answered with an error doesn't break the connection.
+///
+/// ```rust
+/// use clamav_tcp::ClamdClient;
+/// let client = ClamdClient::new("localhost:3310");
+/// let mut conn = client.connect().unwrap();
+/// assert_eq!(conn.ping().unwrap(), "PONG\0");
+/// for _ in 0..2 {
+///     let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+///     let res = conn.scan(&mut eicar).unwrap();
+///     assert_eq!(1, res.detected_infections.len());
+/// }
+/// conn.close().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct ClamdConnection<T: ClamdTransport = TcpStream> {
+    transport: T,
+    chunk_size: usize,
+    strict_mode: bool,
+    /// Id of the last command sent in the session. ClamAV counts them from 1, and only ever
+    /// counts up, so every reply is matched to exactly one command.
+    last_id: u64,
+    closed: bool,
+    /// Set once a command failed before its reply was read, see [ClamdConnection::is_broken].
+    broken: bool,
+    /// Received bytes that belong to replies not read yet.
+    unread: Vec<Byte>,
+}
+
+impl<T: ClamdTransport> ClamdConnection<T> {
+    /// Starts a session over an already open transport, eg. a Unix socket or a proxied stream.
+    ///
+    /// The transport must be expecting a command.
+    pub fn from_transport(transport: T, chunk_size: usize) -> Result<Self, ClamAVClientError> {
+        let mut conn = ClamdConnection {
+            transport,
+            chunk_size,
+            strict_mode: false,
+            last_id: 0,
+            closed: false,
+            broken: false,
+            unread: vec![],
+        };
+        trace_sent(IDSESSION_REQUEST);
+        let sent = conn
+            .transport
+            .write_all(IDSESSION_REQUEST)
+            .map_err(ClamAVClientError::UnableToWriteToStream);
+        conn.break_on_error(sent)?;
+        Ok(conn)
+    }
+
+    pub(crate) fn strict_mode(mut self, strict_mode: bool) -> Self {
+        self.strict_mode = strict_mode;
+        self
+    }
+
+    /// Whether a command failed before its reply was read, so the connection can't be used
+    /// anymore and must not be reused.
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    /// Checks if the ClamAV host is up. See [ping](crate::ping).
+    pub fn ping(&mut self) -> Result<String, ClamAVClientError> {
+        self.command(PING_REQUEST)
+    }
+
+    /// Keeps the session open through an idle period by sending a PING.
+    ///
+    /// ClamAV ends a session that has been waiting for a command longer than its `IdleTimeout`.
+    /// The connection is borrowed mutably, so a keepalive can't overlap a command on the same
+    /// session. Call it from the thread using the session whenever it has been idle for a while,
+    /// well within the timeout. A reply other than `PONG` breaks the connection.
+    ///
+    /// ```rust
+    /// use std::{sync::mpsc, time::Duration};
+    /// use clamav_tcp::ClamdClient;
+    /// let mut conn = ClamdClient::new("localhost:3310").connect().unwrap();
+    /// let (jobs, queue) = mpsc::channel::<Vec<u8>>();
+    /// # drop(jobs);
+    /// loop {
+    ///     match queue.recv_timeout(Duration::from_secs(10)) {
+    ///         Ok(data) => println!("{:?}", conn.scan(&mut data.as_slice())),
+    ///         Err(mpsc::RecvTimeoutError::Timeout) => conn.keepalive().unwrap(),
+    ///         Err(mpsc::RecvTimeoutError::Disconnected) => break,
+    ///     }
+    /// }
+    /// ```
+    pub fn keepalive(&mut self) -> Result<(), ClamAVClientError> {
+        let reply = self.command(PING_REQUEST)?;
+        if reply != "PONG\0" {
+            self.broken = true;
+            return Err(unable_to_parse(&reply));
+        }
+        Ok(())
+    }
+
+    /// Checks that the connection can take the next command, eg. before a pooled connection is
+    /// handed out again, and with `ping` that ClamAV still answers on it.
+    ///
+    /// A reset is never needed between commands: once a command's reply has been read, the
+    /// connection is ready for the next one. The ids ClamAV numbers replies with keep counting
+    /// for the whole session, so the client's count of them is kept in step rather than cleared.
+    /// A reset can't repair a [broken](ClamdConnection::is_broken) connection, which fails with
+    /// [ClamAVClientError::ConnectionClosed] and must be discarded. Without `ping`, nothing is
+    /// sent, so a session that ClamAV ended after its `IdleTimeout` is only noticed with `ping`.
+    ///
+    /// ```rust
+    /// use clamav_tcp::ClamdClient;
+    /// let mut conn = ClamdClient::new("localhost:3310").connect().unwrap();
+    /// conn.scan(&mut "first".as_bytes()).unwrap();
+    /// conn.reset(true).unwrap();
+    /// conn.scan(&mut "second".as_bytes()).unwrap();
+    /// ```
+    pub fn reset(&mut self, ping: bool) -> Result<(), ClamAVClientError> {
+        self.check_usable()?;
+        if ping {
+            self.keepalive()?;
+        }
+        Ok(())
+    }
+
+    /// Checks ClamAV version. See [version](crate::version).
+    pub fn version(&mut self) -> Result<String, ClamAVClientError> {
+        self.command(VERSION_REQUEST)
+    }
+
+    /// Scans something that is [Read] using the connection's chunk size. See [scan](crate::scan).
+    pub fn scan<D: Read>(&mut self, file: &mut D) -> Result<ScanResult, ClamAVClientError> {
+        self.check_usable()?;
+        let reply = write_instream(&mut self.transport, file, Some(self.chunk_size), |_| Ok(()))
+            .and_then(|_| {
+                self.last_id += 1;
+                self.read_reply()
+            });
+        let reply = self.break_on_error(reply)?;
+        parse_reply(&reply, self.strict_mode)
+    }
+
+    /// Scans several labeled items, pipelining the scans over the connection for throughput.
+    ///
+    /// Up to 32 scans are sent before their replies are read, and ClamAV may answer them in
+    /// any order. Results are matched to items by their session id and returned in the order the
+    /// items were given. A reply that is an error only fails its own item.
+    ///
+    /// If the connection fails, the results received so far are kept: the item being sent gets
+    /// the error, and every item still waiting for a reply or not yet sent gets
+    /// [ClamAVClientError::ConnectionClosed]. A reply with an id that no scan is waiting for, eg.
+    /// a second reply to the same scan, fails them with
+    /// [ClamAVClientError::UnableToParseResponse] instead, as it's unknown which scans the
+    /// replies after it answer. The connection can't be used after that.
+    ///
+    /// ```rust
+    /// use clamav_tcp::ClamdClient;
+    /// let mut conn = ClamdClient::new("localhost:3310").connect().unwrap();
+    /// let items = vec![
+    ///     ("Cargo.toml".to_string(), std::fs::File::open("Cargo.toml").unwrap()),
+    ///     ("eicar.txt".to_string(), std::fs::File::open("resources/eicar.txt").unwrap()),
+    /// ];
+    /// let results = conn.scan_all(items);
+    /// assert!(!results[0].1.as_ref().unwrap().is_infected);
+    /// assert!(results[1].1.as_ref().unwrap().is_infected);
+    /// ```
+    pub fn scan_all<D: Read>(
+        &mut self,
+        items: impl IntoIterator<Item = (String, D)>,
+    ) -> Vec<(String, Result<ScanResult, ClamAVClientError>)> {
+        let mut results: Vec<(String, Option<Result<ScanResult, ClamAVClientError>>)> = vec![];
+        // Index into results for every scan that is waiting for its reply.
+        let mut pending: HashMap<u64, usize> = HashMap::new();
+        let mut failed = self.broken;
+        // The reply that didn't answer a pending scan, if that is what failed the connection.
+        let mut stray_reply: Option<String> = None;
+
+        for (label, mut file) in items {
+            results.push((label, None));
+            if failed {
+                continue;
+            }
+
+            if pending.len() >= MAX_PIPELINED {
+                if let Err(e) = self.collect_reply(&mut pending, &mut results) {
+                    stray_reply = stray(&e);
+                    results.last_mut().unwrap().1 = Some(Err(e));
+                    failed = true;
+                    continue;
+                }
+            }
+
+            match write_instream(
+                &mut self.transport,
+                &mut file,
+                Some(self.chunk_size),
+                |_| Ok(()),
+            ) {
+                Ok(_) => {
+                    self.last_id += 1;
+                    pending.insert(self.last_id, results.len() - 1);
+                }
+                Err(e) => {
+                    results.last_mut().unwrap().1 = Some(Err(e));
+                    failed = true;
+                }
+            }
+        }
+
+        while !failed && !pending.is_empty() {
+            if let Err(e) = self.collect_reply(&mut pending, &mut results) {
+                stray_reply = stray(&e);
+                failed = true;
+            }
+        }
+        self.broken = failed;
+
+        results
+            .into_iter()
+            .map(|(label, res)| {
+                let unanswered = || match &stray_reply {
+                    Some(reply) => ClamAVClientError::UnableToParseResponse(reply.clone()),
+                    None => ClamAVClientError::ConnectionClosed,
+                };
+                (label, res.unwrap_or_else(|| Err(unanswered())))
+            })
+            .collect()
+    }
+
+    /// Ends the session, reporting an error if ClamAV can't be told.
+    ///
+    /// A broken connection is only dropped, and fails with [ClamAVClientError::ConnectionClosed].
+    pub fn close(mut self) -> Result<(), ClamAVClientError> {
+        self.closed = true;
+        self.check_usable()?;
+        trace_sent(END_REQUEST);
+        self.transport
+            .write_all(END_REQUEST)
+            .and_then(|_| self.transport.flush())
+            .map_err(ClamAVClientError::UnableToWriteToStream)
+    }
+
+    /// Sends `command` and reads its reply, breaking the connection if either fails.
+    fn command(&mut self, command: &[Byte]) -> Result<String, ClamAVClientError> {
+        self.check_usable()?;
+        let reply = self.send(command).and_then(|_| self.read_reply());
+        self.break_on_error(reply)
+    }
+
+    fn check_usable(&self) -> Result<(), ClamAVClientError> {
+        if self.broken {
+            return Err(ClamAVClientError::ConnectionClosed);
+        }
+        Ok(())
+    }
+
+    /// Marks the connection as broken if `res`, the outcome of talking to ClamAV, is an error.
+    fn break_on_error<R>(
+        &mut self,
+        res: Result<R, ClamAVClientError>,
+    ) -> Result<R, ClamAVClientError> {
+        if res.is_err() {
+            self.broken = true;
+        }
+        res
+    }
+
+    fn send(&mut self, command: &[Byte]) -> Result<(), ClamAVClientError> {
+        trace_sent(command);
+        self.transport
+            .write_all(command)
+            .map_err(ClamAVClientError::UnableToWriteToStream)?;
+        self.last_id += 1;
+        Ok(())
+    }
+
+    /// Reads the reply to the last command, without the id ClamAV prefixes it with.
+    fn read_reply(&mut self) -> Result<String, ClamAVClientError> {
+        let (id, reply) = self.read_session_reply()?;
+        if id != self.last_id {
+            return Err(unable_to_parse(&format!("{id}: {reply}")));
+        }
+        Ok(reply)
+    }
+
+    /// Reads the next reply and stores its result for the pending scan it answers.
+    ///
+    /// Fails if the connection fails or the reply doesn't answer a pending scan.
+    fn collect_reply(
+        &mut self,
+        pending: &mut HashMap<u64, usize>,
+        results: &mut [(String, Option<Result<ScanResult, ClamAVClientError>>)],
+    ) -> Result<(), ClamAVClientError> {
+        let (id, reply) = self.read_session_reply()?;
+        let index = pending
+            .remove(&id)
+            .ok_or_else(|| unable_to_parse(&format!("{id}: {reply}")))?;
+        results[index].1 = Some(parse_reply(&reply, self.strict_mode));
+        Ok(())
+    }
+
+    /// Reads the next reply, split into the id ClamAV prefixed it with and the reply itself.
+    fn read_session_reply(&mut self) -> Result<(u64, String), ClamAVClientError> {
+        let reply = read_buffered_reply(&mut self.transport, &mut self.unread)?;
+        if reply.is_empty() {
+            return Err(ClamAVClientError::ConnectionClosed);
+        }
+
+        let (id, rest) = reply
+            .split_once(": ")
+            .ok_or_else(|| unable_to_parse(&reply))?;
+        let id = id.parse::<u64>().map_err(|_| unable_to_parse(&reply))?;
+        Ok((id, rest.to_string()))
+    }
+}
+
+/// The reply in `e` if it failed [ClamdConnection::scan_all] by not answering a pending scan.
+fn stray(e: &ClamAVClientError) -> Option<String> {
+    match e {
+        ClamAVClientError::UnableToParseResponse(reply) => Some(reply.clone()),
+        _ => None,
+    }
+}
+
+impl<T: ClamdTransport> Drop for ClamdConnection<T> {
+    fn drop(&mut self) {
+        // A broken connection may be in the middle of a stream, where END would be taken for data.
+        if !self.closed && !self.broken {
+            // Dropping closes the connection anyway, so ClamAV ends the session either way.
+            let _ = self.transport.write_all(END_REQUEST);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    #[test]
+    fn strips_session_ids() {
+        let transport = MockTransport::new(
+            b"1: PONG\x002: ClamAV 1.0.0/26734/Mon Nov 28 08:17:05 2022\x003: stream: Eicar FOUND\0",
+        );
+        let mut conn = ClamdConnection::from_transport(transport, 4096).unwrap();
+
+        assert_eq!(conn.ping().unwrap(), "PONG\0");
+        assert_eq!(
+            conn.version().unwrap(),
+            "ClamAV 1.0.0/26734/Mon Nov 28 08:17:05 2022\0"
+        );
+        let res = conn.scan(&mut "eicar".as_bytes()).unwrap();
+        assert_eq!(res.detected_infections, vec!["Eicar"]);
+    }
+
+    #[test]
+    fn rejects_reply_to_another_command() {
+        let transport = MockTransport::new(b"2: PONG\0");
+        let mut conn = ClamdConnection::from_transport(transport, 4096).unwrap();
+        assert!(matches!(
+            conn.ping(),
+            Err(ClamAVClientError::UnableToParseResponse(_))
+        ));
+    }
+
+    #[test]
+    fn reports_closed_connection() {
+        let transport = MockTransport::new(b"");
+        let mut conn = ClamdConnection::from_transport(transport, 4096).unwrap();
+        assert!(matches!(
+            conn.ping(),
+            Err(ClamAVClientError::ConnectionClosed)
+        ));
+    }
+
+    #[test]
+    fn breaks_connection_that_failed_mid_scan() {
+        // Fails writing after the session was started and the scan began, like a reset connection.
+        let transport =
+            MockTransport::closing_after(b"1: stream: OK\0", IDSESSION_REQUEST.len() + 4);
+        let mut conn = ClamdConnection::from_transport(transport, 4).unwrap();
+        assert!(!conn.is_broken());
+
+        let err = conn.scan(&mut "not a virus".as_bytes()).unwrap_err();
+        assert!(matches!(err, ClamAVClientError::UnableToWriteToStream(_)));
+        assert!(conn.is_broken());
+
+        // The unread reply must not be taken for the answer to the next command.
+        let written = conn.transport.written.len();
+        assert!(matches!(
+            conn.ping(),
+            Err(ClamAVClientError::ConnectionClosed)
+        ));
+        let results = conn.scan_all([("a".to_string(), "a".as_bytes())]);
+        assert!(matches!(
+            results[0].1,
+            Err(ClamAVClientError::ConnectionClosed)
+        ));
+        assert_eq!(conn.transport.written.len(), written);
+        assert!(matches!(
+            conn.close(),
+            Err(ClamAVClientError::ConnectionClosed)
+        ));
+    }
+
+    #[test]
+    fn error_replies_keep_the_connection_usable() {
+        let transport = MockTransport::new(b"1: stream: Can't allocate memory ERROR\x002: PONG\0");
+        let mut conn = ClamdConnection::from_transport(transport, 4096).unwrap();
+        assert!(matches!(
+            conn.scan(&mut "data".as_bytes()),
+            Err(ClamAVClientError::ClamdError(_))
+        ));
+        assert!(!conn.is_broken());
+        assert_eq!(conn.ping().unwrap(), "PONG\0");
+    }
+
+    #[test]
+    fn keepalive_pings_within_the_session() {
+        let transport = MockTransport::new(b"1: PONG\x002: stream: OK\x003: UNKNOWN COMMAND\0");
+        let mut conn = ClamdConnection::from_transport(transport, 4096).unwrap();
+        conn.keepalive().unwrap();
+        assert_eq!(conn.transport.written, b"zIDSESSION\0zPING\0");
+        assert!(!conn.scan(&mut "data".as_bytes()).unwrap().is_infected);
+
+        assert!(matches!(
+            conn.keepalive(),
+            Err(ClamAVClientError::UnableToParseResponse(_))
+        ));
+        assert!(conn.is_broken());
+    }
+
+    #[test]
+    fn reset_reuses_the_session_across_scans() {
+        let transport = MockTransport::new(
+            b"1: stream: OK\x002: PONG\x003: stream: Win.Test.EICAR_HDB-1 FOUND\x004: stream: OK\x005: UNKNOWN COMMAND\0",
+        );
+        let mut conn = ClamdConnection::from_transport(transport, 4096).unwrap();
+        assert!(!conn.scan(&mut "clean".as_bytes()).unwrap().is_infected);
+        conn.reset(true).unwrap();
+        assert!(conn.scan(&mut "eicar".as_bytes()).unwrap().is_infected);
+        // Without a ping nothing is sent, and the ids keep counting.
+        conn.reset(false).unwrap();
+        assert!(!conn.scan(&mut "clean".as_bytes()).unwrap().is_infected);
+
+        assert!(conn.reset(true).is_err());
+        assert!(matches!(
+            conn.reset(false),
+            Err(ClamAVClientError::ConnectionClosed)
+        ));
+    }
+
+    #[test]
+    fn sends_session_commands() {
+        let transport = MockTransport::new(b"1: PONG\0");
+        let mut conn = ClamdConnection::from_transport(transport, 4096).unwrap();
+        conn.ping().unwrap();
+        assert_eq!(conn.transport.written, b"zIDSESSION\0zPING\0");
+    }
+
+    #[test]
+    fn scan_all_matches_out_of_order_replies() {
+        let transport = MockTransport::new(
+            b"2: stream: OK\x003: stream: Can't allocate memory ERROR\x001: stream: Eicar FOUND\0",
+        );
+        let mut conn = ClamdConnection::from_transport(transport, 4096).unwrap();
+        let items = ["a", "b", "c"].map(|l| (l.to_string(), l.as_bytes()));
+
+        let results = conn.scan_all(items);
+        let labels: Vec<&str> = results.iter().map(|(l, _)| l.as_str()).collect();
+        assert_eq!(labels, vec!["a", "b", "c"]);
+        assert!(results[0].1.as_ref().unwrap().is_infected);
+        assert!(!results[1].1.as_ref().unwrap().is_infected);
+        assert!(matches!(
+            results[2].1,
+            Err(ClamAVClientError::ClamdError(_))
+        ));
+    }
+
+    #[test]
+    fn scan_all_rejects_replies_to_unknown_or_answered_scans() {
+        for replies in [
+            &b"2: stream: OK\x007: stream: Eicar FOUND\x001: stream: OK\0"[..],
+            b"2: stream: OK\x002: stream: Eicar FOUND\x001: stream: OK\0",
+        ] {
+            let mut conn =
+                ClamdConnection::from_transport(MockTransport::new(replies), 4096).unwrap();
+            let items = ["a", "b", "c"].map(|l| (l.to_string(), l.as_bytes()));
+
+            let results = conn.scan_all(items);
+            assert!(!results[1].1.as_ref().unwrap().is_infected);
+            // The replies after the stray one are not trusted, so "a" gets no result either.
+            for i in [0, 2] {
+                assert!(
+                    matches!(
+                        results[i].1,
+                        Err(ClamAVClientError::UnableToParseResponse(_))
+                    ),
+                    "{:?}",
+                    results[i]
+                );
+            }
+            assert!(conn.is_broken());
+        }
+    }
+
+    #[test]
+    fn scan_all_keeps_partial_results_when_connection_fails() {
+        let transport = MockTransport::new(b"1: stream: OK\0");
+        let mut conn = ClamdConnection::from_transport(transport, 4096).unwrap();
+        let items = ["a", "b"].map(|l| (l.to_string(), l.as_bytes()));
+
+        let results = conn.scan_all(items);
+        assert!(!results[0].1.as_ref().unwrap().is_infected);
+        assert!(matches!(
+            results[1].1,
+            Err(ClamAVClientError::ConnectionClosed)
+        ));
+    }
+
+    #[test]
+    fn scan_all_pipelines_many_items_live() {
+        let mut conn =
+            ClamdConnection::from_transport(TcpStream::connect("localhost:3310").unwrap(), 4096)
+                .unwrap();
+        let eicar = std::fs::read("resources/eicar.txt").unwrap();
+        let items = (0..100).map(|i| {
+            let data: &[u8] = if i % 10 == 0 { &eicar } else { b"clean" };
+            (i.to_string(), data)
+        });
+
+        let results = conn.scan_all(items);
+        assert_eq!(results.len(), 100);
+        for (i, (label, res)) in results.iter().enumerate() {
+            assert_eq!(label, &i.to_string());
+            assert_eq!(res.as_ref().unwrap().is_infected, i % 10 == 0);
+        }
+    }
+
+    #[test]
+    fn can_reuse_live_connection() {
+        let stream = TcpStream::connect("localhost:3310").unwrap();
+        let mut conn = ClamdConnection::from_transport(stream, 4096).unwrap();
+        assert_eq!(conn.ping().unwrap(), "PONG\0");
+        let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+        assert!(conn.scan(&mut eicar).unwrap().is_infected);
+        assert!(!conn.scan(&mut "clean".as_bytes()).unwrap().is_infected);
+        conn.close().unwrap();
+    }
+}
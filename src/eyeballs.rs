@@ -0,0 +1,159 @@
+use std::{
+    io::{Error, ErrorKind},
+    iter,
+    net::{SocketAddr, TcpStream},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use crate::{resolve, ClamAVClientError, ClamdAddress};
+
+/// Connects to the addresses of `address` like the client does by default, but races them
+/// instead of trying them one after the other, see
+/// [ClamdClientBuilder::happy_eyeballs](crate::ClamdClientBuilder::happy_eyeballs).
+///
+/// The addresses are tried alternating between IPv6 and IPv4, starting with the family of the
+/// first one. Every attempt runs on its own thread, and the next one starts after
+/// `attempt_delay` or as soon as the previous one fails. The first connection made is returned.
+/// Attempts that haven't started by then never do, and connections that are made later are
+/// closed as soon as they are.
+pub(crate) fn connect_racing(
+    address: &ClamdAddress,
+    attempt_delay: Duration,
+    timeout: Option<Duration>,
+) -> Result<TcpStream, ClamAVClientError> {
+    let mut addrs = interleave(resolve(address)?).into_iter().peekable();
+    let (results, finished) = mpsc::channel();
+    let mut running = 0;
+    let mut last_err = Error::new(ErrorKind::InvalidInput, "no addresses to connect to");
+    loop {
+        if let Some(addr) = addrs.next() {
+            let results = results.clone();
+            thread::spawn(move || {
+                // Fails once a connection has been picked, which drops and closes this one.
+                let _ = results.send(connect_one(addr, timeout));
+            });
+            running += 1;
+        } else if running == 0 {
+            return Err(ClamAVClientError::UnableToConnect(last_err));
+        }
+
+        let res = if addrs.peek().is_some() {
+            finished.recv_timeout(attempt_delay)
+        } else {
+            finished.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        };
+        match res {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(e)) => {
+                running -= 1;
+                last_err = e;
+            }
+            // `results` is still held here, so the channel is never disconnected.
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => {}
+        }
+    }
+}
+
+fn connect_one(addr: SocketAddr, timeout: Option<Duration>) -> std::io::Result<TcpStream> {
+    let Some(timeout) = timeout else {
+        return TcpStream::connect(addr);
+    };
+    let stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    Ok(stream)
+}
+
+/// Orders `addrs` alternating between address families, starting with the family of the first
+/// address and keeping the order within each family.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_is_ipv6 = addrs.first().is_some_and(SocketAddr::is_ipv6);
+    let (first, other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_is_ipv6);
+    let (mut first, mut other) = (first.into_iter(), other.into_iter());
+    iter::from_fn(|| match (first.next(), other.next()) {
+        (None, None) => None,
+        (a, b) => Some(a.into_iter().chain(b)),
+    })
+    .flatten()
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        time::Instant,
+    };
+
+    use super::*;
+    use crate::ClamdClient;
+
+    #[test]
+    fn interleaves_address_families() {
+        let v4 = |port| SocketAddr::from(([127, 0, 0, 1], port));
+        let v6 = |port| SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], port));
+        assert_eq!(
+            interleave(vec![v6(1), v6(2), v6(3), v4(4), v4(5)]),
+            vec![v6(1), v4(4), v6(2), v4(5), v6(3)]
+        );
+        assert_eq!(
+            interleave(vec![v4(1), v6(2), v4(3)]),
+            vec![v4(1), v6(2), v4(3)]
+        );
+        assert_eq!(interleave(vec![]), vec![]);
+    }
+
+    #[test]
+    fn falls_through_to_the_next_address_without_waiting_out_the_delay() {
+        // A port that was free a moment ago refuses the connection.
+        let refused = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addrs = vec![refused, listener.local_addr().unwrap()];
+
+        let started = Instant::now();
+        let stream =
+            connect_racing(&ClamdAddress::from(addrs), Duration::from_secs(30), None).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(stream.peer_addr().unwrap(), listener.local_addr().unwrap());
+    }
+
+    #[test]
+    fn reports_the_last_failure_when_no_address_accepts() {
+        let refused = |_| {
+            TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+        };
+        let addrs: Vec<SocketAddr> = (0..3).map(refused).collect();
+        let err = connect_racing(&ClamdAddress::from(addrs), Duration::from_millis(50), None)
+            .unwrap_err();
+        assert_eq!(err.io_error_kind(), Some(ErrorKind::ConnectionRefused));
+    }
+
+    #[test]
+    fn client_connects_through_the_race() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let _ = conn.read(&mut [0; 16]);
+            conn.write_all(b"PONG\0").unwrap();
+        });
+
+        let client = ClamdClient::builder(vec![addr])
+            .happy_eyeballs(Duration::from_millis(250))
+            .build()
+            .unwrap();
+        assert_eq!(client.ping().unwrap(), "PONG\0");
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    io::{IoSlice, Write},
+    mem::MaybeUninit,
+    os::{
+        fd::{AsFd, BorrowedFd, RawFd},
+        unix::net::UnixStream,
+    },
+    path::Path,
+};
+
+use rustix::net::{sendmsg, SendAncillaryBuffer, SendAncillaryMessage, SendFlags};
+
+use crate::{
+    multiscan::flatten,
+    transport::{read_to_close, trace_sent, DEFAULT_REPLY_BUFFER_SIZE},
+    Byte, ClamAVClientError, MultiScanResult, ScanResult,
+};
+const FILDES_REQUEST: &[Byte] = b"zFILDES\0";
+
+/// Scans an open file by passing its descriptor to ClamAV with FILDES, so ClamAV reads the file
+/// itself and nothing is streamed. Requires the `fildes` feature.
+///
+/// FILDES only works over ClamAV's local Unix socket, `socket` is its path (`LocalSocket` in
+/// `clamd.conf`). The descriptor is only borrowed: it stays open and owned by the caller, and
+/// ClamAV reads from the file's current offset. Check for FILDES with
+/// [CommandSet::has_fildes](crate::CommandSet::has_fildes) first.
+///
+/// ```no_run
+/// let eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+/// let res = clamav_tcp::scan_fd("/run/clamav/clamd.ctl", &eicar).unwrap();
+/// assert!(res.is_infected);
+/// ```
+pub fn scan_fd(socket: impl AsRef<Path>, fd: impl AsFd) -> Result<ScanResult, ClamAVClientError> {
+    let mut stream = UnixStream::connect(socket).map_err(ClamAVClientError::UnableToConnect)?;
+    scan_fd_on(&mut stream, fd.as_fd())
+}
+
+/// Scans a raw file descriptor like [scan_fd], for callers that manage their own descriptors
+/// (eg. from `accept` or `memfd_create`) and have no [File](std::fs::File) or other owner to
+/// borrow from. The descriptor isn't closed.
+///
+/// # Safety
+///
+/// `fd` must be an open file descriptor, and must stay open and not be reused until this
+/// returns. The caller keeps ownership of it.
+///
+/// ```no_run
+/// use std::os::fd::AsRawFd;
+/// let eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+/// let res = unsafe { clamav_tcp::scan_raw_fd("/run/clamav/clamd.ctl", eicar.as_raw_fd()) };
+/// assert!(res.unwrap().is_infected);
+/// ```
+#[allow(unsafe_code)]
+pub unsafe fn scan_raw_fd(
+    socket: impl AsRef<Path>,
+    fd: RawFd,
+) -> Result<ScanResult, ClamAVClientError> {
+    // SAFETY: the caller guarantees that `fd` stays open for the duration of the call.
+    scan_fd(socket, unsafe { BorrowedFd::borrow_raw(fd) })
+}
+
+/// Sends FILDES with `fd` over `stream` and reads the reply until ClamAV closes the connection.
+fn scan_fd_on(
+    stream: &mut UnixStream,
+    fd: BorrowedFd<'_>,
+) -> Result<ScanResult, ClamAVClientError> {
+    trace_sent(FILDES_REQUEST);
+    stream
+        .write_all(FILDES_REQUEST)
+        .map_err(ClamAVClientError::UnableToWriteToStream)?;
+
+    // The descriptor is sent as ancillary data, which needs at least one byte of regular data.
+    let fds = [fd];
+    let mut space = [MaybeUninit::uninit(); rustix::cmsg_space!(ScmRights(1))];
+    let mut control = SendAncillaryBuffer::new(&mut space);
+    control.push(SendAncillaryMessage::ScmRights(&fds));
+    sendmsg(
+        &*stream,
+        &[IoSlice::new(&[0])],
+        &mut control,
+        SendFlags::empty(),
+    )
+    .map_err(|e| ClamAVClientError::UnableToWriteToStream(e.into()))?;
+
+    let resp =
+        read_to_close(stream, DEFAULT_REPLY_BUFFER_SIZE).map_err(ClamAVClientError::InvalidUTf8)?;
+    flatten(resp.parse::<MultiScanResult>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        io::{IoSliceMut, Read, Seek},
+        net::Shutdown,
+        os::fd::OwnedFd,
+        thread,
+    };
+
+    use rustix::net::{recvmsg, RecvAncillaryBuffer, RecvAncillaryMessage, RecvFlags};
+
+    use super::*;
+
+    /// Receives the descriptor passed with FILDES and reads the file through it.
+    fn receive_file(conn: &mut UnixStream) -> String {
+        let mut command = [0; FILDES_REQUEST.len()];
+        conn.read_exact(&mut command).unwrap();
+        assert_eq!(command, FILDES_REQUEST);
+
+        let mut space = [MaybeUninit::uninit(); rustix::cmsg_space!(ScmRights(1))];
+        let mut control = RecvAncillaryBuffer::new(&mut space);
+        let mut byte = [0; 1];
+        recvmsg(
+            &*conn,
+            &mut [IoSliceMut::new(&mut byte)],
+            &mut control,
+            RecvFlags::empty(),
+        )
+        .unwrap();
+        let fd: OwnedFd = control
+            .drain()
+            .find_map(|msg| match msg {
+                RecvAncillaryMessage::ScmRights(mut fds) => fds.next(),
+                _ => None,
+            })
+            .unwrap();
+
+        let mut data = String::new();
+        File::from(fd).read_to_string(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn passes_the_descriptor() {
+        let (mut client, mut server) = UnixStream::pair().unwrap();
+        let clamd = thread::spawn(move || {
+            let data = receive_file(&mut server);
+            server
+                .write_all(b"fd[7]: Win.Test.EICAR_HDB-1 FOUND\0")
+                .unwrap();
+            server.shutdown(Shutdown::Write).unwrap();
+            data
+        });
+
+        let mut eicar = File::open("resources/eicar.txt").unwrap();
+        let res = scan_fd_on(&mut client, eicar.as_fd()).unwrap();
+        assert_eq!(res.detected_infections, vec!["Win.Test.EICAR_HDB-1"]);
+        assert_eq!(
+            clamd.join().unwrap(),
+            std::fs::read_to_string("resources/eicar.txt").unwrap()
+        );
+
+        // The descriptor is still ours.
+        eicar.rewind().unwrap();
+        assert!(eicar.read(&mut [0; 1]).is_ok());
+    }
+
+    #[test]
+    fn reports_a_missing_socket() {
+        let eicar = File::open("resources/eicar.txt").unwrap();
+        let err = scan_fd("/this/path/does/not/exist", &eicar).unwrap_err();
+        assert!(matches!(err, ClamAVClientError::UnableToConnect(_)));
+    }
+}
@@ -1,27 +1,96 @@
-#![forbid(unsafe_code)]
+// Memory-mapping a file and borrowing a raw file descriptor are unsafe, so the `memmap` and
+// `fildes` features allow it in the `mmap` and `fildes` modules only.
+#![cfg_attr(not(any(feature = "memmap", feature = "fildes")), forbid(unsafe_code))]
+#![cfg_attr(any(feature = "memmap", feature = "fildes"), deny(unsafe_code))]
 use std::{
-    io::Error,
+    io::{Error, ErrorKind},
     net::{SocketAddr, TcpStream, ToSocketAddrs},
+    time::Duration,
 };
+pub mod address;
+pub mod batch;
+pub mod cancel;
+pub mod client;
+pub mod connection;
+pub(crate) mod eyeballs;
+#[cfg(all(unix, feature = "fildes"))]
+pub mod fildes;
+pub mod metrics;
+#[cfg(feature = "memmap")]
+pub mod mmap;
+pub mod multiscan;
 pub mod ping;
+pub mod probe;
+pub mod protocol;
+pub mod quarantine;
 pub mod responses;
+pub mod retry;
 pub mod scan;
+#[cfg(feature = "socket2")]
+pub mod sockopt;
+#[cfg(feature = "socks")]
+pub mod socks;
+pub mod stats;
+pub mod target;
+pub mod transport;
+pub mod upload;
 pub mod version;
-pub use ping::ping;
-pub use responses::ScanResult;
-pub use scan::scan;
+pub mod writer;
+pub use address::ClamdAddress;
+pub use batch::scan_many_concurrent;
+pub use cancel::{scan_cancelable, CancelableScan, ScanHandle};
+pub use client::{ClamdClient, ClamdClientBuilder, Commands, ScanOpts};
+pub use connection::ClamdConnection;
+#[cfg(all(unix, feature = "fildes"))]
+pub use fildes::{scan_fd, scan_raw_fd};
+pub use metrics::ScanMetrics;
+#[cfg(feature = "memmap")]
+pub use mmap::scan_mmap;
+pub use multiscan::{contscan, multiscan, scan_path};
+pub use ping::{ping, ping_latency};
+pub use probe::{probe, self_test, ProbeReport};
+pub use quarantine::scan_file_quarantine;
+pub use responses::{
+    is_heuristic, is_unofficial, ClamdStats, CommandSet, FileScanResult, MultiScanResult,
+    QueueStats, ScanResult, ScanResultWith, Severity, ThreadStats, VersionInfo,
+};
+pub use retry::{scan_within, RetryPolicy};
+#[cfg(feature = "flate2")]
+pub use scan::scan_gzip;
+#[cfg(feature = "digest")]
+pub use scan::scan_hashed;
+pub use scan::{
+    scan, scan_chunks, scan_classified, scan_labeled, scan_matching, scan_on, scan_report,
+    scan_requiring_fresh_db, scan_simple, scan_snapshot, scan_tee, scan_throttled,
+    scan_with_parse_status, scan_with_stats, ScanReport, ScanStats,
+};
+#[cfg(feature = "socket2")]
+pub use sockopt::SocketOptions;
+#[cfg(feature = "socks")]
+pub use socks::Socks5Proxy;
+pub use stats::stats;
+pub use target::ScanTarget;
 use thiserror::Error;
-pub use version::version;
+pub use transport::ClamdTransport;
+pub use upload::{scan_upload, UploadRejection};
+pub use version::{commands, version, version_parsed, wait_until_ready};
+pub use writer::ScanWriter;
 
 pub type Byte = u8;
 
+/// Maximum number of bytes of a raw ClamAV response that is kept in an error.
+const MAX_RESPONSE_IN_ERROR: usize = 256;
+
 #[derive(Error, Debug)]
 pub enum ClamAVClientError {
     #[error("unable to connect to clamav")]
-    /// If unable to establish a [TcpStream] with the ClamAV instance.
+    /// If unable to establish a [TcpStream] with the ClamAV instance, eg. because it is down.
+    ///
+    /// The OS error is kept, see [ClamAVClientError::io_error_kind].
     UnableToConnect(#[from] Error), //- test
     #[error("invalid socket address")]
-    /// If the socket address passed to [scan] or [ping] is invalid.
+    /// If the socket address passed to [scan] or [ping] is invalid or its host name can't be
+    /// resolved.
     ///
     /// eg.
     /// ```
@@ -43,14 +112,401 @@ pub enum ClamAVClientError {
     #[error("unable to write to the stream")]
     /// Unable to write to the [TcpStream].
     UnableToWriteToStream(Error),
+    #[error("unable to read the input to scan")]
+    /// When the input to scan can't be read, eg. the reader passed to [scan] fails or a path
+    /// passed to it can't be opened. Unlike a failed write to the stream, it never means that
+    /// ClamAV closed the connection, even if the input is itself a socket.
+    UnableToReadInput(Error),
+    #[error("unable to write to the sink")]
+    /// Unable to write to the sink passed to [scan_tee].
+    UnableToWriteToSink(Error),
+    #[error("clamav returned an error: {0}")]
+    /// When ClamAV replies with an error instead of a scan result, eg. when it can't access a path.
+    ClamdError(String),
+    #[error("the scanned stream exceeds the size limit of clamav")]
+    /// When the streamed item is larger than ClamAV's `StreamMaxLength`.
+    SizeLimitExceeded,
+    #[error("clamav closed the connection without replying")]
+    /// When a session connection was closed before ClamAV replied, or is used after it broke.
+    ConnectionClosed,
+    #[error("the clamav signature database is older than allowed, it was built {0:?}")]
+    /// When the signature database ClamAV reports is older than the maximum age passed to
+    /// [scan_requiring_fresh_db], with the build date ClamAV reported. A date that is missing
+    /// (`None`) or can't be read is treated as too old.
+    StaleDatabase(Option<String>),
+    #[error("clamav closed the connection with an empty reply")]
+    /// When the connection is closed without a single byte of reply to [scan], [ping] or
+    /// [version]. ClamAV always replies, so this usually means the address is not ClamAV, eg. a
+    /// wrong port that accepts connections and closes them at once.
+    EmptyResponse,
+    #[error("clamav is not ready to serve requests, it replied {0:?}")]
+    /// When ClamAV accepts the connection but drops it or replies to [ping] or [version] with
+    /// something unexpected, as it does while it is still starting up and loading signatures.
+    NotReady(String),
+    #[error("unable to decompress the input")]
+    /// When the compressed input passed to `scan_gzip` is corrupt or truncated.
+    InvalidCompressedInput(Error),
+    #[error("clamav does not support the {0} command")]
+    /// When a feature needs a command the ClamAV host doesn't list in its [CommandSet].
+    UnsupportedCommand(String),
+    #[error("the socks5 proxy failed to connect to clamav: {0}")]
+    /// When the SOCKS5 proxy set with `ClamdClientBuilder::socks5_proxy` refuses the handshake or
+    /// can't connect to ClamAV.
+    ProxyError(String),
+    #[error("the scan did not finish within its time budget")]
+    /// When [scan_within] runs out of its total time budget, or [wait_until_ready] of its
+    /// `max_wait`.
+    Timeout,
+    #[error("the scan was cancelled")]
+    /// When a scan started with [scan_cancelable] was cancelled through its [ScanHandle].
+    Cancelled,
+    #[error("invalid client configuration: {0}")]
+    /// When a [ClamdClientBuilder] was given settings that can't be used.
+    InvalidConfiguration(String),
+    #[error("the file is infected but could not be quarantined")]
+    /// When [scan_file_quarantine] found an infection but couldn't delete or move the file.
+    QuarantineFailed {
+        /// The result of the scan, with the detections.
+        result: ScanResult,
+        /// Why the file couldn't be deleted or moved.
+        source: Error,
+    },
+    #[error("{label}: {source}")]
+    /// An error that happened while scanning an item with [scan_labeled].
+    Labeled {
+        /// The label passed to [scan_labeled].
+        label: String,
+        /// The error that happened during the scan.
+        source: Box<ClamAVClientError>,
+    },
 }
 
-fn connect_tcp_socket(addr: impl ToSocketAddrs) -> Result<TcpStream, ClamAVClientError> {
-    let addr: Vec<SocketAddr> = addr
+impl ClamAVClientError {
+    /// If the error is transient, so that retrying the same operation may succeed.
+    ///
+    /// Failing to connect, a connection that ClamAV closed, a read or write of the connection that
+    /// timed out and a ClamAV that is still starting up are retryable. Invalid addresses,
+    /// unparseable responses, errors ClamAV replied with and errors of the caller's own input or
+    /// sink are not, and neither is [Timeout](ClamAVClientError::Timeout), as the whole time
+    /// budget it reports is already spent.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ClamAVClientError::UnableToConnect(_)
+            | ClamAVClientError::ConnectionClosed
+            | ClamAVClientError::EmptyResponse
+            | ClamAVClientError::NotReady(_) => true,
+            ClamAVClientError::InvalidUTf8(e) | ClamAVClientError::UnableToWriteToStream(e) => {
+                is_timeout(e)
+            }
+            ClamAVClientError::InvalidSocketAddress(_)
+            | ClamAVClientError::UnableToParseResponse(_)
+            | ClamAVClientError::UnableToReadInput(_)
+            | ClamAVClientError::UnableToWriteToSink(_)
+            | ClamAVClientError::InvalidCompressedInput(_)
+            | ClamAVClientError::ClamdError(_)
+            | ClamAVClientError::Cancelled
+            | ClamAVClientError::Timeout
+            | ClamAVClientError::ProxyError(_)
+            | ClamAVClientError::UnsupportedCommand(_)
+            | ClamAVClientError::StaleDatabase(_)
+            | ClamAVClientError::SizeLimitExceeded
+            | ClamAVClientError::InvalidConfiguration(_)
+            | ClamAVClientError::QuarantineFailed { .. } => false,
+            ClamAVClientError::Labeled { source, .. } => source.is_retryable(),
+        }
+    }
+    /// The kind of the I/O error behind this error, if there is one.
+    ///
+    /// Tells apart why connecting failed, eg. [ErrorKind::ConnectionRefused] when nothing listens
+    /// at the address. [Labeled](ClamAVClientError::Labeled) errors report the kind of their source.
+    ///
+    /// ```
+    /// use std::io::ErrorKind;
+    /// use clamav_tcp;
+    /// let err = clamav_tcp::ping("127.0.0.1:1").unwrap_err();
+    /// assert_eq!(err.io_error_kind(), Some(ErrorKind::ConnectionRefused));
+    /// ```
+    pub fn io_error_kind(&self) -> Option<ErrorKind> {
+        match self {
+            ClamAVClientError::UnableToConnect(e)
+            | ClamAVClientError::InvalidSocketAddress(e)
+            | ClamAVClientError::InvalidUTf8(e)
+            | ClamAVClientError::UnableToWriteToStream(e)
+            | ClamAVClientError::UnableToReadInput(e)
+            | ClamAVClientError::UnableToWriteToSink(e)
+            | ClamAVClientError::InvalidCompressedInput(e)
+            | ClamAVClientError::QuarantineFailed { source: e, .. } => Some(e.kind()),
+            ClamAVClientError::Labeled { source, .. } => source.io_error_kind(),
+            ClamAVClientError::UnableToParseResponse(_)
+            | ClamAVClientError::ClamdError(_)
+            | ClamAVClientError::SizeLimitExceeded
+            | ClamAVClientError::ConnectionClosed
+            | ClamAVClientError::EmptyResponse
+            | ClamAVClientError::NotReady(_)
+            | ClamAVClientError::UnsupportedCommand(_)
+            | ClamAVClientError::StaleDatabase(_)
+            | ClamAVClientError::ProxyError(_)
+            | ClamAVClientError::Timeout
+            | ClamAVClientError::Cancelled
+            | ClamAVClientError::InvalidConfiguration(_) => None,
+        }
+    }
+}
+
+/// If `e` was caused by a socket read or write timing out.
+fn is_timeout(e: &Error) -> bool {
+    matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock)
+}
+
+/// If `e` means the peer dropped the connection, eg. a ClamAV that is still starting up.
+pub(crate) fn is_dropped_connection(e: &Error) -> bool {
+    matches!(
+        e.kind(),
+        ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe
+    )
+}
+
+/// Resolves `addr` into the addresses to try connecting to.
+///
+/// A host name that doesn't resolve to any address is an invalid address, so it isn't mistaken
+/// for a ClamAV that is down. Unspecified addresses such as `0.0.0.0` are rejected. They are
+/// meant for binding, and connecting to one silently reaches the local host on some platforms
+/// but fails on others.
+pub(crate) fn resolve(addr: impl ToSocketAddrs) -> Result<Vec<SocketAddr>, ClamAVClientError> {
+    let addrs: Vec<SocketAddr> = addr
         .to_socket_addrs()
         .map_err(ClamAVClientError::InvalidSocketAddress)?
         .collect();
 
+    if addrs.is_empty() {
+        return Err(ClamAVClientError::InvalidSocketAddress(Error::new(
+            ErrorKind::InvalidInput,
+            "the address did not resolve to any socket address",
+        )));
+    }
+
+    if let Some(unspecified) = addrs.iter().find(|a| a.ip().is_unspecified()) {
+        return Err(ClamAVClientError::InvalidSocketAddress(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "{unspecified} is an unspecified address that can only be bound to; \
+                 connect to the address of the ClamAV host instead, eg. 127.0.0.1:{}",
+                unspecified.port()
+            ),
+        )));
+    }
+    Ok(addrs)
+}
+
+fn connect_tcp_socket(addr: impl ToSocketAddrs) -> Result<TcpStream, ClamAVClientError> {
+    let addr = resolve(addr)?;
+
     let stream = TcpStream::connect(&addr[0..]).map_err(ClamAVClientError::UnableToConnect)?;
     Ok(stream)
 }
+
+/// Connects like [connect_tcp_socket], giving up on the connect and on every later read and
+/// write after `timeout`.
+pub(crate) fn connect_tcp_socket_timeout(
+    addr: impl ToSocketAddrs,
+    timeout: Duration,
+) -> Result<TcpStream, ClamAVClientError> {
+    let mut last_err = Error::new(ErrorKind::InvalidInput, "no addresses to connect to");
+    for addr in resolve(addr)? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                stream
+                    .set_read_timeout(Some(timeout))
+                    .and_then(|_| stream.set_write_timeout(Some(timeout)))
+                    .map_err(ClamAVClientError::UnableToConnect)?;
+                return Ok(stream);
+            }
+            Err(e) => last_err = e,
+        }
+    }
+    Err(ClamAVClientError::UnableToConnect(last_err))
+}
+
+/// Truncates `s` to at most `max_len` bytes without splitting a multi-byte character.
+pub(crate) fn truncate_on_char_boundary(s: &str, max_len: usize) -> &str {
+    if s.len() <= max_len {
+        return s;
+    }
+
+    let mut end = max_len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// Builds a [ClamAVClientError::ClamdError] from an error reply, capping its length.
+pub(crate) fn clamd_error(resp: &str) -> ClamAVClientError {
+    ClamAVClientError::ClamdError(
+        truncate_on_char_boundary(resp, MAX_RESPONSE_IN_ERROR).to_string(),
+    )
+}
+
+/// Builds a [ClamAVClientError::NotReady] from a raw response, capping its length.
+pub(crate) fn not_ready(resp: &str) -> ClamAVClientError {
+    ClamAVClientError::NotReady(truncate_on_char_boundary(resp, MAX_RESPONSE_IN_ERROR).to_string())
+}
+
+/// Builds a [ClamAVClientError::UnableToParseResponse] from a raw response, capping its length.
+pub(crate) fn unable_to_parse(resp: &str) -> ClamAVClientError {
+    ClamAVClientError::UnableToParseResponse(
+        truncate_on_char_boundary(resp, MAX_RESPONSE_IN_ERROR).to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn _assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn public_types_are_send_and_sync() {
+        _assert_send_sync::<ClamdClient>();
+        _assert_send_sync::<ClamdClientBuilder>();
+        _assert_send_sync::<ClamAVClientError>();
+        _assert_send_sync::<ScanResult>();
+        _assert_send_sync::<MultiScanResult>();
+        _assert_send_sync::<VersionInfo>();
+        _assert_send_sync::<ScanHandle>();
+        _assert_send_sync::<CancelableScan>();
+        _assert_send_sync::<ClamdConnection>();
+        _assert_send_sync::<ScanMetrics>();
+        _assert_send_sync::<ClamdAddress>();
+        _assert_send_sync::<ScanWriter>();
+    }
+
+    #[test]
+    fn truncation_keeps_short_strings() {
+        assert_eq!(truncate_on_char_boundary("stream: OK", 64), "stream: OK");
+        assert_eq!(truncate_on_char_boundary("", 0), "");
+    }
+
+    #[test]
+    fn truncation_does_not_split_multibyte_chars() {
+        // "ä" is two bytes, so a limit of 2 falls in the middle of it.
+        let s = "aä";
+        assert_eq!(truncate_on_char_boundary(s, 2), "a");
+        assert_eq!(truncate_on_char_boundary(s, 3), "aä");
+
+        // "🦀" is four bytes.
+        let s = "Sig.🦀 FOUND";
+        for max_len in 4..8 {
+            assert_eq!(truncate_on_char_boundary(s, max_len), "Sig.");
+        }
+        assert_eq!(truncate_on_char_boundary(s, 8), "Sig.🦀");
+    }
+
+    #[test]
+    fn parse_errors_cap_the_response() {
+        let resp = "ä".repeat(MAX_RESPONSE_IN_ERROR);
+        match unable_to_parse(&resp) {
+            ClamAVClientError::UnableToParseResponse(r) => {
+                assert_eq!(r.len(), MAX_RESPONSE_IN_ERROR);
+                assert!(resp.starts_with(&r));
+            }
+            e => panic!("unexpected error {e:?}"),
+        }
+    }
+
+    #[test]
+    fn rejects_unspecified_addresses() {
+        for addr in ["0.0.0.0:3310", "[::]:3310"] {
+            let err = ping(addr).unwrap_err();
+            match err {
+                ClamAVClientError::InvalidSocketAddress(e) => {
+                    assert!(e.to_string().contains("unspecified"), "{e}")
+                }
+                e => panic!("{addr}: {e:?}"),
+            }
+        }
+        assert!(resolve("127.0.0.1:3310").is_ok());
+    }
+
+    #[test]
+    fn resolution_failures_are_invalid_addresses() {
+        let empty: &[SocketAddr] = &[];
+        for err in [
+            ping("host.invalid:3310").unwrap_err(),
+            ping(empty).unwrap_err(),
+        ] {
+            assert!(
+                matches!(err, ClamAVClientError::InvalidSocketAddress(_)),
+                "{err:?}"
+            );
+            assert!(!err.is_retryable());
+        }
+    }
+
+    #[test]
+    fn refused_connections_keep_the_os_error() {
+        // Nothing listens on the port once the listener is dropped.
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let err = ping(addr).unwrap_err();
+        assert!(
+            matches!(err, ClamAVClientError::UnableToConnect(_)),
+            "{err:?}"
+        );
+        assert_eq!(err.io_error_kind(), Some(ErrorKind::ConnectionRefused));
+        assert!(err.is_retryable());
+
+        let labeled = ClamAVClientError::Labeled {
+            label: "upload.zip".to_string(),
+            source: Box::new(err),
+        };
+        assert_eq!(labeled.io_error_kind(), Some(ErrorKind::ConnectionRefused));
+        assert_eq!(ClamAVClientError::Timeout.io_error_kind(), None);
+    }
+
+    #[test]
+    fn connect_failures_and_timeouts_are_retryable() {
+        let refused = Error::from(ErrorKind::ConnectionRefused);
+        assert!(ClamAVClientError::UnableToConnect(refused).is_retryable());
+
+        let timed_out = Error::from(ErrorKind::TimedOut);
+        assert!(ClamAVClientError::InvalidUTf8(timed_out).is_retryable());
+
+        let would_block = Error::from(ErrorKind::WouldBlock);
+        assert!(ClamAVClientError::UnableToWriteToStream(would_block).is_retryable());
+    }
+
+    #[test]
+    fn parse_and_address_errors_are_not_retryable() {
+        assert!(!unable_to_parse("stream: ").is_retryable());
+
+        let invalid = Error::from(ErrorKind::InvalidInput);
+        assert!(!ClamAVClientError::InvalidSocketAddress(invalid).is_retryable());
+
+        let invalid_data = Error::from(ErrorKind::InvalidData);
+        assert!(!ClamAVClientError::InvalidUTf8(invalid_data).is_retryable());
+
+        let sink_timeout = Error::from(ErrorKind::TimedOut);
+        assert!(!ClamAVClientError::UnableToWriteToSink(sink_timeout).is_retryable());
+
+        let input_timeout = Error::from(ErrorKind::TimedOut);
+        assert!(!ClamAVClientError::UnableToReadInput(input_timeout).is_retryable());
+        assert!(!ClamAVClientError::Timeout.is_retryable());
+    }
+
+    #[test]
+    fn labeled_errors_keep_the_retry_policy_of_their_source() {
+        let refused = ClamAVClientError::UnableToConnect(Error::from(ErrorKind::ConnectionRefused));
+        let labeled = ClamAVClientError::Labeled {
+            label: "upload.zip".to_string(),
+            source: Box::new(refused),
+        };
+        assert!(labeled.is_retryable());
+        assert_eq!(
+            labeled.to_string(),
+            "upload.zip: unable to connect to clamav"
+        );
+    }
+}
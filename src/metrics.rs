@@ -0,0 +1,85 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{ClamAVClientError, ScanResult};
+
+/// Counters for the scans done by a [ClamdClient](crate::ClamdClient), safe to read from any thread.
+///
+/// Share one with a client through [ClamdClientBuilder::metrics](crate::ClamdClientBuilder::metrics)
+/// and read it from a dashboard or metrics exporter.
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use clamav_tcp::{ClamdClient, ScanMetrics};
+/// let metrics = Arc::new(ScanMetrics::default());
+/// let client = ClamdClient::builder("localhost:3310")
+///     .metrics(metrics.clone())
+///     .build()
+///     .unwrap();
+///
+/// let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+/// client.scan(&mut eicar).unwrap();
+/// assert_eq!(metrics.scans(), 1);
+/// assert_eq!(metrics.infected(), 1);
+/// ```
+#[derive(Debug, Default)]
+pub struct ScanMetrics {
+    scans: AtomicU64,
+    infected: AtomicU64,
+    bytes: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl ScanMetrics {
+    /// Number of scans, including failed ones.
+    pub fn scans(&self) -> u64 {
+        self.scans.load(Ordering::Relaxed)
+    }
+
+    /// Number of scans that found an infection.
+    pub fn infected(&self) -> u64 {
+        self.infected.load(Ordering::Relaxed)
+    }
+
+    /// Number of input bytes streamed to ClamAV. Paths scanned by ClamAV itself aren't counted.
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    /// Number of scans that failed with an error.
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// Counts a finished scan that streamed `bytes` to ClamAV.
+    pub(crate) fn record(&self, res: Result<&ScanResult, &ClamAVClientError>, bytes: u64) {
+        self.scans.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        match res {
+            Ok(res) if res.is_infected => {
+                self.infected.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(_) => {}
+            Err(_) => {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_scans() {
+        let metrics = ScanMetrics::default();
+        metrics.record("stream: OK\0".parse::<ScanResult>().as_ref(), 10);
+        metrics.record("stream: Eicar FOUND\0".parse::<ScanResult>().as_ref(), 68);
+        metrics.record(Err(&ClamAVClientError::ConnectionClosed), 5);
+
+        assert_eq!(metrics.scans(), 3);
+        assert_eq!(metrics.infected(), 1);
+        assert_eq!(metrics.errors(), 1);
+        assert_eq!(metrics.bytes(), 83);
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    fs::File,
+    io::{self, ErrorKind, IoSlice, Write},
+    net::ToSocketAddrs,
+    path::Path,
+};
+
+use memmap2::Mmap;
+
+use crate::{
+    connect_tcp_socket, is_dropped_connection,
+    protocol::{chunk_length_prefix, INSTREAM_FOOTER, INSTREAM_HEADER},
+    scan::{early_reply, parse_reply, read_scan_reply, DEFAULT_CHUNK_SIZE},
+    transport::trace_sent,
+    Byte, ClamAVClientError, ScanResult,
+};
+
+/// Scans the file at `path` by memory-mapping it and streaming the mapped pages to ClamAV, without
+/// copying them through a read buffer first. Meant for files of several gigabytes, for smaller
+/// files [scan](crate::scan) is as fast. Requires the `memmap` feature.
+///
+/// # Safety considerations
+///
+/// The file must not be modified while it is scanned. Data written to it by another process
+/// during the scan may or may not be seen by ClamAV, and if the file is truncated, reading the
+/// missing pages kills the process with `SIGBUS` on Unix. Only map files the process controls,
+/// eg. uploads in a private directory, and use [scan](crate::scan) for anything else.
+///
+/// Failing to open or map the file is reported like failing to read it, with
+/// [UnableToReadInput](ClamAVClientError::UnableToReadInput).
+///
+/// ```rust
+/// let res = clamav_tcp::scan_mmap("localhost:3310", "resources/eicar.txt", None).unwrap();
+/// assert_eq!(1, res.detected_infections.len());
+/// ```
+pub fn scan_mmap<A: ToSocketAddrs>(
+    addr: A,
+    path: impl AsRef<Path>,
+    chunk_size: Option<usize>,
+) -> Result<ScanResult, ClamAVClientError> {
+    let file = File::open(path).map_err(ClamAVClientError::UnableToReadInput)?;
+    // SAFETY: the mapping is only read while it is alive. Concurrent modification of the file is
+    // documented above as the caller's responsibility.
+    #[allow(unsafe_code)]
+    let map = unsafe { Mmap::map(&file) }.map_err(ClamAVClientError::UnableToReadInput)?;
+
+    let mut stream = connect_tcp_socket(addr)?;
+    // Every write is a whole frame, so there is nothing to gain from delaying small segments.
+    stream
+        .set_nodelay(true)
+        .map_err(ClamAVClientError::UnableToConnect)?;
+
+    let reply = match write_mapped(&mut stream, &map, chunk_size) {
+        Ok(()) => read_scan_reply(&mut stream)?,
+        Err(ClamAVClientError::UnableToWriteToStream(e)) if is_dropped_connection(&e) => {
+            early_reply(&mut stream).ok_or(ClamAVClientError::UnableToWriteToStream(e))?
+        }
+        Err(e) => return Err(e),
+    };
+    parse_reply(&reply, false)
+}
+
+/// Writes an INSTREAM request for `data`, one frame per write straight from `data`. The header
+/// goes out with the first frame and the footer with the last one.
+fn write_mapped<S: Write>(
+    stream: &mut S,
+    data: &[Byte],
+    chunk_size: Option<usize>,
+) -> Result<(), ClamAVClientError> {
+    let chunk_size = chunk_size
+        .filter(|&size| size > 0)
+        .unwrap_or(DEFAULT_CHUNK_SIZE)
+        .min(u32::MAX as usize);
+
+    trace_sent(INSTREAM_HEADER);
+    if data.is_empty() {
+        trace_sent(INSTREAM_FOOTER);
+        return write_all_vectored(
+            stream,
+            &mut [IoSlice::new(INSTREAM_HEADER), IoSlice::new(INSTREAM_FOOTER)],
+        )
+        .map_err(ClamAVClientError::UnableToWriteToStream);
+    }
+
+    let mut chunks = data.chunks(chunk_size).enumerate().peekable();
+    while let Some((i, chunk)) = chunks.next() {
+        let header = if i == 0 { INSTREAM_HEADER } else { &[] };
+        let footer = if chunks.peek().is_none() {
+            INSTREAM_FOOTER
+        } else {
+            &[]
+        };
+        let len = chunk_length_prefix(chunk.len() as u32);
+        trace_sent(&len);
+        write_all_vectored(
+            stream,
+            &mut [
+                IoSlice::new(header),
+                IoSlice::new(&len),
+                IoSlice::new(chunk),
+                IoSlice::new(footer),
+            ],
+        )
+        .map_err(ClamAVClientError::UnableToWriteToStream)?;
+    }
+    trace_sent(INSTREAM_FOOTER);
+    Ok(())
+}
+
+/// Writes all of `bufs`, like the unstable `Write::write_all_vectored`.
+fn write_all_vectored<S: Write>(stream: &mut S, mut bufs: &mut [IoSlice<'_>]) -> io::Result<()> {
+    IoSlice::advance_slices(&mut bufs, 0);
+    while !bufs.is_empty() {
+        match stream.write_vectored(bufs) {
+            Ok(0) => return Err(ErrorKind::WriteZero.into()),
+            Ok(written) => IoSlice::advance_slices(&mut bufs, written),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::write_instream;
+
+    #[test]
+    fn frames_match_instream() {
+        for len in [0, 1, 64, 200] {
+            let data = vec![b'a'; len];
+            let mut mapped = vec![];
+            write_mapped(&mut mapped, &data, Some(64)).unwrap();
+
+            let mut streamed = vec![];
+            write_instream(&mut streamed, &mut &data[..], Some(64), |_| Ok(())).unwrap();
+            assert_eq!(mapped, streamed, "{len}");
+        }
+    }
+
+    #[test]
+    fn survives_short_writes() {
+        /// Accepts at most 3 bytes per write.
+        struct Short(Vec<u8>);
+        impl Write for Short {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let len = buf.len().min(3);
+                self.0.extend_from_slice(&buf[..len]);
+                Ok(len)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut out = Short(vec![]);
+        write_mapped(&mut out, b"hello world", Some(4)).unwrap();
+        let mut expected = vec![];
+        write_instream(&mut expected, &mut &b"hello world"[..], Some(4), |_| Ok(())).unwrap();
+        assert_eq!(out.0, expected);
+    }
+
+    #[test]
+    fn scans_mapped_file() {
+        let res = scan_mmap("localhost:3310", "resources/eicar.txt", Some(16)).unwrap();
+        assert_eq!(res.detected_infections, vec!["Win.Test.EICAR_HDB-1"]);
+    }
+
+    #[test]
+    fn missing_file_is_a_read_error() {
+        let err = scan_mmap("localhost:3310", "/this/path/does/not/exist", None).unwrap_err();
+        assert!(matches!(err, ClamAVClientError::UnableToReadInput(_)));
+    }
+}
@@ -0,0 +1,131 @@
+use std::{
+    io::{Read, Write},
+    net::ToSocketAddrs,
+};
+
+use crate::{
+    clamd_error, connect_tcp_socket,
+    transport::{read_to_close, trace_sent, DEFAULT_REPLY_BUFFER_SIZE},
+    Byte, ClamAVClientError, MultiScanResult, ScanResult,
+};
+const SCAN_REQUEST: &[Byte] = b"zSCAN ";
+const MULTISCAN_REQUEST: &[Byte] = b"zMULTISCAN ";
+const CONTSCAN_REQUEST: &[Byte] = b"zCONTSCAN ";
+const TERMINATOR: &[Byte] = b"\0";
+
+/// Scans a file or directory on the ClamAV host, stopping at the first infection.
+///
+/// The path is resolved by ClamAV, so it must exist on the machine ClamAV runs on.
+/// Use [ClamdClientBuilder::fallback_to_stream](crate::ClamdClientBuilder::fallback_to_stream)
+/// to stream the file instead when ClamAV can't access it.
+///
+/// Nothing but the path is sent, and ClamAV unpacks archives itself, so for a ClamAV that shares
+/// a file system with the client, eg. over a mount, this saves streaming the file over a slow
+/// link. ClamAV doesn't support compressing a stream, so [scan](crate::scan) always sends the
+/// input as it is.
+///
+/// ```no_run
+/// use clamav_tcp;
+/// let res = clamav_tcp::scan_path("localhost:3310", "/var/uploads/upload.zip").unwrap();
+/// println!("{:?}", res.detected_infections);
+/// ```
+pub fn scan_path(addr: impl ToSocketAddrs, path: &str) -> Result<ScanResult, ClamAVClientError> {
+    scan_path_on(
+        &mut connect_tcp_socket(addr)?,
+        path,
+        DEFAULT_REPLY_BUFFER_SIZE,
+    )
+}
+
+/// Sends SCAN for `path` over a new connection, flattening the reply into a single result.
+pub(crate) fn scan_path_on<S: Read + Write>(
+    stream: &mut S,
+    path: &str,
+    reply_buffer_size: usize,
+) -> Result<ScanResult, ClamAVClientError> {
+    flatten(path_scan_on(stream, SCAN_REQUEST, path, reply_buffer_size)?)
+}
+
+/// Merges the detections of every file in `res` into a single result.
+///
+/// Without a detection, a file ClamAV couldn't scan fails the whole result with its error, so
+/// it doesn't pass as clean.
+pub(crate) fn flatten(res: MultiScanResult) -> Result<ScanResult, ClamAVClientError> {
+    if !res.is_infected() {
+        if let Some(failed) = res.failed_files().next() {
+            let error = failed.error.as_deref().unwrap_or_default();
+            return Err(clamd_error(&format!("{}: {error} ERROR", failed.path)));
+        }
+    }
+    let detected_infections: Vec<String> = res
+        .files
+        .into_iter()
+        .flat_map(|f| f.detected_infections)
+        .collect();
+    Ok(ScanResult::infected(detected_infections))
+}
+
+/// Scans a file or directory on the ClamAV host using multiple threads.
+///
+/// The path is resolved by ClamAV, so it must exist on the machine ClamAV runs on.
+///
+/// ```no_run
+/// use clamav_tcp;
+/// let res = clamav_tcp::multiscan("localhost:3310", "/var/uploads").unwrap();
+/// for file in res.infected_files() {
+///     println!("{}: {:?}", file.path, file.detected_infections);
+/// }
+/// ```
+pub fn multiscan(
+    addr: impl ToSocketAddrs,
+    path: &str,
+) -> Result<MultiScanResult, ClamAVClientError> {
+    path_scan(addr, MULTISCAN_REQUEST, path)
+}
+
+/// Scans a file or directory on the ClamAV host, continuing after the first infection is found.
+///
+/// The path is resolved by ClamAV, so it must exist on the machine ClamAV runs on.
+///
+/// ```no_run
+/// use clamav_tcp;
+/// let res = clamav_tcp::contscan("localhost:3310", "/var/uploads").unwrap();
+/// println!("{}", res.is_infected());
+/// ```
+pub fn contscan(
+    addr: impl ToSocketAddrs,
+    path: &str,
+) -> Result<MultiScanResult, ClamAVClientError> {
+    path_scan(addr, CONTSCAN_REQUEST, path)
+}
+
+fn path_scan(
+    addr: impl ToSocketAddrs,
+    command: &[Byte],
+    path: &str,
+) -> Result<MultiScanResult, ClamAVClientError> {
+    path_scan_on(
+        &mut connect_tcp_socket(addr)?,
+        command,
+        path,
+        DEFAULT_REPLY_BUFFER_SIZE,
+    )
+}
+
+/// Sends `command` for `path` and parses the reply, reading it `reply_buffer_size` bytes at a time.
+fn path_scan_on<S: Read + Write>(
+    stream: &mut S,
+    command: &[Byte],
+    path: &str,
+    reply_buffer_size: usize,
+) -> Result<MultiScanResult, ClamAVClientError> {
+    let request = [command, path.as_bytes(), TERMINATOR].concat();
+    trace_sent(&request);
+    stream
+        .write_all(&request)
+        .map_err(ClamAVClientError::UnableToWriteToStream)?;
+
+    let resp = read_to_close(stream, reply_buffer_size).map_err(ClamAVClientError::InvalidUTf8)?;
+
+    resp.parse::<MultiScanResult>()
+}
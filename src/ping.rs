@@ -1,38 +1,134 @@
 use std::{
     io::{Read, Write},
     net::ToSocketAddrs,
+    time::{Duration, Instant},
 };
 
-use crate::{connect_tcp_socket, Byte, ClamAVClientError};
-const PING_REQUEST: &[Byte] = b"zPING\0";
-const PING_RESPONSE: &[Byte] = b"zPONG\0";
-const PING_RESPONSE_CAPACITY: usize = PING_RESPONSE.len();
+use crate::{
+    connect_tcp_socket, is_dropped_connection, not_ready, transport::trace_sent, Byte,
+    ClamAVClientError,
+};
+pub(crate) const PING_REQUEST: &[Byte] = b"zPING\0";
+const PONG: &str = "PONG";
+const PING_RESPONSE_CAPACITY: usize = PONG.len() + 1;
 
 /// Checks if the ClamAV host is up.
 ///
+/// A host that accepts the connection but doesn't reply with `PONG`, eg. while it is still
+/// loading its signatures, fails with [NotReady](ClamAVClientError::NotReady). A connection closed
+/// without any reply fails with [EmptyResponse](ClamAVClientError::EmptyResponse).
+///
 /// ```rust
 /// use clamav_tcp;
 /// let resp = clamav_tcp::ping("localhost:3310").unwrap();
 /// assert_eq!(resp, "PONG\0");
 /// ```
 pub fn ping(addr: impl ToSocketAddrs) -> Result<String, ClamAVClientError> {
-    let mut stream = connect_tcp_socket(addr)?;
+    ping_on(&mut connect_tcp_socket(addr)?, PING_REQUEST)
+}
 
+/// Pings the ClamAV host and measures the round trip, from sending PING until the whole `PONG`
+/// reply has arrived. Connecting isn't counted.
+///
+/// Fails like [ping] if the reply isn't `PONG`.
+///
+/// ```rust
+/// use clamav_tcp;
+/// let latency = clamav_tcp::ping_latency("localhost:3310").unwrap();
+/// println!("{latency:?}"); // 112.4µs
+/// ```
+pub fn ping_latency(addr: impl ToSocketAddrs) -> Result<Duration, ClamAVClientError> {
+    ping_latency_on(&mut connect_tcp_socket(addr)?, PING_REQUEST)
+}
+
+/// Times [ping_on] over a new connection.
+pub(crate) fn ping_latency_on<S: Read + Write>(
+    stream: &mut S,
+    request: &[Byte],
+) -> Result<Duration, ClamAVClientError> {
+    let started = Instant::now();
+    ping_on(stream, request)?;
+    Ok(started.elapsed())
+}
+
+/// Sends PING over a new connection and reads the reply until ClamAV closes the connection.
+pub(crate) fn ping_on<S: Read + Write>(
+    stream: &mut S,
+    request: &[Byte],
+) -> Result<String, ClamAVClientError> {
+    trace_sent(request);
     stream
-        .write_all(PING_REQUEST)
+        .write_all(request)
         .map_err(ClamAVClientError::UnableToConnect)?;
 
     let mut resp = String::with_capacity(PING_RESPONSE_CAPACITY);
-    stream
-        .read_to_string(&mut resp)
-        .map_err(ClamAVClientError::InvalidUTf8)?;
+    stream.read_to_string(&mut resp).map_err(|e| {
+        // Dropping the connection without a reply is how a starting ClamAV turns us away.
+        if is_dropped_connection(&e) {
+            not_ready("")
+        } else {
+            ClamAVClientError::InvalidUTf8(e)
+        }
+    })?;
+    if resp.is_empty() {
+        return Err(ClamAVClientError::EmptyResponse);
+    }
 
+    // ClamAV terminates the reply like the command, with `\0` or `\n`.
+    let terminator = request.last().map_or('\0', |&b| char::from(b));
+    if resp.strip_suffix(terminator) != Some(PONG) {
+        return Err(not_ready(&resp));
+    }
     Ok(resp)
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
+    use std::{
+        io::Write,
+        net::{SocketAddr, TcpListener},
+        thread,
+    };
+
     use super::*;
+    use crate::transport::mock::MockTransport;
+
+    /// Starts a server that answers a single connection with `reply` and closes it.
+    pub(crate) fn reply_once(reply: &'static [u8]) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let _ = conn.write_all(reply);
+        });
+        addr
+    }
+
+    #[test]
+    fn ping_reports_a_starting_daemon_as_not_ready() {
+        for reply in [&b"UNKNOWN COMMAND\0"[..], b"PONG"] {
+            let err = ping(reply_once(reply)).unwrap_err();
+            assert!(matches!(err, ClamAVClientError::NotReady(_)), "{reply:?}");
+            assert!(err.is_retryable());
+        }
+    }
+
+    #[test]
+    fn ping_rejects_an_empty_reply() {
+        let mut conn = MockTransport::new(b"");
+        let err = ping_on(&mut conn, PING_REQUEST).unwrap_err();
+        assert!(matches!(err, ClamAVClientError::EmptyResponse));
+        assert!(err.is_retryable());
+    }
+
+    #[test]
+    fn measures_ping_latency() {
+        let latency = ping_latency("localhost:3310").unwrap();
+        assert!(latency < Duration::from_secs(5));
+
+        let err = ping_latency(reply_once(b"UNKNOWN COMMAND\0")).unwrap_err();
+        assert!(matches!(err, ClamAVClientError::NotReady(_)));
+    }
 
     #[test]
     fn ping_fails_with_invalid_addr() {
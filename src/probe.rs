@@ -0,0 +1,165 @@
+use std::{
+    io::{Read, Write},
+    net::ToSocketAddrs,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    connect_tcp_socket,
+    protocol::INSTREAM_HEADER,
+    scan::{exchange, parse_reply, DEFAULT_CHUNK_SIZE},
+    ClamAVClientError, ClamdConnection, ClamdTransport, VersionInfo,
+};
+
+/// The EICAR test file, which every ClamAV signature database detects.
+const EICAR: &[u8] = br"X5O!P%@AP[4\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+
+/// The outcome of a [probe].
+#[derive(Deserialize, Debug, Serialize, Clone, PartialEq, Eq, Default)]
+pub struct ProbeReport {
+    /// If ClamAV accepted the connection and answered PING.
+    #[serde(rename = "alive")]
+    pub alive: bool,
+    /// ClamAV's version, if it answered VERSION with one that could be parsed.
+    #[serde(rename = "version")]
+    pub version: Option<VersionInfo>,
+    /// If the EICAR test file was detected. Always `false` when the EICAR scan was skipped.
+    #[serde(rename = "eicar_detected")]
+    pub eicar_detected: bool,
+}
+
+/// Checks ClamAV's health more thoroughly than [ping](crate::ping), over a single session connection.
+///
+/// Pings ClamAV, reads its version and, if `scan_eicar` is set, scans the EICAR test file to check
+/// that detection works end to end. That catches a ClamAV that is up but has no or broken
+/// signatures. Nothing fails the probe itself: checks that can't be run are reported as failed.
+///
+/// ```rust
+/// use clamav_tcp;
+/// let report = clamav_tcp::probe("localhost:3310", true);
+/// assert!(report.alive);
+/// assert!(report.version.is_some());
+/// assert!(report.eicar_detected);
+/// ```
+pub fn probe(addr: impl ToSocketAddrs, scan_eicar: bool) -> ProbeReport {
+    match connect_tcp_socket(addr)
+        .and_then(|stream| ClamdConnection::from_transport(stream, DEFAULT_CHUNK_SIZE))
+    {
+        Ok(conn) => probe_over(conn, scan_eicar),
+        Err(_) => ProbeReport::default(),
+    }
+}
+
+/// Runs the [probe] checks over a session that was just started.
+pub(crate) fn probe_over<T: ClamdTransport>(
+    mut conn: ClamdConnection<T>,
+    scan_eicar: bool,
+) -> ProbeReport {
+    if !matches!(conn.ping().as_deref(), Ok("PONG\0")) {
+        return ProbeReport::default();
+    }
+
+    let version = conn.version().ok().and_then(|v| v.parse().ok());
+    let eicar_detected = scan_eicar && conn.scan(&mut &EICAR[..]).is_ok_and(|res| res.is_infected);
+    let _ = conn.close();
+    ProbeReport {
+        alive: true,
+        version,
+        eicar_detected,
+    }
+}
+
+/// Scans the EICAR test file to check that ClamAV has working signatures, not just that it is up.
+///
+/// Returns `Ok(true)` if ClamAV reports exactly one detection, and `Ok(false)` if it reports none
+/// or several. Meant to be run once at startup. Unlike [probe], errors while connecting or
+/// scanning are returned.
+///
+/// ```rust
+/// use clamav_tcp;
+/// assert!(clamav_tcp::self_test("localhost:3310").unwrap());
+/// ```
+pub fn self_test(addr: impl ToSocketAddrs) -> Result<bool, ClamAVClientError> {
+    self_test_on(&mut connect_tcp_socket(addr)?)
+}
+
+/// Scans EICAR over a new connection and checks for a single detection.
+pub(crate) fn self_test_on<S: Read + Write>(stream: &mut S) -> Result<bool, ClamAVClientError> {
+    let (reply, _) = exchange(stream, INSTREAM_HEADER, &mut &EICAR[..], None, |_| Ok(()))?;
+    let res = parse_reply(&reply, false)?;
+    Ok(res.detected_infections.len() == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    fn session(replies: &[u8]) -> ClamdConnection<MockTransport> {
+        ClamdConnection::from_transport(MockTransport::new(replies), DEFAULT_CHUNK_SIZE).unwrap()
+    }
+
+    #[test]
+    fn probes_a_healthy_clamd() {
+        let report = probe("localhost:3310", true);
+        assert!(report.alive);
+        assert!(report.version.is_some());
+        assert!(report.eicar_detected);
+    }
+
+    #[test]
+    fn eicar_scan_is_optional() {
+        let conn = session(b"1: PONG\x002: ClamAV 1.0.0/26734/Mon Nov 28 08:17:05 2022\0");
+        let report = probe_over(conn, false);
+        assert!(report.alive);
+        assert_eq!(report.version.unwrap().engine_semver(), Some((1, 0, 0)));
+        assert!(!report.eicar_detected);
+    }
+
+    #[test]
+    fn reports_missing_signatures() {
+        let conn = session(
+            b"1: PONG\x002: ClamAV 1.0.0/26734/Mon Nov 28 08:17:05 2022\x003: stream: OK\0",
+        );
+        let report = probe_over(conn, true);
+        assert!(report.alive);
+        assert!(!report.eicar_detected);
+    }
+
+    #[test]
+    fn self_test_detects_eicar() {
+        assert!(self_test("localhost:3310").unwrap());
+    }
+
+    #[test]
+    fn self_test_expects_a_single_detection() {
+        let mut conn = MockTransport::new(b"stream: Win.Test.EICAR_HDB-1 FOUND\0");
+        assert!(self_test_on(&mut conn).unwrap());
+        assert!(conn.written.windows(EICAR.len()).any(|w| w == EICAR));
+
+        for reply in [
+            &b"stream: OK\0"[..],
+            b"stream: Win.Test.EICAR_HDB-1 FOUND\nstream: Eicar-Signature FOUND\0",
+        ] {
+            assert!(
+                !self_test_on(&mut MockTransport::new(reply)).unwrap(),
+                "{reply:?}"
+            );
+        }
+        assert!(self_test_on(&mut MockTransport::new(b"")).is_err());
+    }
+
+    #[test]
+    fn reports_an_unreachable_clamd() {
+        // Nothing listens on the port of a listener that was just dropped.
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        assert_eq!(probe(addr, true), ProbeReport::default());
+
+        let report = probe_over(session(b"1: UNKNOWN COMMAND\0"), true);
+        assert!(!report.alive);
+    }
+}
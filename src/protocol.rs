@@ -0,0 +1,97 @@
+//! The parts of ClamAV's wire protocol this crate speaks.
+//!
+//! Every command is sent as [COMMAND_PREFIX], the command name and [COMMAND_TERMINATOR], eg.
+//! `zPING\0`. ClamAV answers with a reply ending in [REPLY_TERMINATOR] and, outside of a
+//! session, closes the connection. ClamAV also accepts commands framed with `n` and `\n`, eg.
+//! `nPING\n`, and then ends its replies with `\n`, see [Framing].
+//!
+//! A scan over INSTREAM goes like this:
+//!
+//! 1. The client sends [INSTREAM_HEADER].
+//! 2. The client sends the input as chunks. Every chunk is its length as a [CHUNK_LENGTH_SIZE]
+//!    byte unsigned integer in big-endian byte order, see [chunk_length_prefix], followed by
+//!    that many bytes of input.
+//! 3. The client sends [INSTREAM_FOOTER], a chunk of length zero, to end the input. A chunk of
+//!    length zero always ends the input, so an empty chunk must never be sent before it.
+//! 4. ClamAV replies with `stream: OK`, `stream: <signature> FOUND` or an error such as
+//!    `INSTREAM size limit exceeded. ERROR`, terminated by [REPLY_TERMINATOR]. If the input
+//!    exceeds ClamAV's `StreamMaxLength`, it replies and closes the connection without waiting
+//!    for the rest.
+//!
+//! In a session, started with `zIDSESSION\0` and ended with `zEND\0`, several commands are sent
+//! over one connection and ClamAV prefixes every reply with the number of the command it
+//! answers, counting from 1, eg. `1: PONG\0`.
+
+use crate::Byte;
+
+/// Starts every command, selecting `\0` terminated commands and replies.
+pub const COMMAND_PREFIX: Byte = b'z';
+/// Ends every command.
+pub const COMMAND_TERMINATOR: Byte = b'\0';
+/// Ends every reply, and every line of a reply that has several.
+pub const REPLY_TERMINATOR: Byte = b'\0';
+/// Starts a scan of streamed input.
+pub const INSTREAM_HEADER: &[Byte] = b"zINSTREAM\0";
+/// Ends the input of an INSTREAM scan: a chunk length of zero.
+pub const INSTREAM_FOOTER: &[Byte] = &[0; CHUNK_LENGTH_SIZE];
+/// Number of bytes of the length that precedes every INSTREAM chunk.
+pub const CHUNK_LENGTH_SIZE: usize = std::mem::size_of::<u32>();
+
+/// How commands and their replies are delimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// `z` commands and replies terminated by `\0`, eg. `zPING\0`. Used unless configured
+    /// otherwise.
+    #[default]
+    Null,
+    /// `n` commands and replies terminated by `\n`, eg. `nPING\n`.
+    Newline,
+}
+
+impl Framing {
+    /// The byte every command starts with.
+    pub fn prefix(self) -> Byte {
+        match self {
+            Framing::Null => COMMAND_PREFIX,
+            Framing::Newline => b'n',
+        }
+    }
+
+    /// The byte that ends every command and reply.
+    pub fn terminator(self) -> Byte {
+        match self {
+            Framing::Null => COMMAND_TERMINATOR,
+            Framing::Newline => b'\n',
+        }
+    }
+
+    /// Frames the command `name`, eg. `PING` as `zPING\0`.
+    pub fn command(self, name: &str) -> Vec<Byte> {
+        [&[self.prefix()], name.as_bytes(), &[self.terminator()]].concat()
+    }
+}
+
+/// The length that precedes an INSTREAM chunk of `len` bytes, in big-endian byte order.
+pub fn chunk_length_prefix(len: u32) -> [Byte; CHUNK_LENGTH_SIZE] {
+    len.to_be_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instream_framing() {
+        assert_eq!(INSTREAM_FOOTER, [0, 0, 0, 0]);
+        assert_eq!(INSTREAM_HEADER.first(), Some(&COMMAND_PREFIX));
+        assert_eq!(INSTREAM_HEADER.last(), Some(&COMMAND_TERMINATOR));
+        assert_eq!(chunk_length_prefix(0x0102_0304), [1, 2, 3, 4]);
+        assert_eq!(chunk_length_prefix(0), INSTREAM_FOOTER);
+    }
+
+    #[test]
+    fn frames_commands() {
+        assert_eq!(Framing::Null.command("INSTREAM"), INSTREAM_HEADER);
+        assert_eq!(Framing::Newline.command("PING"), b"nPING\n");
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind},
+    net::ToSocketAddrs,
+    path::Path,
+};
+
+use crate::{scan, ClamAVClientError, ScanResult};
+
+/// Scans a local file and, if it is infected, **deletes it** or moves it into `quarantine_dir`.
+///
+/// This is destructive: with `quarantine_dir` set to `None` an infected file is removed for good.
+/// With a quarantine directory the file is moved there under its own name. An existing file in
+/// the quarantine directory is never overwritten. Clean files are left untouched, and nothing is
+/// done to the file if the scan fails.
+///
+/// If the file is infected but can't be moved or deleted, the scan result is returned inside
+/// [ClamAVClientError::QuarantineFailed], so the detection isn't lost. The file is then still
+/// where it was, except when a move across file systems fails halfway, in which case the copy
+/// is removed again if possible.
+///
+/// ```no_run
+/// use std::path::Path;
+/// let res = clamav_tcp::scan_file_quarantine(
+///     "localhost:3310",
+///     "/var/uploads/upload.zip",
+///     Some(Path::new("/var/quarantine")),
+/// )
+/// .unwrap();
+/// if res.is_infected {
+///     println!("quarantined: {}", res.detections_display());
+/// }
+/// ```
+pub fn scan_file_quarantine(
+    addr: impl ToSocketAddrs,
+    path: impl AsRef<Path>,
+    quarantine_dir: Option<&Path>,
+) -> Result<ScanResult, ClamAVClientError> {
+    let path = path.as_ref();
+    let res = scan(addr, path, None)?;
+    quarantine_infected(res, path, quarantine_dir)
+}
+
+/// Deletes or moves `path` if `res` is infected, see [scan_file_quarantine].
+pub(crate) fn quarantine_infected(
+    res: ScanResult,
+    path: &Path,
+    quarantine_dir: Option<&Path>,
+) -> Result<ScanResult, ClamAVClientError> {
+    if !res.is_infected {
+        return Ok(res);
+    }
+
+    let done = match quarantine_dir {
+        Some(dir) => move_into(path, dir),
+        None => fs::remove_file(path),
+    };
+    match done {
+        Ok(()) => Ok(res),
+        Err(source) => Err(ClamAVClientError::QuarantineFailed {
+            result: res,
+            source,
+        }),
+    }
+}
+
+/// Moves `path` into `dir`, copying it if it is on another file system. Never overwrites a file.
+fn move_into(path: &Path, dir: &Path) -> io::Result<()> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "the path has no file name"))?;
+    let dest = dir.join(name);
+
+    // Unlike renaming, which replaces a file created after any check, linking fails if `dest`
+    // exists.
+    let moved = match fs::hard_link(path, &dest) {
+        Ok(()) => fs::remove_file(path).inspect_err(|_| {
+            // Don't leave the file in both places.
+            let _ = fs::remove_file(&dest);
+        }),
+        Err(e) if matches!(e.kind(), ErrorKind::CrossesDevices | ErrorKind::Unsupported) => {
+            copy_then_remove(path, &dest)
+        }
+        Err(e) => Err(e),
+    };
+    moved.map_err(|e| match e.kind() {
+        ErrorKind::AlreadyExists => io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} already exists", dest.display()),
+        ),
+        _ => e,
+    })
+}
+
+/// Copies `path` to `dest`, which must not exist yet, and removes `path`. A partial copy is
+/// removed again so the file doesn't end up in both places.
+fn copy_then_remove(path: &Path, dest: &Path) -> io::Result<()> {
+    let mut from = fs::File::open(path)?;
+    let mut to = OpenOptions::new().write(true).create_new(true).open(dest)?;
+    let copied = io::copy(&mut from, &mut to)
+        .and_then(|_| to.sync_all())
+        .and_then(|_| fs::remove_file(path));
+    if copied.is_err() {
+        let _ = fs::remove_file(dest);
+    }
+    copied
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// A fresh directory under the system temp dir, holding a file named `name`.
+    fn setup(test: &str, name: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("clamav-tcp-quarantine-{test}"));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("quarantine")).unwrap();
+        let file = dir.join(name);
+        fs::write(&file, "data").unwrap();
+        (dir, file)
+    }
+
+    fn infected() -> ScanResult {
+        "stream: Win.Test.EICAR_HDB-1 FOUND\0".parse().unwrap()
+    }
+
+    #[test]
+    fn leaves_clean_files_alone() {
+        let (dir, file) = setup("clean", "upload.txt");
+        let clean = "stream: OK\0".parse().unwrap();
+        let res = quarantine_infected(clean, &file, None).unwrap();
+        assert!(!res.is_infected);
+        assert!(file.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn deletes_infected_files() {
+        let (dir, file) = setup("delete", "upload.txt");
+        let res = quarantine_infected(infected(), &file, None).unwrap();
+        assert!(res.is_infected);
+        assert!(!file.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn moves_infected_files() {
+        let (dir, file) = setup("move", "upload.txt");
+        let quarantine = dir.join("quarantine");
+        quarantine_infected(infected(), &file, Some(&quarantine)).unwrap();
+        assert!(!file.exists());
+        assert_eq!(
+            fs::read_to_string(quarantine.join("upload.txt")).unwrap(),
+            "data"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn never_replaces_a_file_in_quarantine() {
+        let (dir, file) = setup("no-clobber", "upload.txt");
+        let quarantine = dir.join("quarantine");
+        fs::write(quarantine.join("upload.txt"), "older").unwrap();
+
+        let err = move_into(&file, &quarantine).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "data");
+        assert_eq!(
+            fs::read_to_string(quarantine.join("upload.txt")).unwrap(),
+            "older"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_moves_keep_the_result_and_the_file() {
+        let (dir, file) = setup("failed", "upload.txt");
+        let quarantine = dir.join("quarantine");
+        fs::write(quarantine.join("upload.txt"), "older").unwrap();
+
+        for quarantine in [quarantine.clone(), dir.join("missing")] {
+            let err = quarantine_infected(infected(), &file, Some(&quarantine)).unwrap_err();
+            match err {
+                ClamAVClientError::QuarantineFailed { result, .. } => {
+                    assert_eq!(result.detected_infections, vec!["Win.Test.EICAR_HDB-1"])
+                }
+                e => panic!("{quarantine:?}: {e:?}"),
+            }
+            assert!(file.exists());
+        }
+        assert_eq!(
+            fs::read_to_string(quarantine.join("upload.txt")).unwrap(),
+            "older"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn quarantines_eicar() {
+        let (dir, file) = setup("eicar", "eicar.txt");
+        fs::copy("resources/eicar.txt", &file).unwrap();
+        let quarantine = dir.join("quarantine");
+
+        let res = scan_file_quarantine("localhost:3310", &file, Some(&quarantine)).unwrap();
+        assert!(res.is_infected);
+        assert!(!file.exists());
+        assert!(quarantine.join("eicar.txt").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
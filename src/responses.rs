@@ -1,37 +1,1638 @@
 use serde::{Deserialize, Serialize};
 
-use crate::ClamAVClientError;
-use std::str::FromStr;
+use crate::{clamd_error, unable_to_parse, ClamAVClientError};
+use std::{
+    collections::{BTreeSet, HashMap},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+/// Marks the result for a streamed item in an INSTREAM reply.
+const STREAM_MARKER: &str = "stream:";
+/// Trailing tokens ClamAV follows a detected signature name with.
+const DETECTION_SUFFIXES: &[&str] = &["FOUND"];
+const SIZE_LIMIT_EXCEEDED: &str = "size limit exceeded. ERROR";
+const UNKNOWN_COMMAND: &str = "UNKNOWN COMMAND";
+/// Status ClamAV reports a clean scan with.
+const OK_STATUS: &str = "OK";
+/// Verdict token of a clean result, see [ScanResult::verdict_token].
+const CLEAN_VERDICT: &str = "clean";
+/// Prefix of the verdict token of an infected result.
+const INFECTED_VERDICT: &str = "infected:";
+/// Month abbreviations of the database date in a VERSION reply, in order.
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+/// Suffix ClamAV appends to the names of signatures from third-party databases.
+const UNOFFICIAL_SUFFIX: &str = ".UNOFFICIAL";
+/// Part of the names of ClamAV's heuristic detections, between dots.
+const HEURISTICS_PART: &str = "Heuristics";
 
 /// A struct that describes the result of the scan.
-#[derive(Deserialize, Debug, Serialize)]
+///
+/// The serialized field names are stable, so results can be emitted as JSON with any serde
+/// serializer, eg. `serde_json`:
+/// ```
+/// use clamav_tcp::ScanResult;
+/// let res: ScanResult = "stream: Win.Test.EICAR_HDB-1 FOUND\0".parse().unwrap();
+/// assert_eq!(
+///     serde_json::to_string(&res).unwrap(),
+///     r#"{"is_infected":true,"detected_infections":["Win.Test.EICAR_HDB-1"]}"#
+/// );
+/// ```
+///
+/// Results can be compared and hashed, eg. to use them as cache keys or collect them in a
+/// [HashSet](std::collections::HashSet). All fields count, including the order of the
+/// detections, which is the order ClamAV reported them in, and the label of the scanned item.
+/// To treat results with the same detections as equal, sort and deduplicate
+/// `detected_infections` and clear `scanned_item` first.
+#[derive(Deserialize, Debug, Serialize, PartialEq, Eq, Hash)]
 pub struct ScanResult {
     /// If a malicious file was found within the scanned item.
+    #[serde(rename = "is_infected")]
     pub is_infected: bool,
     /// Names of the detected infections.
+    #[serde(rename = "detected_infections")]
     pub detected_infections: Vec<String>,
+    /// The label of the scanned item, if it was scanned with [scan_labeled](crate::scan_labeled).
+    #[serde(
+        rename = "scanned_item",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub scanned_item: Option<String>,
+}
+
+impl ScanResult {
+    /// A clean result, with no detections. Meant for tests and mocks of the scanner.
+    ///
+    /// ```
+    /// use clamav_tcp::ScanResult;
+    /// let res = ScanResult::clean();
+    /// assert!(!res.is_infected);
+    /// assert!(res.detected_infections.is_empty());
+    /// ```
+    pub fn clean() -> Self {
+        ScanResult::infected(vec![])
+    }
+
+    /// A result with the detections in `names`. Meant for tests and mocks of the scanner.
+    ///
+    /// [ScanResult::is_infected] is set from `names`, so an empty list makes a clean result.
+    ///
+    /// ```
+    /// use clamav_tcp::ScanResult;
+    /// let res = ScanResult::infected(vec!["Win.Test.EICAR_HDB-1".to_string()]);
+    /// assert!(res.is_infected);
+    /// assert_eq!(res.detections_display(), "Win.Test.EICAR_HDB-1");
+    /// ```
+    pub fn infected(names: Vec<String>) -> Self {
+        ScanResult {
+            is_infected: !names.is_empty(),
+            detected_infections: names,
+            scanned_item: None,
+        }
+    }
+
+    /// Whether the scanned item is infected: `true` means something was detected. The same as
+    /// [ScanResult::is_infected], for terse checks.
+    ///
+    /// ```
+    /// use clamav_tcp::ScanResult;
+    /// let res: ScanResult = "stream: Win.Test.EICAR_HDB-1 FOUND\0".parse().unwrap();
+    /// assert!(res.as_bool()); // infected
+    /// assert!(!ScanResult::clean().as_bool()); // clean
+    /// ```
+    pub fn as_bool(&self) -> bool {
+        self.is_infected
+    }
+
+    /// Whether nothing was detected in the scanned item, the opposite of [ScanResult::as_bool].
+    ///
+    /// ```
+    /// use clamav_tcp::ScanResult;
+    /// let res: ScanResult = "stream: OK\0".parse().unwrap();
+    /// assert!(res.is_clean());
+    /// assert!(!res.as_bool());
+    /// ```
+    pub fn is_clean(&self) -> bool {
+        !self.is_infected
+    }
+
+    /// A one line summary of the detections for logging: `"clean"`, or the comma-joined detection names.
+    ///
+    /// ```
+    /// use clamav_tcp::ScanResult;
+    /// let res: ScanResult = "stream: OK\0".parse().unwrap();
+    /// assert_eq!(res.detections_display(), "clean");
+    ///
+    /// let res: ScanResult = "stream: Sig.One FOUND\0stream: Sig.Two FOUND\0".parse().unwrap();
+    /// assert_eq!(res.detections_display(), "Sig.One, Sig.Two");
+    /// ```
+    pub fn detections_display(&self) -> String {
+        if !self.is_infected {
+            return "clean".to_string();
+        }
+        self.detected_infections.join(", ")
+    }
+
+    /// A compact, stable token for the verdict, eg. to cache it keyed by a hash of the content:
+    /// `clean`, or `infected:` followed by the comma-separated detection names. The label of
+    /// the scanned item isn't part of it. Read it back with [ScanResult::from_verdict_token].
+    ///
+    /// ```
+    /// use clamav_tcp::ScanResult;
+    /// let res: ScanResult = "stream: Sig.One FOUND\0stream: Sig.Two FOUND\0".parse().unwrap();
+    /// assert_eq!(res.verdict_token(), "infected:Sig.One,Sig.Two");
+    /// assert_eq!(ScanResult::clean().verdict_token(), "clean");
+    /// ```
+    pub fn verdict_token(&self) -> String {
+        if !self.is_infected {
+            return CLEAN_VERDICT.to_string();
+        }
+        format!("{INFECTED_VERDICT}{}", self.detected_infections.join(","))
+    }
+
+    /// Reads a token made by [ScanResult::verdict_token] back into a result.
+    ///
+    /// ```
+    /// use clamav_tcp::ScanResult;
+    /// let res = ScanResult::from_verdict_token("infected:Sig.One,Sig.Two").unwrap();
+    /// assert_eq!(res.detected_infections, vec!["Sig.One", "Sig.Two"]);
+    /// assert!(ScanResult::from_verdict_token("infected:").is_err());
+    /// ```
+    pub fn from_verdict_token(token: &str) -> Result<Self, ClamAVClientError> {
+        if token == CLEAN_VERDICT {
+            return Ok(ScanResult::clean());
+        }
+        let names: Vec<String> = token
+            .strip_prefix(INFECTED_VERDICT)
+            .ok_or_else(|| unable_to_parse(token))?
+            .split(',')
+            .map(str::to_string)
+            .collect();
+        // An infected verdict always names its detections.
+        if names.iter().any(String::is_empty) {
+            return Err(unable_to_parse(token));
+        }
+        Ok(ScanResult::infected(names))
+    }
+
+    /// Whether any detection name contains one of `patterns`. Matching is case-sensitive, and
+    /// a clean result never matches.
+    ///
+    /// ```
+    /// use clamav_tcp::ScanResult;
+    /// let res: ScanResult = "stream: Win.Test.EICAR_HDB-1 FOUND\0".parse().unwrap();
+    /// assert!(res.matches_any(&["EICAR"]));
+    /// assert!(!res.matches_any(&["Ransom."]));
+    /// ```
+    pub fn matches_any(&self, patterns: &[&str]) -> bool {
+        self.detected_infections
+            .iter()
+            .any(|name| patterns.iter().any(|pattern| name.contains(pattern)))
+    }
+
+    /// The detection names if the result is infected, or `None` if it is clean.
+    ///
+    /// ```
+    /// use clamav_tcp::ScanResult;
+    /// let res: ScanResult = "stream: Win.Test.EICAR_HDB-1 FOUND\0".parse().unwrap();
+    /// assert_eq!(res.into_detections(), Some(vec!["Win.Test.EICAR_HDB-1".to_string()]));
+    /// assert_eq!(ScanResult::clean().into_detections(), None);
+    /// ```
+    pub fn into_detections(self) -> Option<Vec<String>> {
+        self.is_infected.then_some(self.detected_infections)
+    }
+
+    /// The highest [Severity] `policy` assigns to any of the detections, or [Severity::Clean]
+    /// if there are none.
+    ///
+    /// ```
+    /// use clamav_tcp::{ScanResult, Severity};
+    /// let res: ScanResult = "stream: Win.Test.EICAR_HDB-1 FOUND\0".parse().unwrap();
+    /// let severity = res.severity(&|name: &str| {
+    ///     if name.starts_with("Win.Test.") { Severity::Low } else { Severity::High }
+    /// });
+    /// assert_eq!(severity, Severity::Low);
+    /// ```
+    pub fn severity(&self, policy: &dyn Fn(&str) -> Severity) -> Severity {
+        self.detected_infections
+            .iter()
+            .map(|name| policy(name))
+            .max()
+            .unwrap_or(Severity::Clean)
+    }
+
+    /// Pairs the result with `meta`, eg. the id of the request that asked for the scan, so it can
+    /// be handed on without a side map from scans to their context.
+    ///
+    /// ```
+    /// use clamav_tcp::ScanResult;
+    /// let res: ScanResult = "stream: OK\0".parse().unwrap();
+    /// let tagged = res.with_meta("request-42");
+    /// assert_eq!(tagged.meta, "request-42");
+    /// assert!(tagged.result.is_clean());
+    /// ```
+    pub fn with_meta<T>(self, meta: T) -> ScanResultWith<T> {
+        ScanResultWith { result: self, meta }
+    }
+}
+
+/// A [ScanResult] together with data of the caller's, see [ScanResult::with_meta].
+///
+/// ```rust
+/// let uploads = [(7, &b"hello"[..]), (8, &b"world"[..])];
+/// let results: Vec<_> = uploads
+///     .into_iter()
+///     .map(|(id, body)| clamav_tcp::scan("localhost:3310", body, None).map(|r| r.with_meta(id)))
+///     .collect();
+/// for res in results {
+///     let res = res.unwrap();
+///     println!("upload {}: {}", res.meta, res.result.detections_display());
+/// }
+/// ```
+#[derive(Deserialize, Debug, Serialize, PartialEq, Eq, Hash)]
+pub struct ScanResultWith<T> {
+    /// The scan result.
+    #[serde(rename = "result")]
+    pub result: ScanResult,
+    /// The caller's data.
+    #[serde(rename = "meta")]
+    pub meta: T,
+}
+
+/// How serious a detection is, as decided by a caller's policy, see
+/// [scan_classified](crate::scan_classified). Severities are ordered from `Clean` to `High`.
+#[derive(Deserialize, Debug, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Nothing was detected, or the policy considers the detection harmless.
+    Clean,
+    /// Worth logging, eg. adware or potentially unwanted software.
+    Low,
+    /// Worth looking into.
+    Medium,
+    /// Needs action straight away.
+    High,
 }
 
 impl FromStr for ScanResult {
     type Err = ClamAVClientError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        // Take section after "stream: "
-        let stuff: Vec<&str> = s.split("stream: ").into_iter().skip(1).collect();
-        if stuff.clone().into_iter().any(|x| x.starts_with("OK")) {
-            return Ok(ScanResult {
-                is_infected: false,
-                detected_infections: vec![],
-            });
-        }
-
-        let detections = stuff
-            .into_iter()
-            .map(|e| e.to_string().replace(" FOUND\0", ""))
+        // Take the status after "stream:" on every line, whatever precedes it (eg. a session id).
+        let statuses: Vec<&str> = s
+            .split(['\0', '\n'])
+            .filter_map(|line| line.split_once(STREAM_MARKER))
+            .map(|(_, status)| status.trim())
+            // An empty status, eg. a stray marker at the end of an allmatch reply, says nothing.
+            .filter(|status| !status.is_empty())
+            .collect();
+        // Without a marker there is no result, which must not be mistaken for a clean scan.
+        if statuses.is_empty() {
+            let reply = s.trim_end_matches(['\0', '\n']).trim();
+            if reply.ends_with(SIZE_LIMIT_EXCEEDED) {
+                return Err(ClamAVClientError::SizeLimitExceeded);
+            }
+            if strip_suffix_ignore_case(reply, "ERROR").is_some() || reply == UNKNOWN_COMMAND {
+                return Err(clamd_error(reply));
+            }
+            return Err(unable_to_parse(s));
+        }
+        // A failed scan is neither clean nor infected.
+        if let Some(error) = statuses
+            .iter()
+            .find(|status| strip_suffix_ignore_case(status, "ERROR").is_some())
+        {
+            return Err(clamd_error(error));
+        }
+
+        // With allmatch every detection is on its own line, and the reply may end with an `OK`
+        // that isn't a detection.
+        let detections: Vec<String> = statuses
+            .iter()
+            .filter(|status| !is_ok(status))
+            .map(|status| detection_name(status))
+            // A bare "FOUND" names nothing, so it can't count as a detection.
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect();
+        // Without a detection the result is only clean if ClamAV said so.
+        if detections.is_empty() && !statuses.iter().any(|status| is_ok(status)) {
+            return Err(unable_to_parse(s));
+        }
+        Ok(ScanResult::infected(detections))
+    }
+}
+
+/// Whether the detection `name` comes from a third-party signature database rather than from
+/// ClamAV's official ones.
+///
+/// ClamAV doesn't report which database a signature is from, but it appends `.UNOFFICIAL` to
+/// the names of signatures that aren't in a database signed by ClamAV, eg. from a third-party
+/// feed or a local custom database.
+///
+/// ```
+/// use clamav_tcp::{is_unofficial, ScanResult};
+/// let res: ScanResult = "stream: Sanesecurity.Foxhole.JS_Zip_1.UNOFFICIAL FOUND\0".parse().unwrap();
+/// assert!(is_unofficial(&res.detected_infections[0]));
+/// assert!(!is_unofficial("Win.Test.EICAR_HDB-1"));
+/// ```
+pub fn is_unofficial(name: &str) -> bool {
+    name.ends_with(UNOFFICIAL_SUFFIX)
+}
+
+/// Whether the detection `name` is a heuristic one, eg. `Heuristics.Phishing.Email.SpoofedDomain`,
+/// rather than a match of a signature.
+///
+/// Heuristic detections are guesses from how the input looks, such as a mismatched link in an
+/// email or a broken executable, and are more often false positives, so callers may want to
+/// warn about them instead of blocking. ClamAV names them with a `Heuristics` part, usually at
+/// the start of the name.
+///
+/// ```
+/// use clamav_tcp::{is_heuristic, ScanResult};
+/// let res: ScanResult = "stream: Heuristics.Encrypted.PDF FOUND\0".parse().unwrap();
+/// assert!(is_heuristic(&res.detected_infections[0]));
+/// assert!(!is_heuristic("Win.Test.EICAR_HDB-1"));
+/// ```
+pub fn is_heuristic(name: &str) -> bool {
+    name.split('.').any(|part| part == HEURISTICS_PART)
+}
+
+/// Whether `status` reports a clean scan: `OK` as a whole token, possibly followed by a note, but
+/// not a signature whose name merely starts with "OK". Some ClamAV-compatible engines reply
+/// with `ok`, so the case is ignored.
+fn is_ok(status: &str) -> bool {
+    strip_prefix_ignore_case(status, OK_STATUS).is_some_and(|rest| {
+        rest.is_empty() || rest.starts_with(|c: char| c.is_whitespace() || c.is_control())
+    })
+}
+
+/// Takes the signature name out of a status such as `Win.Test.EICAR_HDB-1 FOUND\r`, or
+/// `Win.Test.EICAR_HDB-1 found` as some ClamAV-compatible engines report it.
+fn detection_name(status: &str) -> &str {
+    let status = status.trim_end_matches(|c: char| c.is_whitespace() || c.is_control());
+    DETECTION_SUFFIXES
+        .iter()
+        .find_map(|suffix| {
+            let name = strip_suffix_ignore_case(status, suffix)?;
+            // Only a whole token, a signature that happens to end in "FOUND" is kept intact.
+            (name.is_empty() || name.ends_with(char::is_whitespace)).then_some(name)
+        })
+        .unwrap_or(status)
+        .trim()
+}
+
+/// Strips `prefix` from the start of `s`, ignoring ASCII case.
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = s.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix)
+        .then(|| &s[prefix.len()..])
+}
+
+/// Strips `suffix` from the end of `s`, ignoring ASCII case.
+fn strip_suffix_ignore_case<'a>(s: &'a str, suffix: &str) -> Option<&'a str> {
+    let start = s.len().checked_sub(suffix.len())?;
+    let tail = s.get(start..)?;
+    tail.eq_ignore_ascii_case(suffix).then(|| &s[..start])
+}
+
+/// Checks that every line of an INSTREAM reply is terminated by `\0` and that nothing but
+/// replies follow it.
+pub(crate) fn check_terminated(s: &str) -> Result<(), ClamAVClientError> {
+    let lines = s.strip_suffix('\0').ok_or_else(|| unable_to_parse(s))?;
+    if lines.split('\0').all(|l| l.contains(STREAM_MARKER)) {
+        Ok(())
+    } else {
+        Err(unable_to_parse(s))
+    }
+}
+
+/// The result of scanning a single file on the ClamAV host.
+#[derive(Deserialize, Debug, Serialize)]
+pub struct FileScanResult {
+    /// Path of the scanned file as reported by ClamAV.
+    #[serde(rename = "path")]
+    pub path: String,
+    /// If a malicious file was found at [FileScanResult::path].
+    #[serde(rename = "is_infected")]
+    pub is_infected: bool,
+    /// Names of the infections detected in this file.
+    #[serde(rename = "detected_infections")]
+    pub detected_infections: Vec<String>,
+    /// The error ClamAV reported instead of a result if it couldn't scan this file, eg.
+    /// `Access denied.`
+    #[serde(rename = "error", default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A struct that describes the result of a [multiscan](crate::multiscan) or [contscan](crate::contscan).
+///
+/// ClamAV only reports a clean directory once, so a clean scan usually holds a
+/// single entry for the scanned path itself.
+///
+/// Files below the scanned path that ClamAV couldn't scan, eg. for lack of permission, are
+/// listed with their [FileScanResult::error], so a result that isn't infected is only clean if
+/// [MultiScanResult::failed_files] is empty. A reply that is nothing but an error, eg. for a
+/// scanned path that doesn't exist, fails the whole scan with [ClamAVClientError::ClamdError].
+#[derive(Deserialize, Debug, Serialize)]
+pub struct MultiScanResult {
+    /// Every path ClamAV reported on, in the order it reported them.
+    #[serde(rename = "files")]
+    pub files: Vec<FileScanResult>,
+}
+
+impl MultiScanResult {
+    /// If any of the scanned files was infected.
+    pub fn is_infected(&self) -> bool {
+        self.files.iter().any(|f| f.is_infected)
+    }
+
+    /// The files that were found to be infected.
+    pub fn infected_files(&self) -> impl Iterator<Item = &FileScanResult> {
+        self.files.iter().filter(|f| f.is_infected)
+    }
+
+    /// The files ClamAV couldn't scan, with the errors it reported for them.
+    pub fn failed_files(&self) -> impl Iterator<Item = &FileScanResult> {
+        self.files.iter().filter(|f| f.error.is_some())
+    }
+}
+
+impl FromStr for MultiScanResult {
+    type Err = ClamAVClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut files: Vec<FileScanResult> = vec![];
+
+        // Each reported file is on its own line, terminated by either "\0" or "\n".
+        let lines: Vec<&str> = s
+            .split(['\0', '\n'])
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
             .collect();
-        Ok(ScanResult {
-            is_infected: true,
-            detected_infections: detections,
+        // An error on its own is about the whole command, eg. a path ClamAV can't access.
+        if let [line] = lines[..] {
+            if strip_suffix_ignore_case(line, " ERROR").is_some() {
+                return Err(clamd_error(line));
+            }
+        }
+
+        for line in lines {
+            if let Some(rest) = strip_suffix_ignore_case(line, " ERROR") {
+                // Among other results, an error is about a single file below the scanned path.
+                let (path, error) = rest.split_once(": ").ok_or_else(|| clamd_error(line))?;
+                files.push(FileScanResult {
+                    path: path.to_string(),
+                    is_infected: false,
+                    detected_infections: vec![],
+                    error: Some(error.to_string()),
+                });
+                continue;
+            }
+
+            if let Some(path) = strip_suffix_ignore_case(line, ": OK") {
+                files.push(FileScanResult {
+                    path: path.to_string(),
+                    is_infected: false,
+                    detected_infections: vec![],
+                    error: None,
+                });
+                continue;
+            }
+
+            let (path, detection) = strip_suffix_ignore_case(line, " FOUND")
+                .and_then(|l| l.rsplit_once(": "))
+                .ok_or_else(|| unable_to_parse(line))?;
+
+            // With allmatch a single file can be reported several times.
+            match files.iter_mut().find(|f| f.path == path) {
+                Some(file) => file.detected_infections.push(detection.to_string()),
+                None => files.push(FileScanResult {
+                    path: path.to_string(),
+                    is_infected: true,
+                    detected_infections: vec![detection.to_string()],
+                    error: None,
+                }),
+            }
+        }
+
+        if files.is_empty() {
+            return Err(unable_to_parse(s));
+        }
+
+        Ok(MultiScanResult { files })
+    }
+}
+
+/// The version information ClamAV replies with to VERSION, see [version_parsed](crate::version_parsed).
+///
+/// A version string that was already fetched, eg. with [version](crate::version), can be parsed
+/// without asking ClamAV again:
+/// ```
+/// use clamav_tcp::VersionInfo;
+/// let info: VersionInfo = "ClamAV 1.0.0/26734/Mon Nov 28 08:17:05 2022\0".parse().unwrap();
+/// assert_eq!(info.engine_version, "1.0.0");
+/// assert_eq!(info.database_version, Some(26734));
+/// assert_eq!(info.database_date.as_deref(), Some("Mon Nov 28 08:17:05 2022"));
+/// ```
+#[derive(Deserialize, Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct VersionInfo {
+    /// Version of the ClamAV engine, eg. `1.0.0`.
+    pub engine_version: String,
+    /// Version of the signature database, if ClamAV reported one.
+    pub database_version: Option<u32>,
+    /// Build time of the signature database as reported by ClamAV, eg. `Mon Nov 28 08:17:05 2022`.
+    pub database_date: Option<String>,
+}
+
+impl FromStr for VersionInfo {
+    type Err = ClamAVClientError;
+
+    /// Parses a VERSION reply such as `ClamAV 1.0.0/26734/Mon Nov 28 08:17:05 2022\0`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let version = s
+            .trim_end_matches(['\0', '\n'])
+            .strip_prefix("ClamAV ")
+            .ok_or_else(|| unable_to_parse(s))?;
+
+        let mut parts = version.splitn(3, '/');
+        let engine_version = parts
+            .next()
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .ok_or_else(|| unable_to_parse(s))?;
+        let database_version = parts
+            .next()
+            .map(|v| v.trim().parse::<u32>().map_err(|_| unable_to_parse(s)))
+            .transpose()?;
+        let database_date = parts.next().map(|d| d.trim().to_string());
+
+        Ok(VersionInfo {
+            engine_version: engine_version.to_string(),
+            database_version,
+            database_date,
+        })
+    }
+}
+
+impl VersionInfo {
+    /// The engine version as `(major, minor, patch)`, for comparing against a minimum version.
+    ///
+    /// Suffixes such as `-beta` or `-rc` are ignored and a missing patch version is read as 0.
+    ///
+    /// ```
+    /// use clamav_tcp::VersionInfo;
+    /// # let info = VersionInfo { engine_version: "0.103.8".to_string(), database_version: None, database_date: None };
+    /// // info is eg. the result of clamav_tcp::version_parsed("localhost:3310")
+    /// assert!(info.engine_semver() >= Some((0, 103, 0)));
+    /// ```
+    pub fn engine_semver(&self) -> Option<(u32, u32, u32)> {
+        let version = self
+            .engine_version
+            .split(['-', '+', ' '])
+            .next()
+            .unwrap_or_default();
+
+        let mut numbers = version.split('.').map(|n| n.parse::<u32>().ok());
+        let major = numbers.next()??;
+        let minor = numbers.next()??;
+        let patch = numbers.next().unwrap_or(Some(0))?;
+        if numbers.next().is_some() {
+            return None;
+        }
+
+        Some((major, minor, patch))
+    }
+
+    /// The build time of the signature database, if ClamAV reported one in the format it uses,
+    /// eg. `Mon Nov 28 08:17:05 2022`.
+    ///
+    /// ClamAV reports the time without a time zone, so it is read as UTC and may be off by the
+    /// UTC offset of the ClamAV host.
+    ///
+    /// ```
+    /// use std::time::{Duration, UNIX_EPOCH};
+    /// use clamav_tcp::VersionInfo;
+    /// let info: VersionInfo = "ClamAV 1.0.0/26734/Mon Nov 28 08:17:05 2022\0".parse().unwrap();
+    /// assert_eq!(info.database_time(), Some(UNIX_EPOCH + Duration::from_secs(1_669_623_425)));
+    /// ```
+    pub fn database_time(&self) -> Option<SystemTime> {
+        parse_database_date(self.database_date.as_deref()?)
+    }
+}
+
+/// Parses a date such as `Mon Nov 28 08:17:05 2022`, or `Tue Nov  8 08:17:05 2022` with a
+/// single digit day, as UTC.
+fn parse_database_date(date: &str) -> Option<SystemTime> {
+    let mut fields = date.split_whitespace();
+    let _weekday = fields.next()?;
+    let month = fields.next()?;
+    let month = MONTHS.iter().position(|&m| m == month)? as i64 + 1;
+    let day: i64 = fields.next()?.parse().ok()?;
+    let mut time = fields.next()?.split(':').map(|n| n.parse::<i64>().ok());
+    let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+    let year: i64 = fields.next()?.parse().ok()?;
+    if fields.next().is_some() || time.next().is_some() {
+        return None;
+    }
+    if !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 60 || year < 1970 {
+        return None;
+    }
+
+    // Days since 1970-01-01 of the civil date, counting years from March so leap days come last.
+    let y = if month <= 2 { year - 1 } else { year };
+    let (era, year_of_era) = (y / 400, y % 400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    let secs = days * 86_400 + hours * 3600 + minutes * 60 + seconds;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+/// The commands a ClamAV host supports, as listed in its reply to VERSIONCOMMANDS.
+///
+/// ```
+/// use clamav_tcp::CommandSet;
+/// let reply = "ClamAV 1.0.0/26734/Mon Nov 28 08:17:05 2022| COMMANDS: SCAN PING INSTREAM IDSESSION\0";
+/// let commands: CommandSet = reply.parse().unwrap();
+/// assert!(commands.has_idsession());
+/// assert!(!commands.has_multiscan());
+/// ```
+#[derive(Deserialize, Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct CommandSet {
+    /// The supported command names, eg. `INSTREAM`.
+    #[serde(rename = "commands")]
+    pub commands: BTreeSet<String>,
+}
+
+impl CommandSet {
+    /// If the host supports `command`, eg. `"INSTREAM"`. Names are case-sensitive as in the protocol.
+    pub fn contains(&self, command: &str) -> bool {
+        self.commands.contains(command)
+    }
+
+    /// Fails with [ClamAVClientError::UnsupportedCommand] unless the host supports `command`.
+    pub fn require(&self, command: &str) -> Result<(), ClamAVClientError> {
+        if self.contains(command) {
+            Ok(())
+        } else {
+            Err(ClamAVClientError::UnsupportedCommand(command.to_string()))
+        }
+    }
+
+    /// If sessions with IDSESSION are supported, see [ClamdConnection](crate::ClamdConnection).
+    pub fn has_idsession(&self) -> bool {
+        self.contains("IDSESSION")
+    }
+
+    /// If streaming input with INSTREAM is supported.
+    pub fn has_instream(&self) -> bool {
+        self.contains("INSTREAM")
+    }
+
+    /// If MULTISCAN is supported, see [multiscan](crate::multiscan).
+    pub fn has_multiscan(&self) -> bool {
+        self.contains("MULTISCAN")
+    }
+
+    /// If CONTSCAN is supported, see [contscan](crate::contscan).
+    pub fn has_contscan(&self) -> bool {
+        self.contains("CONTSCAN")
+    }
+
+    /// If ALLMATCHSCAN is supported.
+    pub fn has_allmatchscan(&self) -> bool {
+        self.contains("ALLMATCHSCAN")
+    }
+
+    /// If passing file descriptors with FILDES is supported.
+    pub fn has_fildes(&self) -> bool {
+        self.contains("FILDES")
+    }
+
+    /// If STATS is supported.
+    pub fn has_stats(&self) -> bool {
+        self.contains("STATS")
+    }
+}
+
+impl FromStr for CommandSet {
+    type Err = ClamAVClientError;
+
+    /// Parses a VERSIONCOMMANDS reply such as `ClamAV 1.0.0/26734/...| COMMANDS: SCAN QUIT ...\0`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (_, commands) = s
+            .trim_end_matches(['\0', '\n'])
+            .split_once("COMMANDS:")
+            .ok_or_else(|| unable_to_parse(s))?;
+
+        let commands: BTreeSet<String> = commands.split_whitespace().map(String::from).collect();
+        if commands.is_empty() {
+            return Err(unable_to_parse(s));
+        }
+        Ok(CommandSet { commands })
+    }
+}
+
+/// ClamAV's reply to STATS, see [stats](crate::stats).
+///
+/// The reply is read leniently as it varies between ClamAV releases: keys ClamAV doesn't always
+/// send are optional and keys this crate doesn't know are kept in [ClamdStats::other].
+///
+/// ```
+/// use clamav_tcp::ClamdStats;
+/// let stats = ClamdStats::parse("POOLS: 1\n\nSTATE: VALID PRIMARY\nTHREADS: live 1  idle 0 max 12 idle-timeout 30\nQUEUE: 0 items\nEND\0").unwrap();
+/// assert_eq!(stats.pools, Some(1));
+/// assert_eq!(stats.state.as_deref(), Some("VALID PRIMARY"));
+/// ```
+#[derive(Deserialize, Debug, Serialize, Clone, PartialEq, Eq, Default)]
+pub struct ClamdStats {
+    /// Number of thread pools.
+    #[serde(rename = "pools")]
+    pub pools: Option<u32>,
+    /// State of the signature database, eg. `VALID PRIMARY`.
+    #[serde(rename = "state")]
+    pub state: Option<String>,
+    /// The THREADS line as sent, eg. `live 1  idle 0 max 12 idle-timeout 30`.
+    #[serde(rename = "threads")]
+    pub threads: Option<String>,
+    /// The QUEUE line as sent, eg. `0 items`.
+    #[serde(rename = "queue")]
+    pub queue: Option<String>,
+    /// The commands being processed as listed under QUEUE, eg. `STATS 0.000042`.
+    #[serde(rename = "queue_items")]
+    pub queue_items: Vec<String>,
+    /// The MEMSTATS line as sent.
+    #[serde(rename = "memstats")]
+    pub memstats: Option<String>,
+    /// Any other `KEY: value` lines, by key.
+    #[serde(rename = "other")]
+    pub other: HashMap<String, String>,
+}
+
+impl ClamdStats {
+    /// Parses a STATS reply. Blank lines, `\r\n` line endings and a `\0` terminator are accepted
+    /// and everything after `END` is ignored.
+    pub fn parse(s: &str) -> Result<Self, ClamAVClientError> {
+        let mut stats = ClamdStats::default();
+        let mut seen_key = false;
+        for raw in s.split(['\n', '\0']) {
+            let line = raw.trim();
+            if line == "END" {
+                break;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            // Indented lines without a key list the queued commands.
+            let Some((key, value)) = line
+                .split_once(':')
+                .filter(|_| !raw.starts_with(char::is_whitespace))
+            else {
+                if !seen_key {
+                    return Err(unable_to_parse(s));
+                }
+                stats.queue_items.push(line.to_string());
+                continue;
+            };
+            seen_key = true;
+
+            let value = value.trim().to_string();
+            match key.trim() {
+                "POOLS" => stats.pools = Some(value.parse().map_err(|_| unable_to_parse(s))?),
+                "STATE" => stats.state = Some(value),
+                "THREADS" => stats.threads = Some(value),
+                "QUEUE" => stats.queue = Some(value),
+                "MEMSTATS" => stats.memstats = Some(value),
+                key => {
+                    stats.other.insert(key.to_string(), value);
+                }
+            }
+        }
+
+        if !seen_key {
+            return Err(unable_to_parse(s));
+        }
+        Ok(stats)
+    }
+
+    /// The thread pool counts from the THREADS line, if ClamAV sent one with `live`, `idle` and
+    /// `max` counts. Other counts, such as `idle-timeout`, are ignored.
+    ///
+    /// ```
+    /// use clamav_tcp::{ClamdStats, ThreadStats};
+    /// let stats = ClamdStats::parse("THREADS: live 12  idle 0 max 12 idle-timeout 30\nEND\0").unwrap();
+    /// let threads = stats.thread_stats().unwrap();
+    /// assert_eq!(threads, ThreadStats { live: 12, idle: 0, max: 12 });
+    /// assert!(threads.is_saturated());
+    /// ```
+    pub fn thread_stats(&self) -> Option<ThreadStats> {
+        let mut counts = HashMap::new();
+        let mut fields = self.threads.as_deref()?.split_whitespace();
+        while let (Some(name), Some(count)) = (fields.next(), fields.next()) {
+            counts.insert(name, count.parse::<u32>().ok()?);
+        }
+        Some(ThreadStats {
+            live: *counts.get("live")?,
+            idle: *counts.get("idle")?,
+            max: *counts.get("max")?,
         })
     }
+
+    /// The number of queued items from the QUEUE line, eg. `0 items`, if ClamAV sent one.
+    ///
+    /// ```
+    /// use clamav_tcp::{ClamdStats, QueueStats};
+    /// let stats = ClamdStats::parse("QUEUE: 3 items\nEND\0").unwrap();
+    /// assert_eq!(stats.queue_stats(), Some(QueueStats { items: 3 }));
+    /// ```
+    pub fn queue_stats(&self) -> Option<QueueStats> {
+        let items = self.queue.as_deref()?.split_whitespace().next()?;
+        Some(QueueStats {
+            items: items.parse().ok()?,
+        })
+    }
+}
+
+/// The thread pool counts in a STATS reply, see [ClamdStats::thread_stats].
+#[derive(Deserialize, Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadStats {
+    /// Number of threads serving requests.
+    #[serde(rename = "live")]
+    pub live: u32,
+    /// Number of threads waiting for a request.
+    #[serde(rename = "idle")]
+    pub idle: u32,
+    /// Maximum number of threads, ClamAV's `MaxThreads`.
+    #[serde(rename = "max")]
+    pub max: u32,
+}
+
+impl ThreadStats {
+    /// Whether every thread ClamAV may start is busy, so new requests wait in its queue.
+    pub fn is_saturated(&self) -> bool {
+        self.idle == 0 && self.live >= self.max
+    }
+}
+
+/// The queue counts in a STATS reply, see [ClamdStats::queue_stats].
+#[derive(Deserialize, Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStats {
+    /// Number of requests waiting for a thread.
+    #[serde(rename = "items")]
+    pub items: u32,
+}
+
+impl FromStr for ClamdStats {
+    type Err = ClamAVClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ClamdStats::parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn ok_must_be_a_whole_token() {
+        let res: ScanResult = "stream: OKTrojan.Agent FOUND\0".parse().unwrap();
+        assert!(res.is_infected);
+        assert_eq!(res.detected_infections, vec!["OKTrojan.Agent"]);
+
+        let res: ScanResult = "stream: OK (1024 bytes)\0".parse().unwrap();
+        assert!(!res.is_infected);
+
+        // Not a clean status, so it is reported like any other unrecognized status.
+        let res: ScanResult = "stream: OKAY\0".parse().unwrap();
+        assert!(res.is_infected);
+    }
+
+    #[test]
+    fn accepts_lowercase_status_tokens() {
+        let res: ScanResult = "stream: ok\0".parse().unwrap();
+        assert!(!res.is_infected);
+        let res: ScanResult = "stream: Win.Test.EICAR_HDB-1 found\0".parse().unwrap();
+        assert_eq!(res.detected_infections, vec!["Win.Test.EICAR_HDB-1"]);
+
+        // Only the status token is matched, not the same letters within a signature name.
+        let res: ScanResult = "stream: Sig.found Found\0".parse().unwrap();
+        assert_eq!(res.detected_infections, vec!["Sig.found"]);
+        let res: ScanResult = "stream: okTrojan.Agent found\0".parse().unwrap();
+        assert_eq!(res.detected_infections, vec!["okTrojan.Agent"]);
+
+        let res: MultiScanResult = "/tmp/a: ok\0/tmp/b: Sig.Found found\0".parse().unwrap();
+        assert!(!res.files[0].is_infected);
+        assert_eq!(res.files[1].detected_infections, vec!["Sig.Found"]);
+
+        // A lowercase error is still a failed scan, not a detection.
+        let res = "stream: Can't allocate memory error\0".parse::<ScanResult>();
+        assert!(matches!(res, Err(ClamAVClientError::ClamdError(_))));
+        let res = "/uploads: Access denied. error\0".parse::<MultiScanResult>();
+        assert!(matches!(res, Err(ClamAVClientError::ClamdError(_))));
+        let res: MultiScanResult = "/tmp/a: Access denied. error\0/tmp/b: ok\0"
+            .parse()
+            .unwrap();
+        assert_eq!(res.files[0].error.as_deref(), Some("Access denied."));
+        assert!(!res.files[0].is_infected);
+    }
+
+    #[test]
+    fn matches_any_detection_against_any_pattern() {
+        let res: ScanResult = "stream: Sig.One FOUND\0stream: Ransom.Two FOUND\0"
+            .parse()
+            .unwrap();
+        assert!(res.matches_any(&["Trojan.", "Ransom."]));
+        assert!(!res.matches_any(&["ransom."]));
+        assert!(!res.matches_any(&[]));
+        assert!(!ScanResult::clean().matches_any(&[""]));
+    }
+
+    #[test]
+    fn carries_meta_alongside_the_result() {
+        let res: ScanResult = "stream: Sig.One FOUND\0".parse().unwrap();
+        let tagged = res.with_meta(("request-1", 42));
+        assert_eq!(tagged.meta, ("request-1", 42));
+        assert_eq!(tagged.result.detected_infections, vec!["Sig.One"]);
+
+        let json = serde_json::to_string(&ScanResult::clean().with_meta(7)).unwrap();
+        assert_eq!(
+            json,
+            r#"{"result":{"is_infected":false,"detected_infections":[]},"meta":7}"#
+        );
+    }
+
+    #[test]
+    fn results_can_be_collected_in_a_set() {
+        let replies = [
+            "stream: Sig.One FOUND\0stream: Sig.Two FOUND\0",
+            "stream: Sig.One FOUND\0stream: Sig.Two FOUND\0",
+            "stream: Sig.Two FOUND\0stream: Sig.One FOUND\0",
+            "stream: OK\0",
+        ];
+        let results: HashSet<ScanResult> = replies.iter().map(|r| r.parse().unwrap()).collect();
+        // The order of the detections counts.
+        assert_eq!(results.len(), 3);
+        assert!(results.contains(&ScanResult::clean()));
+        assert!(results.contains(&ScanResult::infected(vec![
+            "Sig.Two".to_string(),
+            "Sig.One".to_string()
+        ])));
+    }
+
+    #[test]
+    fn tells_unofficial_detections_apart() {
+        let res: ScanResult =
+            "stream: Win.Test.EICAR_HDB-1 FOUND\0stream: MiscreantPunch.Malware.UNOFFICIAL FOUND\0"
+                .parse()
+                .unwrap();
+        let unofficial: Vec<bool> = res
+            .detected_infections
+            .iter()
+            .map(|name| is_unofficial(name))
+            .collect();
+        assert_eq!(unofficial, vec![false, true]);
+        // Only the suffix counts, not the word elsewhere in a name.
+        assert!(!is_unofficial("Local.UNOFFICIAL.Sig"));
+    }
+
+    #[test]
+    fn tells_heuristic_detections_apart() {
+        for name in [
+            "Heuristics.Phishing.Email.SpoofedDomain",
+            "Heuristics.Broken.Executable",
+            "Sanesecurity.Heuristics.Foo.UNOFFICIAL",
+        ] {
+            assert!(is_heuristic(name), "{name}");
+        }
+        for name in [
+            "Win.Test.EICAR_HDB-1",
+            "Doc.Trojan.HeuristicsDropper-1",
+            "Heuristic.Sig",
+        ] {
+            assert!(!is_heuristic(name), "{name}");
+        }
+    }
+
+    #[test]
+    fn into_detections_is_none_only_when_clean() {
+        let res: ScanResult = "stream: Sig.One FOUND\0".parse().unwrap();
+        assert_eq!(res.into_detections(), Some(vec!["Sig.One".to_string()]));
+        let res: ScanResult = "stream: OK\0".parse().unwrap();
+        assert_eq!(res.into_detections(), None);
+    }
+
+    #[test]
+    fn verdict_tokens_round_trip() {
+        let infected: ScanResult = "stream: Sig.One FOUND\0stream: Sig.Two FOUND\0"
+            .parse()
+            .unwrap();
+        for res in [ScanResult::clean(), infected] {
+            let token = res.verdict_token();
+            let parsed = ScanResult::from_verdict_token(&token).unwrap();
+            assert_eq!(parsed.is_infected, res.is_infected, "{token}");
+            assert_eq!(parsed.detected_infections, res.detected_infections);
+            assert_eq!(parsed.verdict_token(), token);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_verdict_tokens() {
+        for token in [
+            "",
+            "Clean",
+            "infected",
+            "infected:",
+            "infected:Sig.One,",
+            "ok:Sig",
+        ] {
+            assert!(
+                matches!(
+                    ScanResult::from_verdict_token(token),
+                    Err(ClamAVClientError::UnableToParseResponse(_))
+                ),
+                "{token:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn severity_is_the_highest_of_the_detections() {
+        let policy = |name: &str| match name {
+            "Sig.Low" => Severity::Low,
+            "Sig.High" => Severity::High,
+            _ => Severity::Medium,
+        };
+        let res: ScanResult =
+            "stream: Sig.Low FOUND\0stream: Sig.High FOUND\0stream: Sig.Other FOUND\0"
+                .parse()
+                .unwrap();
+        assert_eq!(res.severity(&policy), Severity::High);
+
+        let res: ScanResult = "stream: OK\0".parse().unwrap();
+        assert_eq!(res.severity(&policy), Severity::Clean);
+    }
+
+    #[test]
+    fn parses_stats_across_versions() {
+        let stats = ClamdStats::parse(include_str!("../resources/replies/stats_1_0")).unwrap();
+        assert_eq!(stats.pools, Some(1));
+        assert_eq!(stats.state.as_deref(), Some("VALID PRIMARY"));
+        assert_eq!(
+            stats.threads.as_deref(),
+            Some("live 1  idle 0 max 12 idle-timeout 30")
+        );
+        assert_eq!(stats.queue.as_deref(), Some("0 items"));
+        assert_eq!(stats.queue_items, vec!["STATS 0.000042"]);
+        assert!(stats.memstats.unwrap().starts_with("heap N/A"));
+        assert!(stats.other.is_empty());
+
+        let stats = ClamdStats::parse(include_str!("../resources/replies/stats_0_100")).unwrap();
+        assert_eq!(stats.pools, Some(1));
+        assert_eq!(stats.queue.as_deref(), Some("1 items"));
+        assert_eq!(
+            stats.queue_items,
+            vec!["INSTREAM 0.412004", "STATS 0.000031"]
+        );
+        assert!(stats.memstats.unwrap().starts_with("heap 9.082M"));
+    }
+
+    #[test]
+    fn reads_thread_and_queue_counts_across_versions() {
+        let stats = ClamdStats::parse(include_str!("../resources/replies/stats_1_0")).unwrap();
+        let threads = stats.thread_stats().unwrap();
+        assert_eq!(
+            threads,
+            ThreadStats {
+                live: 1,
+                idle: 0,
+                max: 12
+            }
+        );
+        // The only live thread is the one answering STATS, so there is room for more.
+        assert!(!threads.is_saturated());
+        assert_eq!(stats.queue_stats(), Some(QueueStats { items: 0 }));
+
+        let stats = ClamdStats::parse(include_str!("../resources/replies/stats_0_100")).unwrap();
+        assert_eq!(
+            stats.thread_stats(),
+            Some(ThreadStats {
+                live: 2,
+                idle: 1,
+                max: 10
+            })
+        );
+        assert_eq!(stats.queue_stats(), Some(QueueStats { items: 1 }));
+
+        let stats =
+            ClamdStats::parse("THREADS: idle 0 live 4 max 4\nQUEUE: 7 items\nEND\0").unwrap();
+        assert!(stats.thread_stats().unwrap().is_saturated());
+        assert_eq!(stats.queue_stats().unwrap().items, 7);
+
+        for reply in [
+            "THREADS: live 1 idle 0\nQUEUE: many\n",
+            "THREADS: live x idle 0 max 1\n",
+            "POOLS: 1\n",
+        ] {
+            let stats = ClamdStats::parse(reply).unwrap();
+            assert_eq!(stats.thread_stats(), None, "{reply:?}");
+            assert_eq!(stats.queue_stats(), None, "{reply:?}");
+        }
+    }
+
+    #[test]
+    fn keeps_unknown_stats_keys() {
+        let stats = "POOLS: 2\nSTATE: VALID PRIMARY\nMYSTAT:  42 \nEND\ngarbage: 1\0"
+            .parse::<ClamdStats>()
+            .unwrap();
+        assert_eq!(stats.pools, Some(2));
+        assert_eq!(stats.other.len(), 1);
+        assert_eq!(stats.other["MYSTAT"], "42");
+    }
+
+    #[test]
+    fn rejects_invalid_stats() {
+        for reply in ["", "END\0", "UNKNOWN COMMAND\0", "POOLS: many\nEND\0"] {
+            assert!(ClamdStats::parse(reply).is_err(), "{reply:?}");
+        }
+    }
+
+    #[test]
+    fn parses_versioncommands_reply() {
+        let commands = include_str!("../resources/replies/versioncommands")
+            .parse::<CommandSet>()
+            .unwrap();
+        assert_eq!(commands.commands.len(), 17);
+        assert!(commands.has_idsession());
+        assert!(commands.has_instream());
+        assert!(commands.has_multiscan());
+        assert!(commands.has_contscan());
+        assert!(commands.has_allmatchscan());
+        assert!(commands.has_fildes());
+        assert!(commands.has_stats());
+        assert!(commands.require("DETSTATS").is_ok());
+        assert!(matches!(
+            commands.require("ZSCAN"),
+            Err(ClamAVClientError::UnsupportedCommand(c)) if c == "ZSCAN"
+        ));
+    }
+
+    #[test]
+    fn rejects_reply_without_commands() {
+        for reply in [
+            "",
+            "ClamAV 1.0.0/26734/Mon Nov 28 08:17:05 2022\0",
+            "UNKNOWN COMMAND\0",
+            "COMMANDS:\0",
+        ] {
+            assert!(reply.parse::<CommandSet>().is_err(), "{reply:?}");
+        }
+    }
+
+    #[test]
+    fn constructors_keep_the_infected_flag_consistent() {
+        for res in [
+            ScanResult::clean(),
+            ScanResult::infected(vec![]),
+            ScanResult::infected(vec!["Sig.One".to_string(), "Sig.Two".to_string()]),
+        ] {
+            assert_eq!(res.is_infected, !res.detected_infections.is_empty());
+            assert_eq!(res.scanned_item, None);
+        }
+        assert!(!ScanResult::infected(vec![]).is_infected);
+    }
+
+    #[test]
+    fn nameless_detections_are_not_infections() {
+        for resp in ["stream: FOUND\0", "stream:  FOUND \r\n"] {
+            assert!(
+                matches!(
+                    resp.parse::<ScanResult>(),
+                    Err(ClamAVClientError::UnableToParseResponse(_))
+                ),
+                "{resp:?}"
+            );
+        }
+
+        let res = "stream: FOUND\0stream: OK\0".parse::<ScanResult>().unwrap();
+        assert!(!res.is_infected);
+        assert!(res.detected_infections.is_empty());
+
+        let res = "stream: FOUND\0stream: Sig.One FOUND\0"
+            .parse::<ScanResult>()
+            .unwrap();
+        assert!(res.is_infected);
+        assert_eq!(res.detected_infections, vec!["Sig.One"]);
+    }
+
+    #[test]
+    fn fixture_instream_clean() {
+        let res = include_str!("../resources/replies/instream_clean")
+            .parse::<ScanResult>()
+            .unwrap();
+        assert!(!res.is_infected);
+        assert!(res.detected_infections.is_empty());
+    }
+
+    #[test]
+    fn fixture_instream_found() {
+        let res = include_str!("../resources/replies/instream_found")
+            .parse::<ScanResult>()
+            .unwrap();
+        assert!(res.is_infected);
+        assert_eq!(res.detected_infections, vec!["Win.Test.EICAR_HDB-1"]);
+    }
+
+    #[test]
+    fn fixture_instream_allmatch() {
+        let res = include_str!("../resources/replies/instream_allmatch")
+            .parse::<ScanResult>()
+            .unwrap();
+        assert!(res.is_infected);
+        assert_eq!(
+            res.detected_infections,
+            vec!["Win.Test.EICAR_HDB-1", "Eicar-Signature"]
+        );
+    }
+
+    #[test]
+    fn fixture_instream_allmatch_lines() {
+        let res = include_str!("../resources/replies/instream_allmatch_lines")
+            .parse::<ScanResult>()
+            .unwrap();
+        assert!(res.is_infected);
+        assert_eq!(
+            res.detected_infections,
+            vec!["Win.Test.EICAR_HDB-1", "Eicar-Signature"]
+        );
+    }
+
+    #[test]
+    fn empty_statuses_are_not_detections() {
+        let res = "stream: OK\nstream:\nEND\0".parse::<ScanResult>().unwrap();
+        assert!(!res.is_infected);
+        assert!(res.detected_infections.is_empty());
+
+        // Nor are they a clean result.
+        assert!(matches!(
+            "stream: \0".parse::<ScanResult>(),
+            Err(ClamAVClientError::UnableToParseResponse(_))
+        ));
+    }
+
+    #[test]
+    fn fixture_instream_size_limit() {
+        let res = include_str!("../resources/replies/instream_size_limit").parse::<ScanResult>();
+        assert!(matches!(res, Err(ClamAVClientError::SizeLimitExceeded)));
+    }
+
+    #[test]
+    fn fixture_unknown_command() {
+        let res = include_str!("../resources/replies/unknown_command").parse::<ScanResult>();
+        assert!(matches!(res, Err(ClamAVClientError::ClamdError(e)) if e == "UNKNOWN COMMAND"));
+    }
+
+    #[test]
+    fn fixture_scan_access_denied() {
+        let res =
+            include_str!("../resources/replies/scan_access_denied").parse::<MultiScanResult>();
+        assert!(matches!(
+            res,
+            Err(ClamAVClientError::ClamdError(e)) if e == "/var/uploads/secret.txt: Access denied. ERROR"
+        ));
+    }
+
+    #[test]
+    fn fixture_contscan_found() {
+        let res = include_str!("../resources/replies/contscan_found")
+            .parse::<MultiScanResult>()
+            .unwrap();
+        let paths: Vec<&str> = res.infected_files().map(|f| f.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec!["/var/uploads/eicar.txt", "/var/uploads/eicar.com"]
+        );
+    }
+
+    #[test]
+    fn fixture_contscan_clean() {
+        let res = include_str!("../resources/replies/contscan_clean")
+            .parse::<MultiScanResult>()
+            .unwrap();
+        assert!(!res.is_infected());
+        assert_eq!(res.files.len(), 1);
+    }
+
+    #[test]
+    fn scan_result_json_shape_is_stable() {
+        let clean = "stream: OK\0".parse::<ScanResult>().unwrap();
+        assert_eq!(
+            serde_json::to_string(&clean).unwrap(),
+            r#"{"is_infected":false,"detected_infections":[]}"#
+        );
+
+        let infected = "stream: Win.Test.EICAR_HDB-1 FOUND\0"
+            .parse::<ScanResult>()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_string(&infected).unwrap(),
+            r#"{"is_infected":true,"detected_infections":["Win.Test.EICAR_HDB-1"]}"#
+        );
+    }
+
+    #[test]
+    fn multiscan_result_json_shape_is_stable() {
+        let res = "/uploads/eicar.txt: Win.Test.EICAR_HDB-1 FOUND\0"
+            .parse::<MultiScanResult>()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_string(&res).unwrap(),
+            r#"{"files":[{"path":"/uploads/eicar.txt","is_infected":true,"detected_infections":["Win.Test.EICAR_HDB-1"]}]}"#
+        );
+    }
+
+    #[test]
+    fn reply_without_marker_is_an_error() {
+        assert!(matches!(
+            "".parse::<ScanResult>(),
+            Err(ClamAVClientError::UnableToParseResponse(_))
+        ));
+        assert!(matches!(
+            "PONG\0".parse::<ScanResult>(),
+            Err(ClamAVClientError::UnableToParseResponse(_))
+        ));
+    }
+
+    #[test]
+    fn stream_errors_are_not_detections() {
+        let res = "stream: Can't allocate memory ERROR\0".parse::<ScanResult>();
+        assert!(matches!(
+            res,
+            Err(ClamAVClientError::ClamdError(e)) if e == "Can't allocate memory ERROR"
+        ));
+    }
+
+    #[test]
+    fn finds_marker_regardless_of_prefix() {
+        for resp in [
+            "stream: OK\0",
+            "1: stream: OK\0",
+            "stream:OK\0",
+            "  stream: OK\n",
+            "[INSTREAM] stream: OK\0",
+        ] {
+            let res = resp.parse::<ScanResult>().unwrap();
+            assert!(!res.is_infected, "{resp:?}");
+        }
+
+        for resp in [
+            "stream: Win.Test.EICAR_HDB-1 FOUND\0",
+            "42: stream: Win.Test.EICAR_HDB-1 FOUND\0",
+            "stream:Win.Test.EICAR_HDB-1 FOUND\0",
+            "session 3 stream: Win.Test.EICAR_HDB-1 FOUND\n",
+            "[INSTREAM] stream: Win.Test.EICAR_HDB-1 FOUND\0",
+        ] {
+            let res = resp.parse::<ScanResult>().unwrap();
+            assert_eq!(
+                res.detected_infections,
+                vec!["Win.Test.EICAR_HDB-1"],
+                "{resp:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn strips_found_regardless_of_terminator() {
+        for resp in [
+            "stream: Win.Test.EICAR_HDB-1 FOUND",
+            "stream: Win.Test.EICAR_HDB-1 FOUND\0",
+            "stream: Win.Test.EICAR_HDB-1 FOUND\r\n",
+            "stream: Win.Test.EICAR_HDB-1  FOUND \t\0",
+        ] {
+            let res = resp.parse::<ScanResult>().unwrap();
+            assert_eq!(
+                res.detected_infections,
+                vec!["Win.Test.EICAR_HDB-1"],
+                "{resp:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_signature_names_ending_in_found() {
+        let res = "stream: Heuristics.NOTFOUND FOUND\0stream: Sig.NOTFOUND\0"
+            .parse::<ScanResult>()
+            .unwrap();
+        assert_eq!(
+            res.detected_infections,
+            vec!["Heuristics.NOTFOUND", "Sig.NOTFOUND"]
+        );
+    }
+
+    #[test]
+    fn lenient_parsing_ignores_trailing_garbage() {
+        let res = "stream: OK\0garbage".parse::<ScanResult>().unwrap();
+        assert!(!res.is_infected);
+    }
+
+    #[test]
+    fn strict_parsing_rejects_trailing_garbage() {
+        assert!(check_terminated("stream: OK\0garbage").is_err());
+        assert!(check_terminated("stream: OK\0garbage\0").is_err());
+        assert!(check_terminated("stream: OK").is_err());
+        assert!(check_terminated("").is_err());
+    }
+
+    #[test]
+    fn strict_parsing_accepts_terminated_replies() {
+        assert!(check_terminated("stream: OK\0").is_ok());
+        assert!(check_terminated("1: stream: Sig.One FOUND\0").is_ok());
+        assert!(check_terminated("stream: Sig.One FOUND\0stream: Sig.Two FOUND\0").is_ok());
+    }
+
+    #[test]
+    fn pairs_paths_with_detections() {
+        let resp = "/uploads/a.exe: Win.Test.EICAR_HDB-1 FOUND\0/uploads/b/c.doc: Doc.Macro.Agent-1 FOUND\0";
+        let res = resp.parse::<MultiScanResult>().unwrap();
+
+        assert!(res.is_infected());
+        let infected: Vec<(&str, &[String])> = res
+            .infected_files()
+            .map(|f| (f.path.as_str(), &f.detected_infections[..]))
+            .collect();
+        assert_eq!(
+            infected,
+            vec![
+                ("/uploads/a.exe", &["Win.Test.EICAR_HDB-1".to_string()][..]),
+                ("/uploads/b/c.doc", &["Doc.Macro.Agent-1".to_string()][..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_mixed_clean_and_infected_reply() {
+        let resp = "/uploads/clean.txt: OK\n/uploads/eicar.txt: Win.Test.EICAR_HDB-1 FOUND\n/uploads/other.txt: OK\n";
+        let res = resp.parse::<MultiScanResult>().unwrap();
+
+        assert_eq!(res.files.len(), 3);
+        assert!(!res.files[0].is_infected);
+        assert!(res.files[1].is_infected);
+        assert!(!res.files[2].is_infected);
+        assert_eq!(res.infected_files().count(), 1);
+        assert_eq!(res.files[1].path, "/uploads/eicar.txt");
+    }
+
+    #[test]
+    fn groups_allmatch_detections_by_path() {
+        let resp = "/uploads/a.zip: Sig.One FOUND\0/uploads/a.zip: Sig.Two FOUND\0";
+        let res = resp.parse::<MultiScanResult>().unwrap();
+
+        assert_eq!(res.files.len(), 1);
+        assert_eq!(res.files[0].detected_infections, vec!["Sig.One", "Sig.Two"]);
+    }
+
+    #[test]
+    fn clean_directory_is_not_infected() {
+        let res = "/uploads: OK\0".parse::<MultiScanResult>().unwrap();
+
+        assert!(!res.is_infected());
+        assert_eq!(res.files[0].path, "/uploads");
+    }
+
+    #[test]
+    fn multiscan_rejects_unexpected_reply() {
+        assert!("".parse::<MultiScanResult>().is_err());
+        assert!("/uploads/a.txt: Sig.One"
+            .parse::<MultiScanResult>()
+            .is_err());
+    }
+
+    #[test]
+    fn multiscan_reports_clamd_errors() {
+        let res = "/uploads: Access denied. ERROR\0".parse::<MultiScanResult>();
+        assert!(matches!(
+            res,
+            Err(ClamAVClientError::ClamdError(e)) if e == "/uploads: Access denied. ERROR"
+        ));
+    }
+
+    #[test]
+    fn keeps_results_next_to_per_file_errors() {
+        let resp = "/uploads/eicar.txt: Win.Test.EICAR_HDB-1 FOUND\0/uploads/secret.txt: Access denied. ERROR\0/uploads/clean.txt: OK\0";
+        let res = resp.parse::<MultiScanResult>().unwrap();
+
+        assert_eq!(res.files.len(), 3);
+        assert!(res.is_infected());
+        let infected: Vec<&str> = res.infected_files().map(|f| f.path.as_str()).collect();
+        assert_eq!(infected, vec!["/uploads/eicar.txt"]);
+        let failed: Vec<(&str, Option<&str>)> = res
+            .failed_files()
+            .map(|f| (f.path.as_str(), f.error.as_deref()))
+            .collect();
+        assert_eq!(
+            failed,
+            vec![("/uploads/secret.txt", Some("Access denied."))]
+        );
+        assert_eq!(res.files[2].error, None);
+    }
+
+    #[test]
+    fn flattening_does_not_pass_failed_files_as_clean() {
+        use crate::multiscan::flatten;
+
+        let res = "/uploads/secret.txt: Access denied. ERROR\0/uploads/clean.txt: OK\0"
+            .parse::<MultiScanResult>()
+            .unwrap();
+        assert!(matches!(
+            flatten(res),
+            Err(ClamAVClientError::ClamdError(e)) if e == "/uploads/secret.txt: Access denied. ERROR"
+        ));
+
+        let res = "/uploads/secret.txt: Access denied. ERROR\0/uploads/eicar.txt: Eicar FOUND\0"
+            .parse::<MultiScanResult>()
+            .unwrap();
+        assert_eq!(flatten(res).unwrap().detected_infections, vec!["Eicar"]);
+    }
+
+    #[test]
+    fn parses_version_reply() {
+        let info = "ClamAV 1.0.0/26734/Mon Nov 28 08:17:05 2022\0"
+            .parse::<VersionInfo>()
+            .unwrap();
+        assert_eq!(
+            info,
+            VersionInfo {
+                engine_version: "1.0.0".to_string(),
+                database_version: Some(26734),
+                database_date: Some("Mon Nov 28 08:17:05 2022".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn parses_version_reply_without_database() {
+        let info = "ClamAV 0.103.8\n".parse::<VersionInfo>().unwrap();
+        assert_eq!(info.engine_version, "0.103.8");
+        assert_eq!(info.database_version, None);
+        assert_eq!(info.database_date, None);
+    }
+
+    #[test]
+    fn rejects_invalid_version_reply() {
+        assert!("".parse::<VersionInfo>().is_err());
+        assert!("UNKNOWN COMMAND\0".parse::<VersionInfo>().is_err());
+        assert!("ClamAV 1.0.0/not-a-number/Mon Nov 28\0"
+            .parse::<VersionInfo>()
+            .is_err());
+    }
+
+    #[test]
+    fn reads_database_date_as_utc() {
+        let time = |date: &str| {
+            VersionInfo {
+                engine_version: "1.0.0".to_string(),
+                database_version: Some(26734),
+                database_date: Some(date.to_string()),
+            }
+            .database_time()
+            .map(|t| t.duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs())
+        };
+
+        assert_eq!(time("Thu Jan  1 00:00:00 1970"), Some(0));
+        assert_eq!(time("Mon Nov 28 08:17:05 2022"), Some(1_669_623_425));
+        assert_eq!(time("Thu Feb 29 12:00:00 2024"), Some(1_709_208_000));
+        assert_eq!(time("Tue Nov  8 08:17:05 2022"), Some(1_667_895_425));
+        assert_eq!(time("Mon Nov 28 2022"), None);
+        assert_eq!(time("Mon Foo 28 08:17:05 2022"), None);
+        assert_eq!(time("Mon Nov 28 25:17:05 2022"), None);
+        assert_eq!(time(""), None);
+
+        let info = "ClamAV 0.103.8\n".parse::<VersionInfo>().unwrap();
+        assert_eq!(info.database_time(), None);
+    }
+
+    #[test]
+    fn extracts_engine_semver() {
+        let semver = |v: &str| {
+            format!("ClamAV {v}/26734/Mon Nov 28 08:17:05 2022\0")
+                .parse::<VersionInfo>()
+                .unwrap()
+                .engine_semver()
+        };
+
+        assert_eq!(semver("1.0.0"), Some((1, 0, 0)));
+        assert_eq!(semver("0.103.8"), Some((0, 103, 8)));
+        assert_eq!(semver("1.1.0-beta"), Some((1, 1, 0)));
+        assert_eq!(semver("0.105.0-rc"), Some((0, 105, 0)));
+        assert_eq!(semver("0.99"), Some((0, 99, 0)));
+        assert_eq!(semver("devel-20221128"), None);
+        assert!(semver("0.103.8") >= Some((0, 103, 0)));
+        assert!(semver("0.102.4") < Some((0, 103, 0)));
+    }
 }
@@ -0,0 +1,331 @@
+use std::{
+    io::{Read, Seek, SeekFrom},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    resolve,
+    scan::{parse_reply, read_scan_reply, write_instream},
+    ClamAVClientError, ScanResult,
+};
+
+/// How [scan_within] retries scans that failed with a [retryable](ClamAVClientError::is_retryable) error.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one. `0` is treated as `1`.
+    pub max_attempts: u32,
+    /// Time to wait before the first retry. The wait doubles after every retry.
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// Three attempts, waiting 100ms before the first retry.
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Scans something that is [Read] and [Seek], retrying transient failures without ever taking
+/// longer than `total_budget` in total.
+///
+/// Connecting, streaming and waiting for the reply all count against the budget, as do the waits
+/// between attempts. If the budget runs out, the scan fails with [ClamAVClientError::Timeout].
+/// Errors that aren't [retryable](ClamAVClientError::is_retryable) are returned straight away,
+/// and once `retry_policy` runs out of attempts the last error is returned.
+///
+/// The input is rewound to where it was when the scan started before every retry, so the whole
+/// input is scanned each time.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use clamav_tcp::RetryPolicy;
+/// let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+/// let res = clamav_tcp::scan_within(
+///     "localhost:3310",
+///     &mut eicar,
+///     Duration::from_secs(5),
+///     RetryPolicy::default(),
+/// )
+/// .unwrap();
+/// assert!(res.is_infected);
+/// ```
+pub fn scan_within<A: ToSocketAddrs, D: Read + Seek>(
+    addr: A,
+    file: &mut D,
+    total_budget: Duration,
+    retry_policy: RetryPolicy,
+) -> Result<ScanResult, ClamAVClientError> {
+    let deadline = Instant::now() + total_budget;
+    let addrs = resolve(addr)?;
+    let start = file
+        .stream_position()
+        .map_err(ClamAVClientError::UnableToReadInput)?;
+
+    let mut backoff = retry_policy.backoff;
+    let mut attempt = 1;
+    loop {
+        let err = match scan_before(&addrs, file, deadline) {
+            Ok(res) => return Ok(res),
+            Err(e) => e,
+        };
+        if Instant::now() >= deadline {
+            return Err(ClamAVClientError::Timeout);
+        }
+        if !err.is_retryable() || attempt >= retry_policy.max_attempts {
+            return Err(err);
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if backoff >= remaining {
+            // The budget would run out while waiting, so there is no time left for another attempt.
+            thread::sleep(remaining);
+            return Err(ClamAVClientError::Timeout);
+        }
+        thread::sleep(backoff);
+        backoff = backoff.saturating_mul(2);
+        attempt += 1;
+
+        file.seek(SeekFrom::Start(start))
+            .map_err(ClamAVClientError::UnableToReadInput)?;
+    }
+}
+
+/// Runs a single INSTREAM scan, giving up once `deadline` has passed.
+fn scan_before<D: Read>(
+    addrs: &[SocketAddr],
+    file: &mut D,
+    deadline: Instant,
+) -> Result<ScanResult, ClamAVClientError> {
+    let mut stream = connect_before(addrs, deadline)?;
+
+    // The timeout is set on a second handle to the socket so it can be shortened before every chunk.
+    let timeouts = stream
+        .try_clone()
+        .map_err(ClamAVClientError::UnableToConnect)?;
+    write_instream(&mut stream, file, None, |_| {
+        timeouts
+            .set_write_timeout(Some(remaining(deadline)?))
+            .map_err(ClamAVClientError::UnableToWriteToStream)
+    })?;
+
+    stream
+        .set_read_timeout(Some(remaining(deadline)?))
+        .map_err(ClamAVClientError::UnableToConnect)?;
+    let reply = read_scan_reply(&mut stream)?;
+
+    parse_reply(&reply, false)
+}
+
+/// Connects to the first of `addrs` that accepts the connection before `deadline`.
+fn connect_before(addrs: &[SocketAddr], deadline: Instant) -> Result<TcpStream, ClamAVClientError> {
+    let mut last_err = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(addr, remaining(deadline)?) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(ClamAVClientError::UnableToConnect(last_err.unwrap_or_else(
+        || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "no addresses to connect to",
+            )
+        },
+    )))
+}
+
+/// The time left until `deadline`, or [ClamAVClientError::Timeout] if it has passed.
+fn remaining(deadline: Instant) -> Result<Duration, ClamAVClientError> {
+    match deadline.checked_duration_since(Instant::now()) {
+        Some(left) if !left.is_zero() => Ok(left),
+        _ => Err(ClamAVClientError::Timeout),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Cursor, Write},
+        net::TcpListener,
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+    };
+
+    use super::*;
+
+    /// Reads an INSTREAM request up to and including its footer, returning the streamed data.
+    fn read_request(conn: &mut TcpStream) -> Vec<u8> {
+        let mut header = [0; 10];
+        conn.read_exact(&mut header).unwrap();
+        let mut data = vec![];
+        loop {
+            let mut len = [0; 4];
+            conn.read_exact(&mut len).unwrap();
+            let len = u32::from_be_bytes(len) as usize;
+            if len == 0 {
+                return data;
+            }
+            let start = data.len();
+            data.resize(start + len, 0);
+            conn.read_exact(&mut data[start..]).unwrap();
+        }
+    }
+
+    /// Starts a server that reads every request and closes the connection without replying,
+    /// counting the connections.
+    fn dropping_server() -> (SocketAddr, Arc<AtomicU32>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let accepted = Arc::new(AtomicU32::new(0));
+        let counter = accepted.clone();
+        thread::spawn(move || {
+            for conn in listener.incoming() {
+                counter.fetch_add(1, Ordering::SeqCst);
+                read_request(&mut conn.unwrap());
+            }
+        });
+        (addr, accepted)
+    }
+
+    #[test]
+    fn scans_within_budget() {
+        let mut data = Cursor::new(b"This is not a virus.".to_vec());
+        let res = scan_within(
+            "localhost:3310",
+            &mut data,
+            Duration::from_secs(5),
+            RetryPolicy::default(),
+        )
+        .unwrap();
+        assert!(!res.is_infected);
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        let (addr, accepted) = dropping_server();
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            backoff: Duration::from_millis(1),
+        };
+        let err = scan_within(
+            addr,
+            &mut Cursor::new(b"data".to_vec()),
+            Duration::from_secs(5),
+            policy,
+        )
+        .unwrap_err();
+        assert!(err.is_retryable(), "{err:?}");
+        assert_eq!(accepted.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn retries_stop_when_the_budget_is_spent() {
+        let (addr, accepted) = dropping_server();
+        let policy = RetryPolicy {
+            max_attempts: 100,
+            backoff: Duration::from_millis(40),
+        };
+        let started = Instant::now();
+        let err = scan_within(
+            addr,
+            &mut Cursor::new(b"data".to_vec()),
+            Duration::from_millis(200),
+            policy,
+        )
+        .unwrap_err();
+
+        assert!(matches!(err, ClamAVClientError::Timeout), "{err:?}");
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(accepted.load(Ordering::SeqCst) < 100);
+    }
+
+    #[test]
+    fn a_silent_server_times_out() {
+        // Accepts the connection but never replies.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let conns: Vec<_> = listener.incoming().collect();
+            drop(conns);
+        });
+
+        let started = Instant::now();
+        let err = scan_within(
+            addr,
+            &mut Cursor::new(b"data".to_vec()),
+            Duration::from_millis(200),
+            RetryPolicy::default(),
+        )
+        .unwrap_err();
+
+        assert!(matches!(err, ClamAVClientError::Timeout), "{err:?}");
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn input_errors_are_not_retried() {
+        /// Input whose reads time out, counting them.
+        struct TimingOut(u32);
+        impl Read for TimingOut {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                self.0 += 1;
+                Err(std::io::ErrorKind::TimedOut.into())
+            }
+        }
+        impl Seek for TimingOut {
+            fn seek(&mut self, _: SeekFrom) -> std::io::Result<u64> {
+                Ok(0)
+            }
+        }
+
+        // Accepts the connections, which is all the scan gets to before the input fails.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut input = TimingOut(0);
+        let err = scan_within(
+            listener.local_addr().unwrap(),
+            &mut input,
+            Duration::from_secs(5),
+            RetryPolicy::default(),
+        )
+        .unwrap_err();
+
+        assert!(
+            matches!(err, ClamAVClientError::UnableToReadInput(_)),
+            "{err:?}"
+        );
+        assert_eq!(input.0, 1);
+    }
+
+    #[test]
+    fn rewinds_the_input_before_retrying() {
+        // Drops the first connection and answers the second one.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            read_request(&mut listener.accept().unwrap().0);
+            let (mut conn, _) = listener.accept().unwrap();
+            let data = read_request(&mut conn);
+            conn.write_all(b"stream: OK\0").unwrap();
+            data
+        });
+
+        let mut data = Cursor::new(b"skipped This is not a virus.".to_vec());
+        data.seek(SeekFrom::Start(8)).unwrap();
+        let policy = RetryPolicy {
+            max_attempts: 2,
+            backoff: Duration::from_millis(1),
+        };
+        let res = scan_within(addr, &mut data, Duration::from_secs(5), policy).unwrap();
+        assert!(!res.is_infected);
+
+        assert_eq!(server.join().unwrap(), b"This is not a virus.");
+    }
+}
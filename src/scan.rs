@@ -1,15 +1,35 @@
 use std::{
-    io::{Read, Write},
+    io::{self, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    iter,
     net::ToSocketAddrs,
+    thread,
+    time::{Duration, SystemTime},
 };
 
-use crate::{connect_tcp_socket, Byte, ClamAVClientError, ScanResult};
+use crate::{
+    connect_tcp_socket, is_dropped_connection,
+    protocol::{chunk_length_prefix, CHUNK_LENGTH_SIZE, INSTREAM_FOOTER, INSTREAM_HEADER},
+    resolve,
+    responses::check_terminated,
+    transport::{read_reply, trace_sent},
+    version_parsed, Byte, ClamAVClientError, ClamdTransport, ScanResult, ScanTarget, Severity,
+    VersionInfo,
+};
 
-const DEFAULT_CHUNK_SIZE: usize = 4096;
-const HEADER: &[Byte] = b"zINSTREAM\0";
-const FOOTER: &[Byte] = &[0; 4];
+pub(crate) const DEFAULT_CHUNK_SIZE: usize = 4096;
+const WOULD_BLOCK_BACKOFF: Duration = Duration::from_millis(10);
 
-/// Scans something that is [Read] and returns the ClamAV response to the scanned item.
+/// Scans a [ScanTarget] and returns the ClamAV response to the scanned item.
+///
+/// The target can be a reader, a byte slice, a [File](std::fs::File) or a local
+/// [Path](std::path::Path). The input is streamed until it reaches EOF, so sources of unknown
+/// length such as [Stdin](std::io::Stdin), pipes or a [ChildStdout](std::process::ChildStdout)
+/// can be scanned, as can a download while it arrives, eg. the body of an HTTP response, without
+/// buffering it first. Interrupted reads are retried, and a read that returns nothing only ends
+/// the input if the next read doesn't return anything either, so an interactive terminal needs
+/// EOF (Ctrl-D) twice. Slow sources are waited on, including non-blocking ones that return
+/// [WouldBlock](std::io::ErrorKind::WouldBlock), which are polled after a short pause rather
+/// than in a busy loop. A `chunk_size` of `None` or `Some(0)` uses the default of 4096 bytes.
 ///
 /// ```rust
 /// use clamav_tcp;
@@ -17,60 +37,1180 @@ const FOOTER: &[Byte] = &[0; 4];
 /// let res = clamav_tcp::scan("localhost:3310", &mut eicar, None).unwrap();
 /// assert_eq!(1, res.detected_infections.len());
 /// ```
-pub fn scan<A: ToSocketAddrs, D: Read>(
+pub fn scan<A: ToSocketAddrs, T: ScanTarget>(
+    addr: A,
+    target: T,
+    chunk_size: Option<usize>,
+) -> Result<ScanResult, ClamAVClientError> {
+    let mut file = target.into_reader()?;
+    let (reply, _) = scan_with(addr, &mut file, chunk_size, |_| Ok(()))?;
+    parse_reply(&reply, false)
+}
+
+/// Scans a [ScanTarget] like [scan] and classifies the result with `policy`, which maps each
+/// detection name to a [Severity]. The overall severity is the highest of them, or
+/// [Severity::Clean] if nothing was detected.
+///
+/// ```rust
+/// use clamav_tcp::Severity;
+/// let policy = |name: &str| {
+///     if name.starts_with("Win.Test.") { Severity::Low } else { Severity::High }
+/// };
+/// let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+/// let (res, severity) = clamav_tcp::scan_classified("localhost:3310", &mut eicar, None, &policy).unwrap();
+/// assert!(res.is_infected);
+/// assert_eq!(severity, Severity::Low);
+/// ```
+pub fn scan_classified<A: ToSocketAddrs, T: ScanTarget>(
+    addr: A,
+    target: T,
+    chunk_size: Option<usize>,
+    policy: &dyn Fn(&str) -> Severity,
+) -> Result<(ScanResult, Severity), ClamAVClientError> {
+    let res = scan(addr, target, chunk_size)?;
+    let severity = res.severity(policy);
+    Ok((res, severity))
+}
+
+/// Scans a [ScanTarget] like [scan] and checks whether any detection name contains one of
+/// `patterns`, eg. to act only on certain malware families. The whole result is returned too,
+/// so every detection can still be logged.
+///
+/// ```rust
+/// let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+/// let (res, matched) =
+///     clamav_tcp::scan_matching("localhost:3310", &mut eicar, None, &["Ransom.", "EICAR"]).unwrap();
+/// assert!(matched);
+/// println!("detected: {}", res.detections_display());
+/// ```
+pub fn scan_matching<A: ToSocketAddrs, T: ScanTarget>(
+    addr: A,
+    target: T,
+    chunk_size: Option<usize>,
+    patterns: &[&str],
+) -> Result<(ScanResult, bool), ClamAVClientError> {
+    let res = scan(addr, target, chunk_size)?;
+    let matched = res.matches_any(patterns);
+    Ok((res, matched))
+}
+
+/// Scans a [ScanTarget] like [scan], returning only the detection names if it is infected, or
+/// `None` if it is clean.
+///
+/// ```rust
+/// let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+/// match clamav_tcp::scan_simple("localhost:3310", &mut eicar, None) {
+///     Ok(None) => println!("clean"),
+///     Ok(Some(names)) => println!("infected: {}", names.join(", ")),
+///     Err(e) => println!("not scanned: {e}"),
+/// }
+/// ```
+pub fn scan_simple<A: ToSocketAddrs, T: ScanTarget>(
+    addr: A,
+    target: T,
+    chunk_size: Option<usize>,
+) -> Result<Option<Vec<String>>, ClamAVClientError> {
+    Ok(scan(addr, target, chunk_size)?.into_detections())
+}
+
+/// Scans a [ScanTarget] like [scan], but only if ClamAV's signature database was built at most
+/// `max_db_age` ago.
+///
+/// ClamAV's version is checked first, and the scan fails with
+/// [StaleDatabase](ClamAVClientError::StaleDatabase) without sending anything if the database
+/// is older, or if ClamAV doesn't report its build date in a form that can be read. The date is
+/// read as UTC, see [VersionInfo::database_time], so allow for the UTC offset of the ClamAV host.
+///
+/// ```rust
+/// use std::time::Duration;
+/// let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+/// let day = Duration::from_secs(24 * 60 * 60);
+/// match clamav_tcp::scan_requiring_fresh_db("localhost:3310", &mut eicar, None, 3 * day) {
+///     Ok(res) => println!("infected: {}", res.is_infected),
+///     Err(clamav_tcp::ClamAVClientError::StaleDatabase(date)) => println!("stale: {date:?}"),
+///     Err(e) => println!("not scanned: {e}"),
+/// }
+/// ```
+pub fn scan_requiring_fresh_db<A: ToSocketAddrs, T: ScanTarget>(
+    addr: A,
+    target: T,
+    chunk_size: Option<usize>,
+    max_db_age: Duration,
+) -> Result<ScanResult, ClamAVClientError> {
+    // Resolved once, so the version check and the scan reach the same host.
+    let addrs = resolve(addr)?;
+    check_database_age(&version_parsed(&addrs[..])?, max_db_age, SystemTime::now())?;
+    scan(&addrs[..], target, chunk_size)
+}
+
+/// Fails unless the database of `info` was built at most `max_age` before `now`. A build time
+/// after `now`, eg. from clock skew, counts as fresh.
+pub(crate) fn check_database_age(
+    info: &VersionInfo,
+    max_age: Duration,
+    now: SystemTime,
+) -> Result<(), ClamAVClientError> {
+    let stale = || ClamAVClientError::StaleDatabase(info.database_date.clone());
+    let built = info.database_time().ok_or_else(stale)?;
+    match now.duration_since(built) {
+        Ok(age) if age > max_age => Err(stale()),
+        _ => Ok(()),
+    }
+}
+
+/// Byte counts of a single scan, for capacity planning and metrics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanStats {
+    /// Number of chunks the input was streamed to ClamAV in.
+    pub chunks: u64,
+    /// Number of input bytes sent to ClamAV, not counting the INSTREAM framing.
+    pub bytes_sent: u64,
+    /// Size of ClamAV's reply in bytes.
+    pub reply_bytes: u64,
+}
+
+/// Scans something that is [Read] like [scan], also returning how much data was exchanged.
+///
+/// With the `tracing` feature the same counts are also emitted as the `chunks`, `bytes_sent`
+/// and `reply_bytes` fields of a debug event for every scan.
+///
+/// ```rust
+/// use clamav_tcp;
+/// let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+/// let (res, stats) = clamav_tcp::scan_with_stats("localhost:3310", &mut eicar, None).unwrap();
+/// assert_eq!(stats.chunks, 1);
+/// assert_eq!(stats.bytes_sent, std::fs::metadata("resources/eicar.txt").unwrap().len());
+/// ```
+pub fn scan_with_stats<A: ToSocketAddrs, D: Read>(
+    addr: A,
+    file: &mut D,
+    chunk_size: Option<usize>,
+) -> Result<(ScanResult, ScanStats), ClamAVClientError> {
+    let (reply, stats) = scan_with(addr, file, chunk_size, |_| Ok(()))?;
+    Ok((parse_reply(&reply, false)?, stats))
+}
+
+/// Scans something that is [Read] like [scan], returning ClamAV's raw reply together with the
+/// result of parsing it.
+///
+/// Meant for monitoring the parser: a reply that fails to parse is still returned as it was
+/// received, so it can be logged and reported. Only failing to get a reply at all, eg. because
+/// ClamAV can't be reached, fails the whole call.
+///
+/// ```rust
+/// use clamav_tcp;
+/// let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+/// let (raw, parsed) = clamav_tcp::scan_with_parse_status("localhost:3310", &mut eicar, None).unwrap();
+/// if let Err(e) = &parsed {
+///     eprintln!("unparseable reply {raw:?}: {e}");
+/// }
+/// ```
+pub fn scan_with_parse_status<A: ToSocketAddrs, D: Read>(
+    addr: A,
+    file: &mut D,
+    chunk_size: Option<usize>,
+) -> Result<(String, Result<ScanResult, ClamAVClientError>), ClamAVClientError> {
+    let mut stream = connect_tcp_socket(addr)?;
+    scan_with_parse_status_on(&mut stream, INSTREAM_HEADER, file, chunk_size, false)
+}
+
+/// Streams `file` after the INSTREAM command `header` over a new connection, see
+/// [scan_with_parse_status].
+pub(crate) fn scan_with_parse_status_on<S: Read + Write, D: Read>(
+    stream: &mut S,
+    header: &[Byte],
+    file: &mut D,
+    chunk_size: Option<usize>,
+    strict: bool,
+) -> Result<(String, Result<ScanResult, ClamAVClientError>), ClamAVClientError> {
+    let (reply, _) = exchange(stream, header, file, chunk_size, |_| Ok(()))?;
+    let parsed = parse_reply(&reply, strict);
+    Ok((reply, parsed))
+}
+
+/// Result of [scan_report], with what was streamed to ClamAV.
+#[derive(Debug)]
+pub struct ScanReport {
+    /// The scan result for what was streamed.
+    pub result: ScanResult,
+    /// Byte counts of the scan. `bytes_sent` is the number of input bytes ClamAV received.
+    pub stats: ScanStats,
+    /// Length of the input, from its position when the scan started to its end.
+    pub expected_len: u64,
+    /// Whether less than `expected_len` bytes were sent, so only part of the input was scanned.
+    pub truncated: bool,
+}
+
+/// Scans something that is [Read] and [Seek] like [scan], and checks that all of it was sent.
+///
+/// The length of the input is taken before it is streamed. If reading it ends early, eg. because
+/// a file was truncated while it was read or a wrapping reader stops too soon, the report is
+/// marked as `truncated`, so a clean result for part of the input isn't mistaken for a clean
+/// input. ClamAV may also stop the stream early by replying with a detection or an error, in
+/// which case nothing is counted as sent.
+///
+/// ```rust
+/// use clamav_tcp;
+/// let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+/// let report = clamav_tcp::scan_report("localhost:3310", &mut eicar, None).unwrap();
+/// assert!(report.result.is_infected);
+/// assert!(!report.truncated);
+/// ```
+pub fn scan_report<A: ToSocketAddrs, D: Read + Seek>(
+    addr: A,
+    file: &mut D,
+    chunk_size: Option<usize>,
+) -> Result<ScanReport, ClamAVClientError> {
+    let mut stream = connect_tcp_socket(addr)?;
+    scan_report_on(&mut stream, INSTREAM_HEADER, file, chunk_size, false)
+}
+
+/// Streams `file` after the INSTREAM command `header` over a new connection, see [scan_report].
+pub(crate) fn scan_report_on<S: Read + Write, D: Read + Seek>(
+    stream: &mut S,
+    header: &[Byte],
+    file: &mut D,
+    chunk_size: Option<usize>,
+    strict: bool,
+) -> Result<ScanReport, ClamAVClientError> {
+    let expected_len = remaining_len(file).map_err(ClamAVClientError::UnableToReadInput)?;
+    let (reply, stats) = exchange(stream, header, file, chunk_size, |_| Ok(()))?;
+    let result = parse_reply(&reply, strict)?;
+    Ok(ScanReport {
+        result,
+        stats,
+        expected_len,
+        truncated: stats.bytes_sent < expected_len,
+    })
+}
+
+/// Number of bytes from the current position of `s` to its end. The position is kept.
+pub(crate) fn remaining_len<S: Seek>(s: &mut S) -> io::Result<u64> {
+    let pos = s.stream_position()?;
+    let end = s.seek(SeekFrom::End(0))?;
+    s.seek(SeekFrom::Start(pos))?;
+    Ok(end.saturating_sub(pos))
+}
+
+/// Scans the bytes of something that is [Read] and [Seek] that are there when the scan starts,
+/// returning the scan result and the number of bytes scanned.
+///
+/// The length of the input is taken before it is streamed, and nothing past it is read, so a
+/// file that is appended to while it is scanned, eg. a log file, is scanned up to where it ended
+/// when the scan started instead of for as long as it keeps growing. The input is left positioned
+/// after the last byte scanned, where a later scan can pick up.
+///
+/// ```rust
+/// use clamav_tcp;
+/// let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+/// let (res, scanned) = clamav_tcp::scan_snapshot("localhost:3310", &mut eicar, None).unwrap();
+/// assert!(res.is_infected);
+/// assert_eq!(scanned, std::fs::metadata("resources/eicar.txt").unwrap().len());
+/// ```
+pub fn scan_snapshot<A: ToSocketAddrs, D: Read + Seek>(
+    addr: A,
+    file: &mut D,
+    chunk_size: Option<usize>,
+) -> Result<(ScanResult, u64), ClamAVClientError> {
+    let mut stream = connect_tcp_socket(addr)?;
+    scan_snapshot_on(&mut stream, INSTREAM_HEADER, file, chunk_size, false)
+}
+
+/// Streams `file` up to its current end after the INSTREAM command `header` over a new
+/// connection, see [scan_snapshot].
+pub(crate) fn scan_snapshot_on<S: Read + Write, D: Read + Seek>(
+    stream: &mut S,
+    header: &[Byte],
+    file: &mut D,
+    chunk_size: Option<usize>,
+    strict: bool,
+) -> Result<(ScanResult, u64), ClamAVClientError> {
+    let len = remaining_len(file).map_err(ClamAVClientError::UnableToReadInput)?;
+    let (reply, stats) = exchange(stream, header, &mut file.take(len), chunk_size, |_| Ok(()))?;
+    Ok((parse_reply(&reply, strict)?, stats.bytes_sent))
+}
+
+/// Scans something that is [Read] while copying every chunk to `sink`, so the data only has to be read once.
+///
+/// The sink is flushed once the whole input has been streamed. If the scan fails midway,
+/// the sink may hold only part of the input.
+///
+/// ```rust
+/// use clamav_tcp;
+/// let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+/// let mut copy = vec![];
+/// let res = clamav_tcp::scan_tee("localhost:3310", &mut eicar, &mut copy, None).unwrap();
+/// assert_eq!(1, res.detected_infections.len());
+/// assert_eq!(copy, std::fs::read("resources/eicar.txt").unwrap());
+/// ```
+pub fn scan_tee<A: ToSocketAddrs, R: Read, W: Write>(
+    addr: A,
+    source: &mut R,
+    sink: &mut W,
+    chunk_size: Option<usize>,
+) -> Result<ScanResult, ClamAVClientError> {
+    let (reply, _) = scan_with(addr, source, chunk_size, |chunk| {
+        sink.write_all(chunk)
+            .map_err(ClamAVClientError::UnableToWriteToSink)
+    })?;
+    sink.flush()
+        .map_err(ClamAVClientError::UnableToWriteToSink)?;
+
+    parse_reply(&reply, false)
+}
+
+/// Scans something that is [Read] like [scan], calling `before_chunk` before every chunk is sent
+/// with the number of bytes streamed once that chunk is sent, eg. to cap the upload rate.
+///
+/// `before_chunk` runs on the calling thread, so sleeping in it blocks the scan (and the caller)
+/// for that long.
+///
+/// ```rust
+/// use std::{thread, time::{Duration, Instant}};
+/// // At most 1 MiB/s.
+/// let rate = 1024 * 1024;
+/// let started = Instant::now();
+/// let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+/// let res = clamav_tcp::scan_throttled("localhost:3310", &mut eicar, None, |sent| {
+///     let due = Duration::from_secs_f64(sent as f64 / rate as f64);
+///     thread::sleep(due.saturating_sub(started.elapsed()));
+/// })
+/// .unwrap();
+/// assert!(res.is_infected);
+/// ```
+pub fn scan_throttled<A: ToSocketAddrs, D: Read>(
+    addr: A,
+    file: &mut D,
+    chunk_size: Option<usize>,
+    before_chunk: impl FnMut(usize),
+) -> Result<ScanResult, ClamAVClientError> {
+    let mut file = Throttled {
+        inner: file,
+        before_chunk,
+        sent: 0,
+    };
+    let (reply, _) = scan_with(addr, &mut file, chunk_size, |_| Ok(()))?;
+    parse_reply(&reply, false)
+}
+
+/// A reader calling `before_chunk` with the running total after every read, which
+/// [write_instream] does right before sending what was read.
+struct Throttled<'a, D, F> {
+    inner: &'a mut D,
+    before_chunk: F,
+    sent: usize,
+}
+
+impl<D: Read, F: FnMut(usize)> Read for Throttled<'_, D, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if read > 0 {
+            self.sent += read;
+            (self.before_chunk)(self.sent);
+        }
+        Ok(read)
+    }
+}
+
+/// Scans something that is [Read] while feeding every chunk to the hasher `H`, so the input can be
+/// hashed without reading it twice. Returns the scan result together with the finalized digest.
+///
+/// Requires the `digest` feature.
+///
+/// ```rust
+/// use sha2::{Digest, Sha256};
+/// let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+/// let (res, digest) = clamav_tcp::scan_hashed::<_, _, Sha256>("localhost:3310", &mut eicar, None).unwrap();
+/// assert!(res.is_infected);
+/// assert_eq!(digest, Sha256::digest(std::fs::read("resources/eicar.txt").unwrap()).to_vec());
+/// ```
+#[cfg(feature = "digest")]
+pub fn scan_hashed<A: ToSocketAddrs, D: Read, H: digest::Digest>(
+    addr: A,
+    file: &mut D,
+    chunk_size: Option<usize>,
+) -> Result<(ScanResult, Vec<u8>), ClamAVClientError> {
+    let mut hasher = H::new();
+    let (reply, _) = scan_with(addr, file, chunk_size, |chunk| {
+        hasher.update(chunk);
+        Ok(())
+    })?;
+    Ok((parse_reply(&reply, false)?, hasher.finalize().to_vec()))
+}
+
+/// Scans gzip-compressed input, decompressing it on the fly so ClamAV scans the plain content.
+///
+/// Input made of several concatenated gzip members is decompressed as a whole. Corrupt or
+/// truncated input fails with [ClamAVClientError::InvalidCompressedInput], separately from errors
+/// while talking to ClamAV. Requires the `flate2` feature.
+///
+/// ```rust
+/// use std::io::Write;
+/// use flate2::{write::GzEncoder, Compression};
+/// let mut encoder = GzEncoder::new(vec![], Compression::default());
+/// encoder.write_all(&std::fs::read("resources/eicar.txt").unwrap()).unwrap();
+/// let compressed = encoder.finish().unwrap();
+///
+/// let res = clamav_tcp::scan_gzip("localhost:3310", &mut &compressed[..], None).unwrap();
+/// assert!(res.is_infected);
+/// ```
+#[cfg(feature = "flate2")]
+pub fn scan_gzip<A: ToSocketAddrs, R: Read>(
+    addr: A,
+    compressed: &mut R,
+    chunk_size: Option<usize>,
+) -> Result<ScanResult, ClamAVClientError> {
+    let mut plain = Decompressing {
+        decoder: flate2::read::MultiGzDecoder::new(compressed),
+        error: None,
+    };
+    match scan_with(addr, &mut plain, chunk_size, |_| Ok(())) {
+        Ok((reply, _)) => parse_reply(&reply, false),
+        Err(e) => Err(plain
+            .error
+            .map(ClamAVClientError::InvalidCompressedInput)
+            .unwrap_or(e)),
+    }
+}
+
+/// Keeps the error of a failed decompression, which would otherwise be reported like a failure
+/// to write to ClamAV.
+#[cfg(feature = "flate2")]
+struct Decompressing<R: Read> {
+    decoder: flate2::read::MultiGzDecoder<R>,
+    error: Option<io::Error>,
+}
+
+#[cfg(feature = "flate2")]
+impl<R: Read> Read for Decompressing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.decoder.read(buf) {
+            // Interrupted and would-block reads are retried, they don't end the scan.
+            Err(e) if !matches!(e.kind(), ErrorKind::Interrupted | ErrorKind::WouldBlock) => {
+                let kind = e.kind();
+                self.error = Some(e);
+                Err(io::Error::from(kind))
+            }
+            res => res,
+        }
+    }
+}
+
+/// Scans something that is [Read], labeling the result and any error with `label`.
+///
+/// The label is stored in [ScanResult::scanned_item] and errors are wrapped in
+/// [ClamAVClientError::Labeled]. With the `tracing` feature the scan runs in a span carrying the label.
+///
+/// ```rust
+/// use clamav_tcp;
+/// let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+/// let res = clamav_tcp::scan_labeled("localhost:3310", "eicar.txt", &mut eicar, None).unwrap();
+/// assert_eq!(res.scanned_item.as_deref(), Some("eicar.txt"));
+///
+/// let err = clamav_tcp::scan_labeled("hello world", "eicar.txt", &mut eicar, None).unwrap_err();
+/// assert!(err.to_string().starts_with("eicar.txt: "));
+/// ```
+pub fn scan_labeled<A: ToSocketAddrs, D: Read>(
     addr: A,
+    label: &str,
+    file: &mut D,
+    chunk_size: Option<usize>,
+) -> Result<ScanResult, ClamAVClientError> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::info_span!("scan", label).entered();
+
+    match scan(addr, file, chunk_size) {
+        Ok(res) => Ok(ScanResult {
+            scanned_item: Some(label.to_string()),
+            ..res
+        }),
+        Err(e) => Err(ClamAVClientError::Labeled {
+            label: label.to_string(),
+            source: Box::new(e),
+        }),
+    }
+}
+
+/// Scans something that is [Read] over an already open connection, leaving the connection usable.
+///
+/// Only the reply to this scan is read, so the connection can be reused for further commands.
+/// It is only borrowed, so it can stay where it is kept, eg. in a pool, between scans.
+/// The connection must be expecting a command. ClamAV closes a connection after its first
+/// reply unless it was put in a session with `zIDSESSION\0` first.
+///
+/// ```rust
+/// use std::{io::Write, net::TcpStream};
+/// use clamav_tcp;
+/// let mut conn = TcpStream::connect("localhost:3310").unwrap();
+/// conn.write_all(b"zIDSESSION\0").unwrap();
+/// for _ in 0..2 {
+///     let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+///     let res = clamav_tcp::scan_on(&mut conn, &mut eicar, None).unwrap();
+///     assert_eq!(1, res.detected_infections.len());
+/// }
+/// conn.write_all(b"zEND\0").unwrap();
+/// ```
+pub fn scan_on<T: ClamdTransport + ?Sized, D: Read>(
+    conn: &mut T,
     file: &mut D,
     chunk_size: Option<usize>,
 ) -> Result<ScanResult, ClamAVClientError> {
-    let chunk_size = chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE);
+    write_instream(conn, file, chunk_size, |_| Ok(()))?;
+
+    parse_reply(&read_reply(conn)?, false)
+}
+
+/// Scans input that is already split into chunks, eg. the frames of another protocol, without
+/// wrapping it in a [Read].
+///
+/// Every chunk is sent as it is, split into several INSTREAM chunks if it is longer than
+/// `chunk_size`. Empty chunks are skipped, as an empty INSTREAM chunk would end the input. A
+/// `chunk_size` of `None` or `Some(0)` uses the default of 4096 bytes.
+///
+/// ```rust
+/// use clamav_tcp;
+/// let eicar = std::fs::read("resources/eicar.txt").unwrap();
+/// let (head, tail) = eicar.split_at(10);
+/// let res = clamav_tcp::scan_chunks("localhost:3310", [head, &[], tail], None).unwrap();
+/// assert!(res.is_infected);
+/// ```
+pub fn scan_chunks<A, I>(
+    addr: A,
+    chunks: I,
+    chunk_size: Option<usize>,
+) -> Result<ScanResult, ClamAVClientError>
+where
+    A: ToSocketAddrs,
+    I: IntoIterator,
+    I::Item: AsRef<[Byte]>,
+{
+    let mut stream = connect_tcp_socket(addr)?;
+    scan_chunks_on(&mut stream, INSTREAM_HEADER, chunks, chunk_size, false)
+}
+
+/// Streams `chunks` after the INSTREAM command `header` over a new connection, see [scan_chunks].
+pub(crate) fn scan_chunks_on<S: Read + Write, I>(
+    stream: &mut S,
+    header: &[Byte],
+    chunks: I,
+    chunk_size: Option<usize>,
+    strict: bool,
+) -> Result<ScanResult, ClamAVClientError>
+where
+    I: IntoIterator,
+    I::Item: AsRef<[Byte]>,
+{
+    let (reply, _) = exchange_with(stream, |stream| {
+        write_chunks(stream, header, chunks, chunk_size)
+    })?;
+    parse_reply(&reply, strict)
+}
+
+/// Writes an INSTREAM request for `chunks`, splitting those longer than `chunk_size`.
+fn write_chunks<S: Write, I>(
+    stream: &mut S,
+    header: &[Byte],
+    chunks: I,
+    chunk_size: Option<usize>,
+) -> Result<ScanStats, ClamAVClientError>
+where
+    I: IntoIterator,
+    I::Item: AsRef<[Byte]>,
+{
+    let chunk_size = chunk_size
+        .filter(|&size| size > 0)
+        .unwrap_or(DEFAULT_CHUNK_SIZE)
+        .min(u32::MAX as usize);
+    let capacity = header.len() + CHUNK_LENGTH_SIZE + chunk_size + INSTREAM_FOOTER.len();
+    let mut stream = BufWriter::with_capacity(capacity, stream);
+
+    trace_sent(header);
+    stream
+        .write_all(header)
+        .map_err(ClamAVClientError::UnableToWriteToStream)?;
+
+    let mut stats = ScanStats::default();
+    for chunk in chunks {
+        // `chunks` yields nothing for an empty chunk, so it is never sent as a zero length.
+        for part in chunk.as_ref().chunks(chunk_size) {
+            let len = chunk_length_prefix(part.len() as u32);
+            trace_sent(&len);
+            stream
+                .write_all(&len)
+                .and_then(|_| stream.write_all(part))
+                .map_err(ClamAVClientError::UnableToWriteToStream)?;
+            stats.chunks += 1;
+            stats.bytes_sent += part.len() as u64;
+        }
+    }
+
+    trace_sent(INSTREAM_FOOTER);
+    stream
+        .write_all(INSTREAM_FOOTER)
+        .and_then(|_| stream.flush())
+        .map_err(ClamAVClientError::UnableToWriteToStream)?;
+    Ok(stats)
+}
+
+/// Reads `chunks` one after the other, returning at most one chunk per read, so streaming it
+/// keeps the chunks apart like [write_chunks] does. Empty chunks are skipped.
+pub(crate) struct ChunksReader<I: Iterator> {
+    chunks: iter::Fuse<I>,
+    current: Option<I::Item>,
+    /// Bytes of `current` that were already read.
+    pos: usize,
+}
+
+impl<I: Iterator> ChunksReader<I> {
+    pub(crate) fn new(chunks: impl IntoIterator<IntoIter = I>) -> Self {
+        ChunksReader {
+            chunks: chunks.into_iter().fuse(),
+            current: None,
+            pos: 0,
+        }
+    }
+}
+
+impl<I> Read for ChunksReader<I>
+where
+    I: Iterator,
+    I::Item: AsRef<[Byte]>,
+{
+    fn read(&mut self, buf: &mut [Byte]) -> io::Result<usize> {
+        loop {
+            if let Some(chunk) = &self.current {
+                let rest = &chunk.as_ref()[self.pos..];
+                if !rest.is_empty() {
+                    let len = rest.len().min(buf.len());
+                    buf[..len].copy_from_slice(&rest[..len]);
+                    self.pos += len;
+                    return Ok(len);
+                }
+            }
+            match self.chunks.next() {
+                Some(chunk) => {
+                    self.current = Some(chunk);
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Streams `file` to ClamAV, calling `on_chunk` with every chunk after it has been sent, and returns the raw reply.
+pub(crate) fn scan_with<A: ToSocketAddrs, D: Read>(
+    addr: A,
+    file: &mut D,
+    chunk_size: Option<usize>,
+    on_chunk: impl FnMut(&[Byte]) -> Result<(), ClamAVClientError>,
+) -> Result<(String, ScanStats), ClamAVClientError> {
     let mut stream = connect_tcp_socket(addr)?;
+    exchange(&mut stream, INSTREAM_HEADER, file, chunk_size, on_chunk)
+}
+
+/// Streams `file` over `stream` after the INSTREAM command `header` and reads the reply until
+/// ClamAV closes the connection.
+///
+/// ClamAV may reply and close the connection before the whole input has been sent, eg. when the
+/// input exceeds its size limit. The write then fails, and if ClamAV's reply reports a detection
+/// or an error it is returned instead of the write error. The returned [ScanStats] only count the
+/// reply in that case.
+pub(crate) fn exchange<S: Read + Write, D: Read>(
+    stream: &mut S,
+    header: &[Byte],
+    file: &mut D,
+    chunk_size: Option<usize>,
+    on_chunk: impl FnMut(&[Byte]) -> Result<(), ClamAVClientError>,
+) -> Result<(String, ScanStats), ClamAVClientError> {
+    exchange_closing(stream, header, file, chunk_size, on_chunk, |_| Ok(()))
+}
+
+/// Streams `file` like [exchange], calling `close_write` once the footer has been sent and
+/// flushed, eg. to shut down the write half of a connection that is only used for this scan.
+pub(crate) fn exchange_closing<S: Read + Write, D: Read>(
+    stream: &mut S,
+    header: &[Byte],
+    file: &mut D,
+    chunk_size: Option<usize>,
+    on_chunk: impl FnMut(&[Byte]) -> Result<(), ClamAVClientError>,
+    close_write: impl FnOnce(&mut S) -> io::Result<()>,
+) -> Result<(String, ScanStats), ClamAVClientError> {
+    exchange_with(stream, |stream| {
+        let stats = write_instream_with(stream, header, file, chunk_size, on_chunk)?;
+        close_write(stream).map_err(ClamAVClientError::UnableToWriteToStream)?;
+        Ok(stats)
+    })
+}
+
+/// Sends an INSTREAM request over `stream` with `write` and reads the reply, like [exchange].
+fn exchange_with<S: Read + Write>(
+    stream: &mut S,
+    write: impl FnOnce(&mut S) -> Result<ScanStats, ClamAVClientError>,
+) -> Result<(String, ScanStats), ClamAVClientError> {
+    let mut stats = match write(stream) {
+        Ok(stats) => stats,
+        // Only a failed write means ClamAV may have replied and closed the connection, a failed
+        // read of the input leaves it waiting for the rest.
+        Err(ClamAVClientError::UnableToWriteToStream(e)) if is_dropped_connection(&e) => {
+            let reply = early_reply(stream).ok_or(ClamAVClientError::UnableToWriteToStream(e))?;
+            let stats = ScanStats {
+                reply_bytes: reply.len() as u64,
+                ..ScanStats::default()
+            };
+            return Ok((reply, stats));
+        }
+        Err(e) => return Err(e),
+    };
+
+    let buf = read_scan_reply(stream)?;
+    stats.reply_bytes = buf.len() as u64;
+
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        chunks = stats.chunks,
+        bytes_sent = stats.bytes_sent,
+        reply_bytes = stats.reply_bytes,
+        "instream finished"
+    );
+
+    Ok((buf, stats))
+}
+
+/// Reads a reply ClamAV sent before closing the connection mid-stream, if it reports a detection
+/// or an error. A clean result for a partial stream is meaningless, so it is ignored.
+pub(crate) fn early_reply<S: Read>(stream: &mut S) -> Option<String> {
+    let mut bytes = vec![];
+    // The connection may be reset after the reply arrived, what was read until then is enough.
+    let _ = stream.read_to_end(&mut bytes);
+    let reply = String::from_utf8_lossy(&bytes).into_owned();
+    match reply.parse::<ScanResult>() {
+        Ok(res) if res.is_infected => Some(reply),
+        Err(ClamAVClientError::SizeLimitExceeded | ClamAVClientError::ClamdError(_)) => Some(reply),
+        _ => None,
+    }
+}
+
+/// Reads a reply to INSTREAM until ClamAV closes the connection.
+///
+/// A connection cut in the middle of a multibyte character leaves invalid UTF-8 at the end of an
+/// otherwise complete reply. The invalid bytes are then replaced, and the reply is kept if a
+/// result can still be parsed from it.
+pub(crate) fn read_scan_reply<S: Read + ?Sized>(
+    stream: &mut S,
+) -> Result<String, ClamAVClientError> {
+    let mut bytes = vec![];
+    stream
+        .read_to_end(&mut bytes)
+        .map_err(ClamAVClientError::InvalidUTf8)?;
+    let invalid = match String::from_utf8(bytes) {
+        Ok(reply) => return Ok(reply),
+        Err(invalid) => invalid,
+    };
+
+    let reply = String::from_utf8_lossy(invalid.as_bytes()).into_owned();
+    match reply.parse::<ScanResult>() {
+        Err(ClamAVClientError::UnableToParseResponse(_)) => Err(ClamAVClientError::InvalidUTf8(
+            io::Error::new(ErrorKind::InvalidData, invalid.utf8_error()),
+        )),
+        _ => {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                valid_up_to = invalid.utf8_error().valid_up_to(),
+                "replaced invalid UTF-8 in reply"
+            );
+            Ok(reply)
+        }
+    }
+}
+
+/// Parses a reply to INSTREAM. With `strict`, the reply must be properly terminated.
+pub(crate) fn parse_reply(reply: &str, strict: bool) -> Result<ScanResult, ClamAVClientError> {
+    // ClamAV always replies after the footer, so an empty reply must not pass as clean.
+    if reply.is_empty() {
+        return Err(ClamAVClientError::EmptyResponse);
+    }
+    if strict {
+        check_terminated(reply)?;
+    }
+    let parsed = reply.parse::<ScanResult>()?;
+
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        is_infected = parsed.is_infected,
+        detected_infections = ?parsed.detected_infections,
+        "scan finished"
+    );
+
+    Ok(parsed)
+}
+
+/// Writes an INSTREAM request for `file` to `stream`.
+///
+/// Only a read of `Ok(0)` ends the stream. Reads that are interrupted are retried straight away
+/// and reads that would block are retried after a short pause, so slow sources such as pipes
+/// and sockets are streamed until they reach EOF.
+pub(crate) fn write_instream<S: Write + ?Sized, D: Read>(
+    stream: &mut S,
+    file: &mut D,
+    chunk_size: Option<usize>,
+    on_chunk: impl FnMut(&[Byte]) -> Result<(), ClamAVClientError>,
+) -> Result<ScanStats, ClamAVClientError> {
+    write_instream_with(stream, INSTREAM_HEADER, file, chunk_size, on_chunk)
+}
+
+/// Writes an INSTREAM request like [write_instream], starting it with the command `header`.
+fn write_instream_with<S: Write + ?Sized, D: Read>(
+    stream: &mut S,
+    header: &[Byte],
+    file: &mut D,
+    chunk_size: Option<usize>,
+    mut on_chunk: impl FnMut(&[Byte]) -> Result<(), ClamAVClientError>,
+) -> Result<ScanStats, ClamAVClientError> {
+    // A zero-sized buffer would read `Ok(0)` straight away and end the stream before any data is sent.
+    let chunk_size = chunk_size
+        .filter(|&size| size > 0)
+        .unwrap_or(DEFAULT_CHUNK_SIZE);
+
+    // Buffer a whole frame so the header, a chunk and its length prefix go out in one write.
+    // Small inputs are sent with a single write, footer included.
+    let capacity = header.len() + CHUNK_LENGTH_SIZE + chunk_size + INSTREAM_FOOTER.len();
+    let mut stream = BufWriter::with_capacity(capacity, stream);
 
     // Write header
+    trace_sent(header);
     stream
-        .write_all(HEADER)
+        .write_all(header)
         .map_err(ClamAVClientError::UnableToWriteToStream)?;
 
     // Write filesize
+    let mut stats = ScanStats::default();
     let mut buf = vec![0; chunk_size];
     loop {
-        let stream_portion_len = file
-            .read(&mut buf[..])
-            .map_err(ClamAVClientError::UnableToWriteToStream)?;
+        let stream_portion_len =
+            read_chunk(file, &mut buf[..]).map_err(ClamAVClientError::UnableToReadInput)?;
         if stream_portion_len != 0 {
             // Write the header to the stream. This is the size of the current chunk in big endian.
+            let len = chunk_length_prefix(stream_portion_len as u32);
+            trace_sent(&len);
             stream
-                .write_all(&(stream_portion_len as u32).to_be_bytes())
+                .write_all(&len)
                 .map_err(ClamAVClientError::UnableToWriteToStream)?;
             stream
                 .write_all(&buf[0..stream_portion_len])
                 .map_err(ClamAVClientError::UnableToWriteToStream)?;
+            on_chunk(&buf[0..stream_portion_len])?;
+            stats.chunks += 1;
+            stats.bytes_sent += stream_portion_len as u64;
         } else {
             // Write footer
+            trace_sent(INSTREAM_FOOTER);
             stream
-                .write_all(FOOTER)
+                .write_all(INSTREAM_FOOTER)
                 .map_err(ClamAVClientError::UnableToWriteToStream)?;
             break;
         }
     }
 
-    let mut buf = String::new();
     stream
-        .read_to_string(&mut buf)
-        .map_err(ClamAVClientError::InvalidUTf8)?;
+        .flush()
+        .map_err(ClamAVClientError::UnableToWriteToStream)?;
 
-    let parsed = buf.parse::<ScanResult>()?;
+    Ok(stats)
+}
 
-    Ok(parsed)
+/// Buffers `file` for sending it as a single INSTREAM chunk if it is at most `max_len` bytes long.
+///
+/// Returns the buffered bytes and the chunk size to stream them followed by the rest of `file`
+/// with: the length of the whole input if it fits, otherwise `chunk_size`, so longer inputs are
+/// still streamed in chunks. At most `max_len + 1` bytes are buffered.
+pub(crate) fn buffer_single_chunk<D: Read>(
+    file: &mut D,
+    max_len: usize,
+    chunk_size: usize,
+) -> io::Result<(Vec<Byte>, usize)> {
+    let mut buffered = vec![];
+    let mut buf = vec![0; chunk_size.max(1)];
+    loop {
+        let want = (max_len + 1 - buffered.len()).min(buf.len());
+        let read = read_chunk(file, &mut buf[..want])?;
+        if read == 0 {
+            let len = buffered.len();
+            return Ok((buffered, len));
+        }
+        buffered.extend_from_slice(&buf[..read]);
+        if buffered.len() > max_len {
+            return Ok((buffered, chunk_size));
+        }
+    }
+}
+
+/// Reads the next chunk from `file`, retrying reads that were interrupted or would block.
+///
+/// Returns 0 only at the end of the input. A read that returns nothing is confirmed by a second
+/// one, as ending the stream early would get a clean result for part of the input. The second
+/// read is the last, so a source that keeps returning nothing still ends, though a terminal
+/// waits for EOF again after the first.
+fn read_chunk<D: Read>(file: &mut D, buf: &mut [Byte]) -> io::Result<usize> {
+    let mut empty_reads = 0;
+    loop {
+        match file.read(buf) {
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(WOULD_BLOCK_BACKOFF),
+            Ok(0) if empty_reads == 0 && !buf.is_empty() => empty_reads += 1,
+            res => return res,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::transport::mock::MockTransport;
+
+    #[test]
+    fn rejects_stale_or_undated_databases() {
+        let info = |reply: &str| reply.parse::<VersionInfo>().unwrap();
+        let fresh = info("ClamAV 1.0.0/26734/Mon Nov 28 08:17:05 2022\0");
+        let built = fresh.database_time().unwrap();
+        let day = Duration::from_secs(24 * 60 * 60);
+
+        check_database_age(&fresh, day, built + day).unwrap();
+        // Built after now, eg. when the clocks disagree.
+        check_database_age(&fresh, day, built - day).unwrap();
+        assert!(matches!(
+            check_database_age(&fresh, day, built + 2 * day),
+            Err(ClamAVClientError::StaleDatabase(Some(date))) if date == "Mon Nov 28 08:17:05 2022"
+        ));
+
+        for undated in ["ClamAV 0.103.8\0", "ClamAV 1.0.0/26734/yesterday\0"] {
+            let res = check_database_age(&info(undated), day, built);
+            assert!(
+                matches!(res, Err(ClamAVClientError::StaleDatabase(_))),
+                "{undated}"
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_raw_reply_next_to_parse_result() {
+        let mut conn = MockTransport::new(b"stream: Win.Test.EICAR_HDB-1 FOUND\0");
+        let (raw, parsed) =
+            scan_with_parse_status_on(&mut conn, INSTREAM_HEADER, &mut &b"eicar"[..], None, false)
+                .unwrap();
+        assert_eq!(raw, "stream: Win.Test.EICAR_HDB-1 FOUND\0");
+        assert!(parsed.unwrap().is_infected);
+
+        let mut conn = MockTransport::new(b"stream? garbled\0");
+        let (raw, parsed) =
+            scan_with_parse_status_on(&mut conn, INSTREAM_HEADER, &mut &b"data"[..], None, false)
+                .unwrap();
+        assert_eq!(raw, "stream? garbled\0");
+        assert!(matches!(
+            parsed,
+            Err(ClamAVClientError::UnableToParseResponse(_))
+        ));
+
+        let res = scan_with_parse_status("asd", &mut &b"data"[..], None);
+        assert!(matches!(
+            res,
+            Err(ClamAVClientError::InvalidSocketAddress(_))
+        ));
+    }
+
+    #[test]
+    fn remaining_len_keeps_position() {
+        let mut cursor = io::Cursor::new(vec![0; 100]);
+        cursor.seek(SeekFrom::Start(40)).unwrap();
+        assert_eq!(remaining_len(&mut cursor).unwrap(), 60);
+        assert_eq!(cursor.position(), 40);
+    }
+
+    #[test]
+    fn empty_read_mid_stream_does_not_end_the_input() {
+        /// Returns `Ok(0)` once after the first read, then the rest of the data.
+        struct Hiccup<'a> {
+            data: &'a [u8],
+            reads: usize,
+        }
+        impl Read for Hiccup<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.reads += 1;
+                if self.reads == 2 {
+                    return Ok(0);
+                }
+                let len = buf.len().min(self.data.len()).min(4);
+                buf[..len].copy_from_slice(&self.data[..len]);
+                self.data = &self.data[len..];
+                Ok(len)
+            }
+        }
+
+        let mut conn = MockTransport::new(b"stream: OK\0");
+        let mut input = Hiccup {
+            data: b"abcdefgh",
+            reads: 0,
+        };
+        let (_, stats) =
+            exchange(&mut conn, INSTREAM_HEADER, &mut input, Some(64), |_| Ok(())).unwrap();
+        assert_eq!(stats.bytes_sent, 8);
+        assert_eq!(
+            conn.written,
+            [
+                INSTREAM_HEADER,
+                &4u32.to_be_bytes(),
+                b"abcd",
+                &4u32.to_be_bytes(),
+                b"efgh",
+                INSTREAM_FOOTER,
+            ]
+            .concat()
+        );
+    }
+
     #[test]
+    fn streams_given_chunks_without_empty_ones() {
+        let mut conn = MockTransport::new(b"stream: OK\0");
+        let chunks: Vec<&[u8]> = vec![b"ab", b"", b"cdefg"];
+        let res = scan_chunks_on(&mut conn, INSTREAM_HEADER, chunks, Some(4), false).unwrap();
+        assert!(!res.is_infected);
+        assert_eq!(
+            conn.written,
+            [
+                INSTREAM_HEADER,
+                &2u32.to_be_bytes(),
+                b"ab",
+                &4u32.to_be_bytes(),
+                b"cdef",
+                &1u32.to_be_bytes(),
+                b"g",
+                INSTREAM_FOOTER,
+            ]
+            .concat()
+        );
+
+        let mut conn = MockTransport::new(b"stream: OK\0");
+        scan_chunks_on(
+            &mut conn,
+            INSTREAM_HEADER,
+            Vec::<Vec<u8>>::new(),
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(conn.written, [INSTREAM_HEADER, INSTREAM_FOOTER].concat());
+    }
+
+    #[test]
+    fn reports_input_that_ends_before_its_length() {
+        /// Reports the full length when seeked, but stops reading after `limit` bytes.
+        struct EarlyEof {
+            inner: io::Cursor<Vec<u8>>,
+            limit: u64,
+        }
+        impl Read for EarlyEof {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let left = self.limit.saturating_sub(self.inner.position()) as usize;
+                let len = buf.len().min(left);
+                self.inner.read(&mut buf[..len])
+            }
+        }
+        impl Seek for EarlyEof {
+            fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+                self.inner.seek(pos)
+            }
+        }
+
+        let mut conn = MockTransport::new(b"stream: OK\0");
+        let mut input = io::Cursor::new(vec![0; 100]);
+        let report =
+            scan_report_on(&mut conn, INSTREAM_HEADER, &mut input, Some(16), false).unwrap();
+        assert_eq!(report.expected_len, 100);
+        assert_eq!(report.stats.bytes_sent, 100);
+        assert!(!report.truncated);
+
+        let mut conn = MockTransport::new(b"stream: OK\0");
+        let mut input = EarlyEof {
+            inner: io::Cursor::new(vec![0; 100]),
+            limit: 60,
+        };
+        let report =
+            scan_report_on(&mut conn, INSTREAM_HEADER, &mut input, Some(16), false).unwrap();
+        assert!(!report.result.is_infected);
+        assert_eq!(report.expected_len, 100);
+        assert_eq!(report.stats.bytes_sent, 60);
+        assert!(report.truncated);
+    }
+
+    #[test]
+    fn snapshot_stops_at_the_length_when_the_scan_started() {
+        /// Reports `len` when seeked to its end, but never runs out of data, like a file that
+        /// is appended to faster than it is read.
+        struct Growing {
+            pos: u64,
+            len: u64,
+        }
+        impl Read for Growing {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                buf.fill(b'a');
+                self.pos += buf.len() as u64;
+                self.len = self.len.max(self.pos);
+                Ok(buf.len())
+            }
+        }
+        impl Seek for Growing {
+            fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+                self.pos = match pos {
+                    SeekFrom::Start(n) => n,
+                    SeekFrom::End(n) => self.len.saturating_add_signed(n),
+                    SeekFrom::Current(n) => self.pos.saturating_add_signed(n),
+                };
+                Ok(self.pos)
+            }
+        }
+
+        let mut conn = MockTransport::new(b"stream: OK\0");
+        let mut input = Growing { pos: 10, len: 100 };
+        let (res, scanned) =
+            scan_snapshot_on(&mut conn, INSTREAM_HEADER, &mut input, Some(16), false).unwrap();
+        assert!(!res.is_infected);
+        assert_eq!(scanned, 90);
+        assert_eq!(input.pos, 100);
+        assert!(conn.written.ends_with(INSTREAM_FOOTER));
+        assert_eq!(
+            conn.written.len(),
+            INSTREAM_HEADER.len() + 90 + 6 * CHUNK_LENGTH_SIZE + INSTREAM_FOOTER.len()
+        );
+    }
+
+    #[test]
+    fn keeps_reply_followed_by_truncated_character() {
+        // The first two bytes of "€", cut off by the connection closing.
+        let mut conn = MockTransport::new(b"stream: Win.Test.EICAR_HDB-1 FOUND\0\xe2\x82");
+        let reply = read_scan_reply(&mut conn).unwrap();
+        let res = parse_reply(&reply, false).unwrap();
+        assert_eq!(res.detected_infections, vec!["Win.Test.EICAR_HDB-1"]);
+
+        let mut conn = MockTransport::new(b"stream: OK\0\xe2\x82");
+        assert!(
+            !parse_reply(&read_scan_reply(&mut conn).unwrap(), false)
+                .unwrap()
+                .is_infected
+        );
+
+        // Nothing usable was received before the invalid bytes.
+        let mut conn = MockTransport::new(b"stre\xe2\x82");
+        let err = read_scan_reply(&mut conn).unwrap_err();
+        assert_eq!(err.io_error_kind(), Some(ErrorKind::InvalidData));
+    }
+
+    #[test]
+    // Scanning through `&mut` as before ScanTarget existed must keep compiling.
+    #[allow(clippy::needless_borrows_for_generic_args)]
     fn can_scan_buf() {
         let mut buf = "This is not a virus.".as_bytes();
         let res = scan("localhost:3310", &mut buf, None).unwrap();
-        assert_eq!(res.is_infected, false);
+        assert!(!res.is_infected);
     }
 
     #[test]
@@ -84,15 +1224,502 @@ mod tests {
     fn detects_eicar() {
         let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
         let res = scan("localhost:3310", &mut eicar, None).unwrap();
-				println!("{:?}", res.detected_infections);
+        println!("{:?}", res.detected_infections);
         assert_eq!(1, res.detected_infections.len());
     }
 
     #[test]
+    fn classifies_detections() {
+        let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+        let (res, severity) =
+            scan_classified("localhost:3310", &mut eicar, None, &|_| Severity::Medium).unwrap();
+        assert!(res.is_infected);
+        assert_eq!(severity, Severity::Medium);
+
+        let (_, severity) = scan_classified(
+            "localhost:3310",
+            "This is not a virus.".as_bytes(),
+            None,
+            &|_| Severity::High,
+        )
+        .unwrap();
+        assert_eq!(severity, Severity::Clean);
+    }
+
+    #[test]
+    #[allow(clippy::needless_borrows_for_generic_args)]
     fn can_scan_string() {
         let mut eicar =
             r"X5O!P%@AP[4\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*".as_bytes();
         let res = scan("localhost:3310", &mut eicar, None).unwrap();
         assert_eq!(1, res.detected_infections.len());
     }
+
+    #[cfg(feature = "digest")]
+    #[test]
+    fn hash_covers_every_chunk() {
+        use sha2::{Digest, Sha256};
+
+        let data = vec![b'a'; 10_000];
+        let (res, digest) =
+            scan_hashed::<_, _, Sha256>("localhost:3310", &mut data.as_slice(), Some(64)).unwrap();
+        assert!(!res.is_infected);
+        assert_eq!(digest, Sha256::digest(&data).to_vec());
+    }
+
+    #[test]
+    fn tee_copies_everything_to_sink() {
+        let input = "This is not a virus.".repeat(1000);
+        let mut sink = vec![];
+        let res = scan_tee("localhost:3310", &mut input.as_bytes(), &mut sink, Some(64)).unwrap();
+        assert!(!res.is_infected);
+        assert_eq!(sink, input.as_bytes());
+    }
+
+    #[cfg(feature = "flate2")]
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[cfg(feature = "flate2")]
+    #[test]
+    fn scans_decompressed_gzip() {
+        let eicar = std::fs::read("resources/eicar.txt").unwrap();
+        // Two members, as produced by concatenating gzip files.
+        let mut compressed = gzip(b"This is not a virus.");
+        compressed.extend(gzip(&eicar));
+
+        let res = scan_gzip("localhost:3310", &mut &compressed[..], Some(16)).unwrap();
+        assert_eq!(res.detected_infections, vec!["Win.Test.EICAR_HDB-1"]);
+    }
+
+    #[cfg(feature = "flate2")]
+    #[test]
+    fn corrupt_gzip_is_not_a_scan_error() {
+        let compressed = gzip(&[b'a'; 10_000]);
+        let truncated = &compressed[..compressed.len() / 2];
+        let mut corrupt = compressed.clone();
+        corrupt[12..20].copy_from_slice(&[0xff; 8]);
+
+        for input in [truncated, &corrupt[..], b"not gzip at all"] {
+            let err = scan_gzip("localhost:3310", &mut &input[..], None).unwrap_err();
+            assert!(
+                matches!(err, ClamAVClientError::InvalidCompressedInput(_)),
+                "{err:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn early_detection_wins_over_write_error() {
+        let mut conn = MockTransport::closing_after(b"stream: Win.Test.EICAR_HDB-1 FOUND\0", 100);
+        let mut data = &[0; 100_000][..];
+
+        let (reply, stats) =
+            exchange(&mut conn, INSTREAM_HEADER, &mut data, Some(64), |_| Ok(())).unwrap();
+        let res = parse_reply(&reply, false).unwrap();
+        assert_eq!(res.detected_infections, vec!["Win.Test.EICAR_HDB-1"]);
+        assert_eq!(stats.reply_bytes, reply.len() as u64);
+    }
+
+    #[test]
+    fn early_size_limit_error_wins_over_write_error() {
+        let mut conn = MockTransport::closing_after(b"INSTREAM size limit exceeded. ERROR\0", 100);
+        let mut data = &[0; 100_000][..];
+
+        let (reply, _) =
+            exchange(&mut conn, INSTREAM_HEADER, &mut data, Some(64), |_| Ok(())).unwrap();
+        assert!(matches!(
+            parse_reply(&reply, false),
+            Err(ClamAVClientError::SizeLimitExceeded)
+        ));
+    }
+
+    #[test]
+    fn early_close_without_a_verdict_keeps_the_write_error() {
+        for reply in [&b""[..], b"stream: OK\0"] {
+            let mut conn = MockTransport::closing_after(reply, 100);
+            let mut data = &[0; 100_000][..];
+
+            let err =
+                exchange(&mut conn, INSTREAM_HEADER, &mut data, Some(64), |_| Ok(())).unwrap_err();
+            assert!(
+                matches!(&err, ClamAVClientError::UnableToWriteToStream(e) if e.kind() == ErrorKind::BrokenPipe),
+                "{reply:?}: {err:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn input_errors_are_not_mistaken_for_a_dropped_connection() {
+        /// A connection ClamAV keeps open, waiting for the rest of the input.
+        struct Waiting;
+        impl Read for Waiting {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                panic!("ClamAV doesn't reply before the input ends");
+            }
+        }
+        impl Write for Waiting {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        /// A download whose connection is reset.
+        struct Reset;
+        impl Read for Reset {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(ErrorKind::ConnectionReset.into())
+            }
+        }
+
+        let err =
+            exchange(&mut Waiting, INSTREAM_HEADER, &mut Reset, None, |_| Ok(())).unwrap_err();
+        assert!(
+            matches!(&err, ClamAVClientError::UnableToReadInput(e) if e.kind() == ErrorKind::ConnectionReset),
+            "{err:?}"
+        );
+        assert!(!err.is_retryable());
+    }
+
+    #[test]
+    fn throttle_hook_sees_running_totals() {
+        let mut totals = vec![];
+        let res = scan_throttled(
+            "localhost:3310",
+            &mut "0123456789".as_bytes(),
+            Some(4),
+            |sent| totals.push(sent),
+        )
+        .unwrap();
+        assert!(!res.is_infected);
+        assert_eq!(totals, vec![4, 8, 10]);
+    }
+
+    #[test]
+    fn tee_distinguishes_sink_errors() {
+        struct FailingSink;
+        impl Write for FailingSink {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("disk full"))
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut buf = "This is not a virus.".as_bytes();
+        let res = scan_tee("localhost:3310", &mut buf, &mut FailingSink, None);
+        assert!(matches!(
+            res,
+            Err(ClamAVClientError::UnableToWriteToSink(_))
+        ));
+    }
+
+    #[test]
+    fn instream_waits_for_slow_reader() {
+        struct SlowReader {
+            reads: Vec<io::Result<&'static [u8]>>,
+        }
+        impl Read for SlowReader {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                if self.reads.is_empty() {
+                    return Ok(0);
+                }
+                let data = self.reads.remove(0)?;
+                buf[..data.len()].copy_from_slice(data);
+                Ok(data.len())
+            }
+        }
+
+        let mut reader = SlowReader {
+            reads: vec![
+                Err(ErrorKind::WouldBlock.into()),
+                Ok(b"hello"),
+                Err(ErrorKind::Interrupted.into()),
+                Err(ErrorKind::WouldBlock.into()),
+                Ok(b"world"),
+            ],
+        };
+        let mut out = vec![];
+        write_instream(&mut out, &mut reader, None, |_| Ok(())).unwrap();
+
+        let expected = [
+            INSTREAM_HEADER,
+            &5u32.to_be_bytes(),
+            b"hello",
+            &5u32.to_be_bytes(),
+            b"world",
+            INSTREAM_FOOTER,
+        ]
+        .concat();
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn instream_ignores_zero_chunk_size() {
+        let mut out = vec![];
+        write_instream(&mut out, &mut "data".as_bytes(), Some(0), |_| Ok(())).unwrap();
+        assert_eq!(
+            out,
+            [
+                INSTREAM_HEADER,
+                &4u32.to_be_bytes(),
+                b"data",
+                INSTREAM_FOOTER
+            ]
+            .concat()
+        );
+    }
+
+    #[derive(Default)]
+    struct CountingWriter {
+        writes: usize,
+        data: Vec<u8>,
+    }
+
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn small_input_is_sent_in_one_write() {
+        let mut out = CountingWriter::default();
+        write_instream(&mut out, &mut "tiny".as_bytes(), None, |_| Ok(())).unwrap();
+
+        assert_eq!(out.writes, 1);
+        assert_eq!(
+            out.data,
+            [
+                INSTREAM_HEADER,
+                &4u32.to_be_bytes(),
+                b"tiny",
+                INSTREAM_FOOTER
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn large_input_is_sent_one_frame_per_write() {
+        let input = [b'a'; 64 * 3];
+        let mut out = CountingWriter::default();
+        let stats = write_instream(&mut out, &mut &input[..], Some(64), |_| Ok(())).unwrap();
+        assert_eq!(stats.chunks, 3);
+        assert_eq!(stats.bytes_sent, 64 * 3);
+
+        // The header rides along with the first frame and the footer with the last one.
+        assert_eq!(out.writes, 3);
+        assert_eq!(
+            out.data.len(),
+            INSTREAM_HEADER.len() + 3 * (4 + 64) + INSTREAM_FOOTER.len()
+        );
+    }
+
+    #[test]
+    fn small_input_is_sent_as_a_single_chunk() {
+        let input = [b'a'; 200];
+        let (buffered, chunk_size) = buffer_single_chunk(&mut &input[..], 1024, 64).unwrap();
+        assert_eq!(chunk_size, 200);
+
+        let mut out = vec![];
+        let mut rest = &input[input.len()..];
+        let stats = write_instream(
+            &mut out,
+            &mut buffered.as_slice().chain(&mut rest),
+            Some(chunk_size),
+            |_| Ok(()),
+        )
+        .unwrap();
+        assert_eq!(stats.chunks, 1);
+        assert_eq!(
+            out,
+            [
+                INSTREAM_HEADER,
+                &200u32.to_be_bytes(),
+                &input[..],
+                INSTREAM_FOOTER
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn large_input_falls_back_to_chunks() {
+        let input = [b'a'; 200];
+        let mut file = &input[..];
+        let (buffered, chunk_size) = buffer_single_chunk(&mut file, 100, 64).unwrap();
+        assert_eq!(chunk_size, 64);
+        assert_eq!(buffered.len(), 101);
+
+        let mut sent = vec![];
+        let stats = write_instream(
+            &mut io::sink(),
+            &mut buffered.as_slice().chain(&mut file),
+            Some(chunk_size),
+            |chunk| {
+                sent.extend_from_slice(chunk);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert!(stats.chunks > 1);
+        assert_eq!(sent, input);
+    }
+
+    #[test]
+    fn scan_on_reads_a_single_reply() {
+        let mut conn = MockTransport::new(b"1: stream: OK\x002: stream: Eicar-Signature FOUND\0");
+
+        let res = scan_on(&mut conn, &mut "clean".as_bytes(), None).unwrap();
+        assert!(!res.is_infected);
+        let res = scan_on(&mut conn, &mut "eicar".as_bytes(), None).unwrap();
+        assert_eq!(res.detected_infections, vec!["Eicar-Signature"]);
+
+        let expected = [
+            INSTREAM_HEADER,
+            &5u32.to_be_bytes(),
+            b"clean",
+            INSTREAM_FOOTER,
+            INSTREAM_HEADER,
+            &5u32.to_be_bytes(),
+            b"eicar",
+            INSTREAM_FOOTER,
+        ]
+        .concat();
+        assert_eq!(conn.written, expected);
+    }
+
+    #[test]
+    fn scan_on_parses_reply_delivered_byte_by_byte() {
+        let mut conn = MockTransport::trickle(b"stream: Win.Test.EICAR_HDB-1 FOUND\0stream: OK\0");
+
+        let res = scan_on(&mut conn, &mut "eicar".as_bytes(), None).unwrap();
+        assert_eq!(res.detected_infections, vec!["Win.Test.EICAR_HDB-1"]);
+        let res = scan_on(&mut conn, &mut "clean".as_bytes(), None).unwrap();
+        assert!(!res.is_infected);
+    }
+
+    #[test]
+    fn empty_reply_is_not_clean() {
+        let mut conn = MockTransport::new(b"");
+        let res = scan_on(&mut conn, &mut "clean".as_bytes(), None);
+        assert!(matches!(res, Err(ClamAVClientError::EmptyResponse)));
+    }
+
+    #[test]
+    fn instream_streams_pipe_with_interrupted_reads() {
+        /// Fails every other read with `Interrupted`, like a pipe read hit by a signal.
+        struct Interrupting<R> {
+            inner: R,
+            interrupt: bool,
+        }
+        impl<R: Read> Read for Interrupting<R> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.interrupt = !self.interrupt;
+                if self.interrupt {
+                    return Err(ErrorKind::Interrupted.into());
+                }
+                self.inner.read(buf)
+            }
+        }
+
+        let (reader, mut writer) = io::pipe().unwrap();
+        let producer = thread::spawn(move || {
+            for part in [&b"first "[..], b"second ", b"third"] {
+                writer.write_all(part).unwrap();
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+
+        let mut reader = Interrupting {
+            inner: reader,
+            interrupt: false,
+        };
+        let mut sent = vec![];
+        write_instream(&mut io::sink(), &mut reader, None, |chunk| {
+            sent.extend_from_slice(chunk);
+            Ok(())
+        })
+        .unwrap();
+        producer.join().unwrap();
+
+        assert_eq!(sent, b"first second third");
+    }
+
+    #[test]
+    fn streams_a_download_as_it_trickles_in() {
+        /// Counts the reads, to tell waiting on the socket from spinning on it.
+        struct Counting<R> {
+            inner: R,
+            reads: usize,
+        }
+        impl<R: Read> Read for Counting<R> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                self.reads += 1;
+                self.inner.read(buf)
+            }
+        }
+
+        let parts = [&b"HTTP body, "[..], b"sent in ", b"slow parts"];
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            for part in parts {
+                thread::sleep(Duration::from_millis(50));
+                conn.write_all(part).unwrap();
+            }
+        });
+
+        let download = std::net::TcpStream::connect(addr).unwrap();
+        download.set_nonblocking(true).unwrap();
+        let mut download = Counting {
+            inner: download,
+            reads: 0,
+        };
+        let mut conn = MockTransport::new(b"stream: OK\0");
+        let res = scan_on(&mut conn, &mut download, Some(8)).unwrap();
+        server.join().unwrap();
+
+        assert!(!res.is_infected);
+        let mut sent = vec![];
+        let mut request = &conn.written[INSTREAM_HEADER.len()..];
+        while let Some((len, rest)) = request.split_first_chunk::<CHUNK_LENGTH_SIZE>() {
+            let len = u32::from_be_bytes(*len) as usize;
+            sent.extend_from_slice(&rest[..len]);
+            request = &rest[len..];
+        }
+        assert!(request.is_empty());
+        assert_eq!(sent, parts.concat());
+        // About 150ms of waiting, polled every 10ms.
+        assert!(download.reads < 100, "{} reads", download.reads);
+    }
+
+    #[test]
+    fn reports_scan_stats() {
+        let input = "This is not a virus.".repeat(10);
+        let (res, stats) =
+            scan_with_stats("localhost:3310", &mut input.as_bytes(), Some(64)).unwrap();
+        assert!(!res.is_infected);
+        assert_eq!(
+            stats,
+            ScanStats {
+                chunks: 4,
+                bytes_sent: 200,
+                reply_bytes: "stream: OK\0".len() as u64,
+            }
+        );
+    }
 }
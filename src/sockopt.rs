@@ -0,0 +1,155 @@
+use std::{
+    io::{Error, ErrorKind},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::{resolve, ClamAVClientError, ClamdAddress};
+
+/// Socket options for the connections a [ClamdClient](crate::ClamdClient) makes, see
+/// [ClamdClientBuilder::socket_options](crate::ClamdClientBuilder::socket_options).
+///
+/// These are rarely needed, eg. to pin the local port for a firewall rule, or to reconnect from
+/// a pinned port while earlier connections from it are still in `TIME_WAIT`. More options may be
+/// added, so build it from [SocketOptions::default] and the setters.
+///
+/// Requires the `socket2` feature.
+///
+/// ```rust
+/// use clamav_tcp::{ClamdClient, SocketOptions};
+/// let client = ClamdClient::builder("localhost:3310")
+///     .socket_options(
+///         SocketOptions::default()
+///             .reuse_address(true)
+///             .local_address("127.0.0.1:40100".parse().unwrap()),
+///     )
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SocketOptions {
+    /// Sets `SO_REUSEADDR` before connecting.
+    pub reuse_address: bool,
+    /// Binds to this local address before connecting. Port 0 lets the OS pick the port.
+    pub local_address: Option<SocketAddr>,
+}
+
+impl SocketOptions {
+    /// Sets `SO_REUSEADDR` before connecting, so a pinned local address can be bound again
+    /// while an earlier connection from it is in `TIME_WAIT`.
+    pub fn reuse_address(mut self, reuse_address: bool) -> Self {
+        self.reuse_address = reuse_address;
+        self
+    }
+
+    /// Connects from `local_address` instead of one the OS picks.
+    pub fn local_address(mut self, local_address: SocketAddr) -> Self {
+        self.local_address = Some(local_address);
+        self
+    }
+
+    /// Connects to the first address of `address` that accepts the connection, giving up on
+    /// each after `timeout` if there is one, like the client does without socket options.
+    pub(crate) fn connect(
+        &self,
+        address: &ClamdAddress,
+        timeout: Option<Duration>,
+    ) -> Result<TcpStream, ClamAVClientError> {
+        let mut last_err = Error::new(ErrorKind::InvalidInput, "no addresses to connect to");
+        for addr in resolve(address)? {
+            match self.connect_one(addr, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_err = e,
+            }
+        }
+        Err(ClamAVClientError::UnableToConnect(last_err))
+    }
+
+    fn connect_one(
+        &self,
+        addr: SocketAddr,
+        timeout: Option<Duration>,
+    ) -> std::io::Result<TcpStream> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        if self.reuse_address {
+            socket.set_reuse_address(true)?;
+        }
+        if let Some(local) = self.local_address {
+            socket.bind(&local.into())?;
+        }
+        match timeout {
+            Some(timeout) => {
+                socket.connect_timeout(&addr.into(), timeout)?;
+                socket.set_read_timeout(Some(timeout))?;
+                socket.set_write_timeout(Some(timeout))?;
+            }
+            None => socket.connect(&addr.into())?,
+        }
+        Ok(socket.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use super::*;
+    use crate::ClamdClient;
+
+    /// A local port that was free a moment ago.
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    #[test]
+    fn connects_from_the_pinned_local_address() {
+        let local: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut peers = vec![];
+            for _ in 0..2 {
+                let (mut conn, peer) = listener.accept().unwrap();
+                let _ = conn.read(&mut [0; 16]);
+                conn.write_all(b"PONG\0").unwrap();
+                peers.push(peer);
+            }
+            peers
+        });
+
+        let client = ClamdClient::builder(addr.to_string())
+            .socket_options(
+                SocketOptions::default()
+                    .reuse_address(true)
+                    .local_address(local),
+            )
+            .build()
+            .unwrap();
+        // The first connection is closed by the server, so the second reuses the port at once.
+        assert_eq!(client.ping().unwrap(), "PONG\0");
+        assert_eq!(client.ping().unwrap(), "PONG\0");
+        assert_eq!(server.join().unwrap(), vec![local, local]);
+    }
+
+    #[test]
+    fn reports_unusable_local_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let taken = listener.local_addr().unwrap();
+        let opts = SocketOptions::default().local_address(taken);
+        let err = opts
+            .connect(&ClamdAddress::from(taken.to_string()), None)
+            .unwrap_err();
+        assert_eq!(err.io_error_kind(), Some(ErrorKind::AddrInUse));
+    }
+}
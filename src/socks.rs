@@ -0,0 +1,301 @@
+use std::{
+    io::{Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream},
+};
+
+use crate::{ClamAVClientError, ClamdAddress};
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const USERNAME_PASSWORD: u8 = 2;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const CONNECT: u8 = 1;
+const IPV4: u8 = 1;
+const DOMAIN_NAME: u8 = 3;
+const IPV6: u8 = 4;
+const SUCCEEDED: u8 = 0;
+
+/// A SOCKS5 proxy to reach ClamAV through, see
+/// [ClamdClientBuilder::socks5_proxy](crate::ClamdClientBuilder::socks5_proxy).
+///
+/// Requires the `socks` feature.
+///
+/// ```rust
+/// use clamav_tcp::{ClamdClient, Socks5Proxy};
+/// let client = ClamdClient::builder("clamav.internal:3310")
+///     .socks5_proxy(Socks5Proxy::new("proxy.internal:1080").with_auth("scanner", "secret"))
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Clone)]
+pub struct Socks5Proxy {
+    pub(crate) address: ClamdAddress,
+    auth: Option<(String, String)>,
+}
+
+impl Socks5Proxy {
+    /// A proxy at `address` that doesn't require authentication.
+    pub fn new(address: impl Into<ClamdAddress>) -> Self {
+        Socks5Proxy {
+            address: address.into(),
+            auth: None,
+        }
+    }
+
+    /// Authenticates to the proxy with a username and password.
+    pub fn with_auth(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.auth = Some((username.into(), password.into()));
+        self
+    }
+
+    /// Asks the proxy, already connected to over `stream`, to connect to `target`. Host names are
+    /// resolved by the proxy, as ClamAV may not be resolvable from this side of it.
+    pub(crate) fn handshake(
+        &self,
+        stream: &mut TcpStream,
+        target: &ClamdAddress,
+    ) -> Result<(), ClamAVClientError> {
+        let method = if self.auth.is_some() {
+            USERNAME_PASSWORD
+        } else {
+            NO_AUTH
+        };
+        send(stream, &[VERSION, 1, method])?;
+        match receive::<2>(stream)? {
+            [VERSION, m] if m == method => {}
+            [VERSION, NO_ACCEPTABLE_METHODS] => {
+                return Err(proxy_error(
+                    "the proxy doesn't accept the authentication method",
+                ))
+            }
+            _ => return Err(proxy_error("unexpected reply to the greeting")),
+        }
+
+        if let Some((username, password)) = &self.auth {
+            let mut request = vec![1];
+            for field in [username, password] {
+                let len = u8::try_from(field.len())
+                    .map_err(|_| proxy_error("username and password must be at most 255 bytes"))?;
+                request.push(len);
+                request.extend_from_slice(field.as_bytes());
+            }
+            send(stream, &request)?;
+            if receive::<2>(stream)?[1] != SUCCEEDED {
+                return Err(proxy_error("authentication failed"));
+            }
+        }
+
+        let mut request = vec![VERSION, CONNECT, 0];
+        encode_target(&mut request, target)?;
+        send(stream, &request)?;
+
+        let [_, reply, _, address_type] = receive::<4>(stream)?;
+        if reply != SUCCEEDED {
+            return Err(proxy_error(reply_message(reply)));
+        }
+        // The address the proxy connected from is of no use, but it must be read past.
+        let bound_len = match address_type {
+            IPV4 => 4,
+            IPV6 => 16,
+            DOMAIN_NAME => receive::<1>(stream)?[0] as usize,
+            _ => return Err(proxy_error("unexpected address type in the reply")),
+        };
+        let mut bound_address = vec![0; bound_len + 2];
+        stream
+            .read_exact(&mut bound_address)
+            .map_err(ClamAVClientError::UnableToConnect)?;
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Socks5Proxy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Keep the password out of logs.
+        f.debug_struct("Socks5Proxy")
+            .field("address", &self.address)
+            .field(
+                "username",
+                &self.auth.as_ref().map(|(username, _)| username),
+            )
+            .finish()
+    }
+}
+
+fn encode_target(request: &mut Vec<u8>, target: &ClamdAddress) -> Result<(), ClamAVClientError> {
+    let addr = match target {
+        ClamdAddress::Socket(addr) => *addr,
+        // The proxy connects to a single address, so only the first one is used.
+        ClamdAddress::Resolved(addrs) => *addrs
+            .first()
+            .ok_or_else(|| proxy_error("the ClamAV address list is empty"))?,
+        ClamdAddress::Host(host) => {
+            let invalid = || proxy_error("the ClamAV address must be given as host:port");
+            let (host, port) = host.rsplit_once(':').ok_or_else(invalid)?;
+            let port: u16 = port.parse().map_err(|_| invalid())?;
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            match host.parse::<IpAddr>() {
+                Ok(ip) => SocketAddr::new(ip, port),
+                Err(_) => {
+                    let len = u8::try_from(host.len())
+                        .map_err(|_| proxy_error("host names must be at most 255 bytes"))?;
+                    request.extend_from_slice(&[DOMAIN_NAME, len]);
+                    request.extend_from_slice(host.as_bytes());
+                    request.extend_from_slice(&port.to_be_bytes());
+                    return Ok(());
+                }
+            }
+        }
+    };
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            request.push(IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            request.push(IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+    }
+    request.extend_from_slice(&addr.port().to_be_bytes());
+    Ok(())
+}
+
+fn reply_message(reply: u8) -> &'static str {
+    match reply {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn send(stream: &mut TcpStream, message: &[u8]) -> Result<(), ClamAVClientError> {
+    stream
+        .write_all(message)
+        .map_err(ClamAVClientError::UnableToConnect)
+}
+
+fn receive<const N: usize>(stream: &mut TcpStream) -> Result<[u8; N], ClamAVClientError> {
+    let mut buf = [0; N];
+    stream
+        .read_exact(&mut buf)
+        .map_err(ClamAVClientError::UnableToConnect)?;
+    Ok(buf)
+}
+
+fn proxy_error(message: &str) -> ClamAVClientError {
+    ClamAVClientError::ProxyError(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, net::TcpListener, thread};
+
+    use super::*;
+    use crate::ClamdClient;
+
+    /// Starts a SOCKS5 proxy that answers CONNECT requests with `reply` and, on success, relays
+    /// to the local clamd. Returns its address and a handle yielding the requested target.
+    fn proxy(reply: u8, auth: Option<&'static [u8]>) -> (SocketAddr, thread::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut greeting = [0; 3];
+            conn.read_exact(&mut greeting).unwrap();
+            conn.write_all(&[VERSION, greeting[2]]).unwrap();
+            if let Some(expected) = auth {
+                let mut request = vec![0; expected.len()];
+                conn.read_exact(&mut request).unwrap();
+                let status = if request == expected { SUCCEEDED } else { 1 };
+                conn.write_all(&[1, status]).unwrap();
+            }
+
+            let mut target = vec![0; 4];
+            conn.read_exact(&mut target).unwrap();
+            let rest = match target[3] {
+                IPV4 => 6,
+                IPV6 => 18,
+                _ => {
+                    let mut len = [0];
+                    conn.read_exact(&mut len).unwrap();
+                    target.push(len[0]);
+                    len[0] as usize + 2
+                }
+            };
+            let mut address = vec![0; rest];
+            conn.read_exact(&mut address).unwrap();
+            target.extend(address);
+            conn.write_all(&[VERSION, reply, 0, IPV4, 127, 0, 0, 1, 0, 0])
+                .unwrap();
+
+            if reply == SUCCEEDED {
+                let clamd = TcpStream::connect("localhost:3310").unwrap();
+                let (mut from_client, mut to_clamd) =
+                    (conn.try_clone().unwrap(), clamd.try_clone().unwrap());
+                let upstream = thread::spawn(move || {
+                    io::copy(&mut from_client, &mut to_clamd).ok();
+                    to_clamd.shutdown(std::net::Shutdown::Write).ok();
+                });
+                io::copy(&mut &clamd, &mut conn).ok();
+                // ClamAV is done, so is the client.
+                conn.shutdown(std::net::Shutdown::Both).ok();
+                upstream.join().unwrap();
+            }
+            target
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn tunnels_requests_through_the_proxy() {
+        let (addr, proxy) = proxy(SUCCEEDED, None);
+        let client = ClamdClient::builder("localhost:3310")
+            .socks5_proxy(Socks5Proxy::new(addr))
+            .build()
+            .unwrap();
+
+        assert_eq!(client.ping().unwrap(), "PONG\0");
+        let target = proxy.join().unwrap();
+        assert_eq!(target, b"\x05\x01\x00\x03\x09localhost\x0c\xee");
+    }
+
+    #[test]
+    fn authenticates_to_the_proxy() {
+        let (addr, proxy) = proxy(SUCCEEDED, Some(b"\x01\x04user\x04pass"));
+        let client = ClamdClient::builder(("127.0.0.1", 3310))
+            .socks5_proxy(Socks5Proxy::new(addr).with_auth("user", "pass"))
+            .build()
+            .unwrap();
+
+        let res = client.scan(&mut "This is not a virus.".as_bytes()).unwrap();
+        assert!(!res.is_infected);
+        let target = proxy.join().unwrap();
+        assert_eq!(target, b"\x05\x01\x00\x01\x7f\x00\x00\x01\x0c\xee");
+    }
+
+    #[test]
+    fn proxy_failures_are_reported() {
+        let (addr, _) = proxy(5, None);
+        let client = ClamdClient::builder("localhost:3310")
+            .socks5_proxy(Socks5Proxy::new(addr))
+            .build()
+            .unwrap();
+
+        match client.ping().unwrap_err() {
+            ClamAVClientError::ProxyError(e) => assert_eq!(e, "connection refused"),
+            e => panic!("{e:?}"),
+        }
+    }
+
+    #[test]
+    fn debug_hides_the_password() {
+        let proxy = Socks5Proxy::new("proxy:1080").with_auth("user", "hunter2");
+        assert!(!format!("{proxy:?}").contains("hunter2"));
+    }
+}
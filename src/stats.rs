@@ -0,0 +1,49 @@
+use std::{
+    io::{Read, Write},
+    net::ToSocketAddrs,
+};
+
+use crate::{
+    connect_tcp_socket,
+    transport::{read_to_close, trace_sent, DEFAULT_REPLY_BUFFER_SIZE},
+    Byte, ClamAVClientError, ClamdStats,
+};
+const STATS_REQUEST: &[Byte] = b"zSTATS\0";
+
+/// Fetches ClamAV's statistics on its thread pools, queue and memory use.
+///
+/// ```rust
+/// use clamav_tcp;
+/// let stats = clamav_tcp::stats("localhost:3310").unwrap();
+/// println!("{:?}", stats.threads); // Some("live 1  idle 0 max 12 idle-timeout 30")
+/// ```
+pub fn stats(addr: impl ToSocketAddrs) -> Result<ClamdStats, ClamAVClientError> {
+    stats_on(&mut connect_tcp_socket(addr)?, DEFAULT_REPLY_BUFFER_SIZE)
+}
+
+/// Sends STATS over a new connection and parses the reply, reading it `reply_buffer_size` bytes
+/// at a time.
+pub(crate) fn stats_on<S: Read + Write>(
+    stream: &mut S,
+    reply_buffer_size: usize,
+) -> Result<ClamdStats, ClamAVClientError> {
+    trace_sent(STATS_REQUEST);
+    stream
+        .write_all(STATS_REQUEST)
+        .map_err(ClamAVClientError::UnableToConnect)?;
+
+    let resp = read_to_close(stream, reply_buffer_size).map_err(ClamAVClientError::InvalidUTf8)?;
+
+    ClamdStats::parse(&resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_read_stats() {
+        let stats = stats("localhost:3310").unwrap();
+        assert!(stats.pools.is_some());
+    }
+}
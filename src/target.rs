@@ -0,0 +1,87 @@
+use std::{fs::File, io::Read, path::Path};
+
+use crate::ClamAVClientError;
+
+/// Something that can be streamed to ClamAV with [scan](crate::scan).
+///
+/// Implemented for readers (`&mut R` where `R` is [Read], including `&mut dyn Read`), byte slices,
+/// [File]s and local [Path]s. A path is opened here and its contents are streamed, unlike
+/// [scan_path](crate::scan_path) which asks ClamAV to open a path on its own host.
+///
+/// ```rust
+/// use std::path::Path;
+/// let by_path = clamav_tcp::scan("localhost:3310", Path::new("resources/eicar.txt"), None).unwrap();
+/// let bytes = std::fs::read("resources/eicar.txt").unwrap();
+/// let by_bytes = clamav_tcp::scan("localhost:3310", &bytes[..], None).unwrap();
+/// assert_eq!(by_path.detected_infections, by_bytes.detected_infections);
+/// ```
+pub trait ScanTarget {
+    /// The reader the target is streamed from.
+    type Reader: Read;
+
+    /// Opens the target for reading.
+    fn into_reader(self) -> Result<Self::Reader, ClamAVClientError>;
+}
+
+impl<'a, R: Read + ?Sized> ScanTarget for &'a mut R {
+    type Reader = &'a mut R;
+
+    fn into_reader(self) -> Result<Self::Reader, ClamAVClientError> {
+        Ok(self)
+    }
+}
+
+impl<'a> ScanTarget for &'a [u8] {
+    type Reader = &'a [u8];
+
+    fn into_reader(self) -> Result<Self::Reader, ClamAVClientError> {
+        Ok(self)
+    }
+}
+
+impl ScanTarget for File {
+    type Reader = File;
+
+    fn into_reader(self) -> Result<Self::Reader, ClamAVClientError> {
+        Ok(self)
+    }
+}
+
+impl ScanTarget for &Path {
+    type Reader = File;
+
+    fn into_reader(self) -> Result<Self::Reader, ClamAVClientError> {
+        File::open(self).map_err(ClamAVClientError::UnableToReadInput)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan;
+
+    #[test]
+    fn every_target_scans_the_same() {
+        let path = Path::new("resources/eicar.txt");
+        let bytes = std::fs::read(path).unwrap();
+        let mut reader: &mut dyn Read = &mut &bytes[..];
+        let mut file = File::open(path).unwrap();
+
+        let results = [
+            scan("localhost:3310", path, None).unwrap(),
+            scan("localhost:3310", &bytes[..], None).unwrap(),
+            scan("localhost:3310", File::open(path).unwrap(), None).unwrap(),
+            scan("localhost:3310", &mut file, None).unwrap(),
+            scan("localhost:3310", &mut reader, None).unwrap(),
+        ];
+        for res in results {
+            assert_eq!(res.detected_infections, vec!["Win.Test.EICAR_HDB-1"]);
+        }
+    }
+
+    #[test]
+    fn missing_path_is_an_input_error() {
+        let err = scan("localhost:3310", Path::new("does/not/exist"), None).unwrap_err();
+        assert!(matches!(err, ClamAVClientError::UnableToReadInput(_)));
+    }
+}
@@ -0,0 +1,284 @@
+use std::io::{Error, ErrorKind, Read, Write};
+
+use crate::{protocol::REPLY_TERMINATOR, Byte, ClamAVClientError};
+/// Bytes read at a time by [read_to_close] unless configured otherwise, and by
+/// [read_buffered_reply].
+pub(crate) const DEFAULT_REPLY_BUFFER_SIZE: usize = 8192;
+/// Maximum number of bytes of a single write logged by [trace_sent].
+#[cfg(feature = "tracing")]
+const MAX_TRACED_BYTES: usize = 64;
+
+/// A connection to ClamAV that commands can be sent over.
+///
+/// Implemented for anything that is [Read] and [Write], such as a [TcpStream](std::net::TcpStream).
+pub trait ClamdTransport: Read + Write {}
+
+impl<T: Read + Write + ?Sized> ClamdTransport for T {}
+
+/// Reads exactly one `\0` terminated reply from `conn`, leaving anything after it unread.
+///
+/// The terminator is kept in the returned reply. If the connection is closed before a
+/// terminator is seen, whatever was received is returned.
+pub(crate) fn read_reply<T: Read + ?Sized>(conn: &mut T) -> Result<String, ClamAVClientError> {
+    let mut reply = vec![];
+    // Read a byte at a time so the start of the next reply is never consumed.
+    let mut byte = [0; 1];
+    loop {
+        match conn.read(&mut byte) {
+            Ok(0) => break,
+            Ok(_) => {
+                reply.push(byte[0]);
+                if byte[0] == REPLY_TERMINATOR {
+                    break;
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(ClamAVClientError::InvalidUTf8(e)),
+        }
+    }
+
+    reply_to_string(reply)
+}
+
+/// Reads the next `\0` terminated reply from `conn`, like [read_reply], but in large reads.
+///
+/// Whatever arrives after the reply is kept in `unread` and returned by the next call, so
+/// `unread` must be passed with every read from `conn`.
+pub(crate) fn read_buffered_reply<T: Read + ?Sized>(
+    conn: &mut T,
+    unread: &mut Vec<Byte>,
+) -> Result<String, ClamAVClientError> {
+    // Bytes already searched for the terminator, so a long reply isn't scanned again every read.
+    let mut searched = 0;
+    loop {
+        if let Some(end) = unread[searched..]
+            .iter()
+            .position(|&b| b == REPLY_TERMINATOR)
+        {
+            let reply = unread.drain(..=searched + end).collect();
+            return reply_to_string(reply);
+        }
+        searched = unread.len();
+
+        unread.resize(searched + DEFAULT_REPLY_BUFFER_SIZE, 0);
+        let read = conn.read(&mut unread[searched..]);
+        unread.truncate(searched + read.as_ref().map_or(0, |read| *read));
+        match read {
+            Ok(0) => return reply_to_string(std::mem::take(unread)),
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(ClamAVClientError::InvalidUTf8(e)),
+        }
+    }
+}
+
+fn reply_to_string(reply: Vec<Byte>) -> Result<String, ClamAVClientError> {
+    String::from_utf8(reply)
+        .map_err(|e| ClamAVClientError::InvalidUTf8(Error::new(ErrorKind::InvalidData, e)))
+}
+
+/// Reads everything `conn` sends until it closes the connection, `buffer_size` bytes at a time.
+pub(crate) fn read_to_close<T: Read + ?Sized>(
+    conn: &mut T,
+    buffer_size: usize,
+) -> std::io::Result<String> {
+    let mut reply = vec![];
+    let mut buf = vec![0; buffer_size.max(1)];
+    loop {
+        match conn.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => reply.extend_from_slice(&buf[..read]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    String::from_utf8(reply).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Logs bytes sent to ClamAV at trace level, for debugging the protocol against unusual ClamAV
+/// builds. Only commands and INSTREAM framing are passed here, never the scanned data.
+#[cfg(feature = "tracing")]
+pub(crate) fn trace_sent(bytes: &[Byte]) {
+    tracing::trace!(len = bytes.len(), bytes = %escape_sent(bytes), "sent");
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn trace_sent(_: &[Byte]) {}
+
+/// Escapes `bytes` for logging, capped at [MAX_TRACED_BYTES].
+#[cfg(feature = "tracing")]
+fn escape_sent(bytes: &[Byte]) -> String {
+    let shown = &bytes[..bytes.len().min(MAX_TRACED_BYTES)];
+    let mut escaped = shown.escape_ascii().to_string();
+    if shown.len() < bytes.len() {
+        escaped.push_str("...");
+    }
+    escaped
+}
+
+/// In-memory [ClamdTransport] for tests, replying with canned bytes.
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::io::{Cursor, Error, ErrorKind, Read, Result, Write};
+
+    pub(crate) struct MockTransport {
+        replies: Cursor<Vec<u8>>,
+        /// Maximum number of bytes returned by a single read.
+        max_read: usize,
+        /// Everything that was written to the transport.
+        pub(crate) written: Vec<u8>,
+        /// Number of bytes accepted before writes fail as if the peer closed the connection.
+        write_limit: usize,
+    }
+
+    impl MockTransport {
+        pub(crate) fn new(replies: &[u8]) -> Self {
+            MockTransport {
+                replies: Cursor::new(replies.to_vec()),
+                max_read: usize::MAX,
+                written: vec![],
+                write_limit: usize::MAX,
+            }
+        }
+
+        /// Accepts `write_limit` bytes and then fails writes with [ErrorKind::BrokenPipe], like a
+        /// peer that replied and closed the connection early.
+        pub(crate) fn closing_after(replies: &[u8], write_limit: usize) -> Self {
+            MockTransport {
+                write_limit,
+                ..MockTransport::new(replies)
+            }
+        }
+
+        /// Delivers the replies one byte per read, like a reply split over many TCP segments.
+        pub(crate) fn trickle(replies: &[u8]) -> Self {
+            MockTransport {
+                max_read: 1,
+                ..MockTransport::new(replies)
+            }
+        }
+    }
+
+    impl Read for MockTransport {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let len = buf.len().min(self.max_read);
+            self.replies.read(&mut buf[..len])
+        }
+    }
+
+    impl Write for MockTransport {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            let len = buf.len().min(self.write_limit - self.written.len());
+            if len == 0 && !buf.is_empty() {
+                return Err(Error::from(ErrorKind::BrokenPipe));
+            }
+            self.written.write(&buf[..len])
+        }
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mock::MockTransport, *};
+
+    #[test]
+    fn reads_one_reply_at_a_time() {
+        let mut conn = MockTransport::new(b"1: stream: OK\x002: stream: Eicar FOUND\0");
+
+        assert_eq!(read_reply(&mut conn).unwrap(), "1: stream: OK\0");
+        assert_eq!(read_reply(&mut conn).unwrap(), "2: stream: Eicar FOUND\0");
+        assert_eq!(read_reply(&mut conn).unwrap(), "");
+    }
+
+    #[test]
+    fn assembles_reply_split_across_reads() {
+        let mut conn = MockTransport::trickle(b"stream: Win.Test.EICAR_HDB-1 FOUND\0stream: OK\0");
+
+        assert_eq!(
+            read_reply(&mut conn).unwrap(),
+            "stream: Win.Test.EICAR_HDB-1 FOUND\0"
+        );
+        assert_eq!(read_reply(&mut conn).unwrap(), "stream: OK\0");
+    }
+
+    #[test]
+    fn buffered_reads_keep_the_next_reply() {
+        let mut conn = MockTransport::new(b"1: stream: OK\x002: stream: Eicar FOUND\0");
+        let mut unread = vec![];
+
+        assert_eq!(
+            read_buffered_reply(&mut conn, &mut unread).unwrap(),
+            "1: stream: OK\0"
+        );
+        // Both replies arrived in a single read.
+        assert_eq!(unread, b"2: stream: Eicar FOUND\0");
+        assert_eq!(
+            read_buffered_reply(&mut conn, &mut unread).unwrap(),
+            "2: stream: Eicar FOUND\0"
+        );
+        assert_eq!(read_buffered_reply(&mut conn, &mut unread).unwrap(), "");
+
+        let mut conn = MockTransport::trickle(b"1: stream: OK\x002: stream: Eicar FOUND\0");
+        let mut unread = vec![];
+        assert_eq!(
+            read_buffered_reply(&mut conn, &mut unread).unwrap(),
+            "1: stream: OK\0"
+        );
+        assert_eq!(
+            read_buffered_reply(&mut conn, &mut unread).unwrap(),
+            "2: stream: Eicar FOUND\0"
+        );
+
+        let mut conn = MockTransport::new(b"stream: OK");
+        assert_eq!(
+            read_buffered_reply(&mut conn, &mut vec![]).unwrap(),
+            "stream: OK"
+        );
+    }
+
+    #[test]
+    fn reads_until_close_in_buffer_sized_reads() {
+        /// Records the size of every read buffer.
+        struct Recording<'a> {
+            data: &'a [u8],
+            sizes: Vec<usize>,
+        }
+        impl Read for Recording<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.sizes.push(buf.len());
+                self.data.read(buf)
+            }
+        }
+
+        let mut conn = Recording {
+            data: b"POOLS: 1\n\nSTATE: VALID PRIMARY\nEND\0",
+            sizes: vec![],
+        };
+        let reply = read_to_close(&mut conn, 16).unwrap();
+        assert_eq!(reply, "POOLS: 1\n\nSTATE: VALID PRIMARY\nEND\0");
+        assert_eq!(conn.sizes, vec![16; 4]);
+
+        let err = read_to_close(&mut &[0xff, 0xfe][..], 16).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn escapes_and_caps_traced_bytes() {
+        assert_eq!(escape_sent(b"zINSTREAM\0"), "zINSTREAM\\x00");
+        assert_eq!(escape_sent(&4u32.to_be_bytes()), "\\x00\\x00\\x00\\x04");
+
+        let long = escape_sent(&[b'a'; MAX_TRACED_BYTES + 1]);
+        assert_eq!(long, format!("{}...", "a".repeat(MAX_TRACED_BYTES)));
+    }
+
+    #[test]
+    fn returns_partial_reply_on_eof() {
+        let mut conn = MockTransport::new(b"stream: OK");
+        assert_eq!(read_reply(&mut conn).unwrap(), "stream: OK");
+    }
+}
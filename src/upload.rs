@@ -0,0 +1,147 @@
+use std::{
+    io::{Read, Write},
+    net::ToSocketAddrs,
+};
+
+use thiserror::Error;
+
+use crate::{
+    connect_tcp_socket,
+    protocol::INSTREAM_HEADER,
+    scan::{exchange, parse_reply},
+    Byte, ClamAVClientError, ScanResult,
+};
+
+/// Why [scan_upload] turned an upload away.
+#[derive(Error, Debug)]
+pub enum UploadRejection {
+    #[error("the upload is larger than the limit")]
+    /// When the upload is larger than the `max_bytes` passed to [scan_upload]. The rest of it
+    /// is not read.
+    TooLarge,
+    #[error("the upload is infected: {}", .0.join(", "))]
+    /// When ClamAV found something in the upload, with the names of the detections.
+    Infected(Vec<String>),
+    #[error("the upload could not be scanned")]
+    /// When the upload could not be scanned, eg. because ClamAV is down. An upload that ClamAV
+    /// itself finds too large is rejected with [ClamAVClientError::SizeLimitExceeded] here.
+    ScanError(#[from] ClamAVClientError),
+}
+
+/// Scans an upload and accepts it only if it is at most `max_bytes` long and clean.
+///
+/// The upload is streamed to ClamAV as it is read, and the scan is aborted as soon as more than
+/// `max_bytes` have been read. Any failure to scan rejects the upload, so nothing gets through
+/// unscanned.
+///
+/// ```rust
+/// use clamav_tcp::{scan_upload, UploadRejection};
+/// let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+/// match scan_upload("localhost:3310", &mut eicar, 1024 * 1024) {
+///     Ok(()) => println!("accepted"),
+///     Err(UploadRejection::TooLarge) => println!("413"),
+///     Err(UploadRejection::Infected(names)) => println!("422: {}", names.join(", ")),
+///     Err(UploadRejection::ScanError(e)) => println!("503: {e}"),
+/// }
+/// ```
+pub fn scan_upload(
+    addr: impl ToSocketAddrs,
+    body: &mut impl Read,
+    max_bytes: u64,
+) -> Result<(), UploadRejection> {
+    scan_upload_on(
+        &mut connect_tcp_socket(addr)?,
+        INSTREAM_HEADER,
+        body,
+        None,
+        max_bytes,
+    )
+}
+
+/// Streams `body` after the INSTREAM command `header` over a new connection, see [scan_upload].
+pub(crate) fn scan_upload_on<S: Read + Write, D: Read>(
+    stream: &mut S,
+    header: &[Byte],
+    body: &mut D,
+    chunk_size: Option<usize>,
+    max_bytes: u64,
+) -> Result<(), UploadRejection> {
+    let mut streamed: u64 = 0;
+    let res = exchange(stream, header, body, chunk_size, |chunk| {
+        streamed += chunk.len() as u64;
+        if streamed > max_bytes {
+            // Only aborts the stream, the rejection is decided below.
+            return Err(ClamAVClientError::SizeLimitExceeded);
+        }
+        Ok(())
+    });
+    let res = res.and_then(|(reply, _)| parse_reply(&reply, false));
+    judge_upload(res, streamed > max_bytes)
+}
+
+/// Decides on an upload from the result of scanning it and whether it turned out `too_large`,
+/// which wins over whatever the scan ended with.
+pub(crate) fn judge_upload(
+    res: Result<ScanResult, ClamAVClientError>,
+    too_large: bool,
+) -> Result<(), UploadRejection> {
+    if too_large {
+        return Err(UploadRejection::TooLarge);
+    }
+    let res = res?;
+    if res.is_infected {
+        return Err(UploadRejection::Infected(res.detected_infections));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{protocol::INSTREAM_FOOTER, transport::mock::MockTransport};
+
+    #[test]
+    fn accepts_clean_uploads_within_the_limit() {
+        let mut conn = MockTransport::new(b"stream: OK\0");
+        scan_upload_on(&mut conn, INSTREAM_HEADER, &mut &b"hello"[..], None, 5).unwrap();
+        assert!(conn.written.ends_with(INSTREAM_FOOTER));
+    }
+
+    #[test]
+    fn rejects_uploads_over_the_limit() {
+        let mut conn = MockTransport::new(b"stream: OK\0");
+        let body = [b'a'; 64];
+        let res = scan_upload_on(&mut conn, INSTREAM_HEADER, &mut &body[..], Some(16), 20);
+        assert!(matches!(res, Err(UploadRejection::TooLarge)));
+        // The stream was cut off after the chunk that crossed the limit.
+        assert!(conn.written.len() < INSTREAM_HEADER.len() + body.len());
+        assert!(!conn.written.ends_with(INSTREAM_FOOTER));
+    }
+
+    #[test]
+    fn rejects_infected_uploads() {
+        let mut conn = MockTransport::new(b"stream: Win.Test.EICAR_HDB-1 FOUND\0");
+        let res = scan_upload_on(&mut conn, INSTREAM_HEADER, &mut &b"eicar"[..], None, 1024);
+        match res {
+            Err(UploadRejection::Infected(names)) => {
+                assert_eq!(names, vec!["Win.Test.EICAR_HDB-1"])
+            }
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn rejects_uploads_that_cannot_be_scanned() {
+        let mut conn = MockTransport::new(b"INSTREAM size limit exceeded. ERROR\0");
+        let res = scan_upload_on(&mut conn, INSTREAM_HEADER, &mut &b"data"[..], None, 1024);
+        assert!(matches!(
+            res,
+            Err(UploadRejection::ScanError(
+                ClamAVClientError::SizeLimitExceeded
+            ))
+        ));
+
+        let res = scan_upload("asd", &mut &b"data"[..], 1024);
+        assert!(matches!(res, Err(UploadRejection::ScanError(_))));
+    }
+}
@@ -1,23 +1,143 @@
 use std::{
     io::{Read, Write},
     net::ToSocketAddrs,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
 };
 
-use crate::{connect_tcp_socket, Byte, ClamAVClientError};
-const VERSION_REQUEST: &[Byte] = b"zVERSION\0";
+use crate::{
+    connect_tcp_socket, connect_tcp_socket_timeout, is_dropped_connection, not_ready, resolve,
+    transport::trace_sent, Byte, ClamAVClientError, CommandSet, VersionInfo,
+};
+pub(crate) const VERSION_REQUEST: &[Byte] = b"zVERSION\0";
+const VERSIONCOMMANDS_REQUEST: &[Byte] = b"zVERSIONCOMMANDS\0";
+const VERSION_PREFIX: &str = "ClamAV ";
 
 /// Checks ClamAV version.
 ///
+/// Fails with [NotReady](ClamAVClientError::NotReady) if ClamAV accepts the connection but doesn't
+/// reply with a version, which happens while it is starting up, and with
+/// [EmptyResponse](ClamAVClientError::EmptyResponse) if the connection is closed without any reply.
+///
 /// ```rust
 /// use clamav_tcp;
 /// let resp = clamav_tcp::version("localhost:3310").unwrap();
 /// println!("{}", resp); // "ClamAV 1.0.0/26734/Mon Nov 28 08:17:05 2022\"
 /// ```
 pub fn version(addr: impl ToSocketAddrs) -> Result<String, ClamAVClientError> {
-    let mut stream = connect_tcp_socket(addr)?;
+    version_on(&mut connect_tcp_socket(addr)?, VERSION_REQUEST)
+}
+
+/// Sends VERSION over a new connection and reads the reply until ClamAV closes the connection.
+pub(crate) fn version_on<S: Read + Write>(
+    stream: &mut S,
+    request: &[Byte],
+) -> Result<String, ClamAVClientError> {
+    trace_sent(request);
+    stream
+        .write_all(request)
+        .map_err(ClamAVClientError::UnableToConnect)?;
+
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp).map_err(|e| {
+        if is_dropped_connection(&e) {
+            not_ready("")
+        } else {
+            ClamAVClientError::InvalidUTf8(e)
+        }
+    })?;
+    if resp.is_empty() {
+        return Err(ClamAVClientError::EmptyResponse);
+    }
+
+    if !resp.starts_with(VERSION_PREFIX) {
+        return Err(not_ready(&resp));
+    }
+    Ok(resp)
+}
+
+/// Checks ClamAV version and parses the reply into a [VersionInfo].
+///
+/// ```rust
+/// use clamav_tcp;
+/// let info = clamav_tcp::version_parsed("localhost:3310").unwrap();
+/// println!("{:?}", info.engine_semver()); // Some((1, 0, 0))
+/// ```
+pub fn version_parsed(addr: impl ToSocketAddrs) -> Result<VersionInfo, ClamAVClientError> {
+    version(addr)?.parse()
+}
+
+/// Waits for ClamAV to finish starting up, eg. in a container that was just started, and returns
+/// its version once it is ready.
+///
+/// Asks for the version every `poll_interval` until ClamAV replies with one. Until then, failed
+/// connections and the replies of a ClamAV that is still loading its signatures, see
+/// [NotReady](ClamAVClientError::NotReady), are retried. Every attempt gives up when `max_wait`
+/// is over, and the wait fails with [ClamAVClientError::Timeout] once no attempt is left before
+/// then. Errors that aren't [retryable](ClamAVClientError::is_retryable), such as an invalid
+/// address, are returned straight away.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use clamav_tcp;
+/// let info = clamav_tcp::wait_until_ready(
+///     "localhost:3310",
+///     Duration::from_secs(10),
+///     Duration::from_millis(500),
+/// )
+/// .unwrap();
+/// println!("{:?}", info.engine_semver()); // Some((1, 0, 0))
+/// ```
+pub fn wait_until_ready(
+    addr: impl ToSocketAddrs,
+    max_wait: Duration,
+    poll_interval: Duration,
+) -> Result<VersionInfo, ClamAVClientError> {
+    let deadline = Instant::now() + max_wait;
+    // Resolved once, so a host name that doesn't resolve fails at once rather than being polled.
+    let addrs = resolve(addr)?;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(ClamAVClientError::Timeout);
+        }
+        let err = match connect_tcp_socket_timeout(&addrs[..], remaining)
+            .and_then(|mut stream| version_on(&mut stream, VERSION_REQUEST))
+        {
+            Ok(resp) => return resp.parse(),
+            Err(e) => e,
+        };
+        if !err.is_retryable() {
+            return Err(err);
+        }
+
+        if poll_interval >= deadline.saturating_duration_since(Instant::now()) {
+            // The next poll would only start after the wait is over.
+            return Err(ClamAVClientError::Timeout);
+        }
+        thread::sleep(poll_interval);
+    }
+}
 
+/// Lists the commands the ClamAV host supports, for checking its capabilities before relying on them.
+///
+/// ```rust
+/// use clamav_tcp;
+/// let commands = clamav_tcp::commands("localhost:3310").unwrap();
+/// assert!(commands.has_instream());
+/// ```
+pub fn commands(addr: impl ToSocketAddrs) -> Result<CommandSet, ClamAVClientError> {
+    commands_on(&mut connect_tcp_socket(addr)?)
+}
+
+/// Sends VERSIONCOMMANDS over a new connection and parses the reply.
+pub(crate) fn commands_on<S: Read + Write>(
+    stream: &mut S,
+) -> Result<CommandSet, ClamAVClientError> {
+    trace_sent(VERSIONCOMMANDS_REQUEST);
     stream
-        .write_all(VERSION_REQUEST)
+        .write_all(VERSIONCOMMANDS_REQUEST)
         .map_err(ClamAVClientError::UnableToConnect)?;
 
     let mut resp = String::new();
@@ -25,16 +145,159 @@ pub fn version(addr: impl ToSocketAddrs) -> Result<String, ClamAVClientError> {
         .read_to_string(&mut resp)
         .map_err(ClamAVClientError::InvalidUTf8)?;
 
-    Ok(resp)
+    resp.parse()
+}
+
+/// A version reply kept for a while, shared by the clones of a client, see
+/// [ClamdClient::version_cached](crate::ClamdClient::version_cached).
+#[derive(Debug)]
+pub(crate) struct VersionCache {
+    ttl: Duration,
+    /// The last reply and when it was fetched.
+    cached: Mutex<Option<(Instant, String)>>,
+}
+
+impl VersionCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        VersionCache {
+            ttl,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Returns the cached reply if it is younger than the TTL, or fetches a new one with `fetch`.
+    ///
+    /// The lock is held while fetching, so concurrent callers wait for one refresh instead of
+    /// all asking ClamAV. Errors are not cached.
+    pub(crate) fn get_or_refresh(
+        &self,
+        fetch: impl FnOnce() -> Result<String, ClamAVClientError>,
+    ) -> Result<String, ClamAVClientError> {
+        // A panic while fetching leaves nothing half-updated, so a poisoned lock is still usable.
+        let mut cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((fetched, version)) = cached.as_ref() {
+            if fetched.elapsed() < self.ttl {
+                return Ok(version.clone());
+            }
+        }
+        let version = fetch()?;
+        *cached = Some((Instant::now(), version.clone()));
+        Ok(version)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::cell::Cell;
+
     use super::*;
+    use crate::{ping::tests::reply_once, transport::mock::MockTransport};
+
+    #[test]
+    fn caches_version_until_it_expires() {
+        let cache = VersionCache::new(Duration::from_millis(50));
+        let fetches = Cell::new(0);
+        let fetch = || {
+            fetches.set(fetches.get() + 1);
+            Ok(format!("ClamAV 1.0.{}", fetches.get()))
+        };
+
+        assert_eq!(cache.get_or_refresh(fetch).unwrap(), "ClamAV 1.0.1");
+        assert_eq!(cache.get_or_refresh(fetch).unwrap(), "ClamAV 1.0.1");
+        assert_eq!(fetches.get(), 1);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(cache.get_or_refresh(fetch).unwrap(), "ClamAV 1.0.2");
+        assert_eq!(fetches.get(), 2);
+    }
+
+    #[test]
+    fn does_not_cache_errors() {
+        let cache = VersionCache::new(Duration::from_secs(60));
+        assert!(cache
+            .get_or_refresh(|| Err(ClamAVClientError::ConnectionClosed))
+            .is_err());
+        assert_eq!(
+            cache
+                .get_or_refresh(|| Ok("ClamAV 1.0.0".to_string()))
+                .unwrap(),
+            "ClamAV 1.0.0"
+        );
+    }
 
     #[test]
     fn can_read_version() {
         let err = version("localhost:3310").is_ok();
         assert!(err);
     }
+
+    #[test]
+    fn version_reports_a_starting_daemon_as_not_ready() {
+        let err = version(reply_once(b"UNKNOWN COMMAND\0")).unwrap_err();
+        assert!(matches!(err, ClamAVClientError::NotReady(_)));
+    }
+
+    #[test]
+    fn version_rejects_an_empty_reply() {
+        let mut conn = MockTransport::new(b"");
+        let err = version_on(&mut conn, VERSION_REQUEST).unwrap_err();
+        assert!(matches!(err, ClamAVClientError::EmptyResponse));
+    }
+
+    #[test]
+    fn waits_until_the_daemon_is_ready() {
+        // Turns the first three polls away like a ClamAV that is still loading its signatures.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            for poll in 0..4 {
+                let (mut conn, _) = listener.accept().unwrap();
+                let _ = conn.read(&mut [0; 16]);
+                let reply: &[u8] = if poll < 3 {
+                    b"UNKNOWN COMMAND\0"
+                } else {
+                    b"ClamAV 1.0.0/26734/Mon Nov 28 08:17:05 2022\0"
+                };
+                conn.write_all(reply).unwrap();
+            }
+        });
+
+        let info =
+            wait_until_ready(addr, Duration::from_secs(10), Duration::from_millis(10)).unwrap();
+        assert_eq!(info.engine_semver(), Some((1, 0, 0)));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn gives_up_waiting_for_the_daemon() {
+        // A port that was free a moment ago refuses every connection.
+        let refused = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let err = wait_until_ready(
+            refused,
+            Duration::from_millis(100),
+            Duration::from_millis(10),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ClamAVClientError::Timeout));
+
+        let err = wait_until_ready("asd", Duration::from_secs(10), Duration::from_millis(10))
+            .unwrap_err();
+        assert!(matches!(err, ClamAVClientError::InvalidSocketAddress(_)));
+    }
+
+    #[test]
+    fn can_list_commands() {
+        let commands = commands("localhost:3310").unwrap();
+        assert!(commands.has_instream());
+        assert!(commands.contains("VERSIONCOMMANDS"));
+    }
+
+    #[test]
+    fn can_parse_version() {
+        let info = version_parsed("localhost:3310").unwrap();
+        assert!(info.engine_semver().is_some());
+    }
 }
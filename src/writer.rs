@@ -0,0 +1,180 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+};
+
+use crate::{
+    connect_tcp_socket, is_dropped_connection,
+    protocol::{chunk_length_prefix, CHUNK_LENGTH_SIZE, INSTREAM_FOOTER, INSTREAM_HEADER},
+    scan::{early_reply, parse_reply, read_scan_reply, DEFAULT_CHUNK_SIZE},
+    transport::trace_sent,
+    ClamAVClientError, ScanResult,
+};
+
+/// A [Write] that streams everything written to it to ClamAV, for scanning the output of code
+/// that writes to a [Write], such as serializers or [io::copy].
+///
+/// Written bytes are buffered and sent to ClamAV a chunk at a time, [flush](Write::flush) sends
+/// whatever is buffered straight away. The scan only completes when [ScanWriter::finish] is
+/// called, which ends the stream and returns the result. A writer that is dropped without being
+/// finished closes the connection and the scan is abandoned.
+///
+/// ```rust
+/// use std::io::Write;
+/// use clamav_tcp::ScanWriter;
+/// let mut writer = ScanWriter::new("localhost:3310", None).unwrap();
+/// let mut eicar = std::fs::File::open("resources/eicar.txt").unwrap();
+/// std::io::copy(&mut eicar, &mut writer).unwrap();
+/// let res = writer.finish().unwrap();
+/// assert!(res.is_infected);
+/// ```
+#[derive(Debug)]
+pub struct ScanWriter<S: Read + Write = TcpStream> {
+    stream: S,
+    /// The chunk being filled, after room for its length prefix.
+    frame: Vec<u8>,
+    chunk_size: usize,
+}
+
+impl ScanWriter {
+    /// Connects to ClamAV and starts an INSTREAM scan. A `chunk_size` of `None` or `Some(0)` uses
+    /// the default of 4096 bytes.
+    pub fn new<A: ToSocketAddrs>(
+        addr: A,
+        chunk_size: Option<usize>,
+    ) -> Result<Self, ClamAVClientError> {
+        ScanWriter::from_stream(connect_tcp_socket(addr)?, chunk_size)
+    }
+}
+
+impl<S: Read + Write> ScanWriter<S> {
+    fn from_stream(mut stream: S, chunk_size: Option<usize>) -> Result<Self, ClamAVClientError> {
+        let chunk_size = chunk_size
+            .filter(|&size| size > 0)
+            .unwrap_or(DEFAULT_CHUNK_SIZE)
+            .min(u32::MAX as usize);
+        trace_sent(INSTREAM_HEADER);
+        stream
+            .write_all(INSTREAM_HEADER)
+            .map_err(ClamAVClientError::UnableToWriteToStream)?;
+
+        let mut frame = Vec::with_capacity(CHUNK_LENGTH_SIZE + chunk_size);
+        frame.resize(CHUNK_LENGTH_SIZE, 0);
+        Ok(ScanWriter {
+            stream,
+            frame,
+            chunk_size,
+        })
+    }
+
+    /// Ends the stream and waits for ClamAV's result for everything written.
+    pub fn finish(mut self) -> Result<ScanResult, ClamAVClientError> {
+        self.end()
+    }
+
+    fn end(&mut self) -> Result<ScanResult, ClamAVClientError> {
+        let sent = self
+            .send_frame()
+            .and_then(|_| {
+                trace_sent(INSTREAM_FOOTER);
+                self.stream.write_all(INSTREAM_FOOTER)
+            })
+            .and_then(|_| self.stream.flush());
+        if let Err(e) = sent {
+            // ClamAV may have replied and closed the connection early, eg. over its size limit.
+            if !is_dropped_connection(&e) {
+                return Err(ClamAVClientError::UnableToWriteToStream(e));
+            }
+            let reply =
+                early_reply(&mut self.stream).ok_or(ClamAVClientError::UnableToWriteToStream(e))?;
+            return parse_reply(&reply, false);
+        }
+
+        let reply = read_scan_reply(&mut self.stream)?;
+        parse_reply(&reply, false)
+    }
+
+    /// Sends the buffered bytes as a chunk, if there are any.
+    fn send_frame(&mut self) -> io::Result<()> {
+        let len = self.frame.len() - CHUNK_LENGTH_SIZE;
+        if len == 0 {
+            return Ok(());
+        }
+        self.frame[..CHUNK_LENGTH_SIZE].copy_from_slice(&chunk_length_prefix(len as u32));
+        trace_sent(&self.frame[..CHUNK_LENGTH_SIZE]);
+        self.stream.write_all(&self.frame)?;
+        self.frame.truncate(CHUNK_LENGTH_SIZE);
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Write for ScanWriter<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let room = self.chunk_size - (self.frame.len() - CHUNK_LENGTH_SIZE);
+        let len = buf.len().min(room);
+        self.frame.extend_from_slice(&buf[..len]);
+        if len == room {
+            self.send_frame()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_frame()?;
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::mock::MockTransport;
+
+    #[test]
+    fn frames_written_bytes_into_chunks() {
+        let stream = MockTransport::new(b"stream: OK\0");
+        let mut writer = ScanWriter::from_stream(stream, Some(4)).unwrap();
+        writer.write_all(b"abcdef").unwrap();
+        assert_eq!(writer.stream.written, b"zINSTREAM\0\0\0\0\x04abcd".to_vec());
+
+        // Flushing sends the partial chunk.
+        writer.flush().unwrap();
+        writer.write_all(b"g").unwrap();
+        assert!(!writer.end().unwrap().is_infected);
+        assert_eq!(
+            writer.stream.written,
+            b"zINSTREAM\0\0\0\0\x04abcd\0\0\0\x02ef\0\0\0\x01g\0\0\0\0".to_vec()
+        );
+    }
+
+    #[test]
+    fn scans_what_is_written() {
+        let mut writer = ScanWriter::new("localhost:3310", Some(16)).unwrap();
+        let words = 3;
+        write!(writer, "This is not a virus, just {words} words.").unwrap();
+        assert!(!writer.finish().unwrap().is_infected);
+
+        let mut writer = ScanWriter::new("localhost:3310", Some(16)).unwrap();
+        io::copy(
+            &mut std::fs::File::open("resources/eicar.txt").unwrap(),
+            &mut writer,
+        )
+        .unwrap();
+        assert_eq!(
+            writer.finish().unwrap().detected_infections,
+            vec!["Win.Test.EICAR_HDB-1"]
+        );
+    }
+
+    #[test]
+    fn early_size_limit_reply_is_returned_from_finish() {
+        let stream = MockTransport::closing_after(b"INSTREAM size limit exceeded. ERROR\0", 12);
+        let mut writer = ScanWriter::from_stream(stream, Some(4)).unwrap();
+        // The chunk is only sent, and refused, once the writer is finished.
+        writer.write_all(b"ab").unwrap();
+        assert!(matches!(
+            writer.finish(),
+            Err(ClamAVClientError::SizeLimitExceeded)
+        ));
+    }
+}